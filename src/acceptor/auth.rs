@@ -0,0 +1,117 @@
+// MIT License
+
+// Copyright (c) 2019 Zhuhao Wang
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use crate::core::Result;
+use async_trait::async_trait;
+use ring::constant_time;
+use std::{collections::HashMap, future::Future};
+
+/// Verifies the credential presented by an inbound client.
+#[async_trait]
+pub trait Authenticator: Send + Sync {
+    async fn authenticate(&self, username: &str, password: &str) -> Result<bool>;
+}
+
+/// Checks credentials against a fixed table of users.
+#[derive(Clone, Debug, Default)]
+pub struct StaticAuthenticator {
+    users: HashMap<String, String>,
+}
+
+impl StaticAuthenticator {
+    pub fn new() -> Self {
+        StaticAuthenticator {
+            users: HashMap::new(),
+        }
+    }
+
+    pub fn insert(&mut self, username: &str, password: &str) {
+        self.users.insert(username.to_owned(), password.to_owned());
+    }
+}
+
+#[async_trait]
+impl Authenticator for StaticAuthenticator {
+    async fn authenticate(&self, username: &str, password: &str) -> Result<bool> {
+        Ok(self
+            .users
+            .get(username)
+            // Don't let the time taken tell how much of the password matched.
+            .map(|p| {
+                constant_time::verify_slices_are_equal(p.as_bytes(), password.as_bytes()).is_ok()
+            })
+            .unwrap_or(false))
+    }
+}
+
+/// Delegates the verification to an async callback.
+pub struct FnAuthenticator<F> {
+    inner: F,
+}
+
+impl<F, Fut> FnAuthenticator<F>
+where
+    F: Fn(String, String) -> Fut + Send + Sync,
+    Fut: Future<Output = Result<bool>> + Send,
+{
+    pub fn new(f: F) -> Self {
+        FnAuthenticator { inner: f }
+    }
+}
+
+#[async_trait]
+impl<F, Fut> Authenticator for FnAuthenticator<F>
+where
+    F: Fn(String, String) -> Fut + Send + Sync,
+    Fut: Future<Output = Result<bool>> + Send,
+{
+    async fn authenticate(&self, username: &str, password: &str) -> Result<bool> {
+        (self.inner)(username.to_owned(), password.to_owned()).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::executor::block_on;
+
+    #[test]
+    fn static_authenticator_checks_password() {
+        let mut authenticator = StaticAuthenticator::new();
+        authenticator.insert("user", "pass");
+
+        assert!(block_on(authenticator.authenticate("user", "pass")).unwrap());
+        assert!(!block_on(authenticator.authenticate("user", "wrong")).unwrap());
+        assert!(!block_on(authenticator.authenticate("user", "pas")).unwrap());
+        assert!(!block_on(authenticator.authenticate("user", "passs")).unwrap());
+        assert!(!block_on(authenticator.authenticate("other", "pass")).unwrap());
+    }
+
+    #[test]
+    fn fn_authenticator_calls_callback() {
+        let authenticator =
+            FnAuthenticator::new(|username: String, _| async move { Ok(username == "user") });
+
+        assert!(block_on(authenticator.authenticate("user", "")).unwrap());
+        assert!(!block_on(authenticator.authenticate("other", "")).unwrap());
+    }
+}
@@ -21,7 +21,7 @@
 // SOFTWARE.
 
 use crate::{
-    acceptor::{http::HttpError, Acceptor},
+    acceptor::{http::HttpError, Acceptor, Authenticator, ClientMetadata, MidHandshake},
     core::{Endpoint, Error, Result},
 };
 use async_trait::async_trait;
@@ -32,14 +32,37 @@ use futures::{
 };
 use futures_tokio_compat::Compat;
 use hyper::{
-    http::{method::Method, Request, Response},
+    http::{header, method::Method, Request, Response, StatusCode},
     server::conn::Http,
     service, Body,
 };
-use std::sync::{Arc, Mutex};
+use std::{
+    future::Future,
+    io,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+};
 
 pub struct HttpConnectAcceptor<T: AsyncRead + AsyncWrite + Send + Unpin + 'static> {
     io: T,
+    authenticator: Option<Arc<dyn Authenticator>>,
+}
+
+impl<T: AsyncRead + AsyncWrite + Send + Unpin + 'static> HttpConnectAcceptor<T> {
+    pub fn new(io: T) -> Self {
+        HttpConnectAcceptor {
+            io,
+            authenticator: None,
+        }
+    }
+
+    /// Requires the client to authenticate with `Proxy-Authorization: Basic`.
+    pub fn with_authenticator(io: T, authenticator: Arc<dyn Authenticator>) -> Self {
+        HttpConnectAcceptor {
+            io,
+            authenticator: Some(authenticator),
+        }
+    }
 }
 
 #[async_trait]
@@ -47,61 +70,166 @@ impl<T: AsyncRead + AsyncWrite + Send + Unpin + 'static>
     Acceptor<HttpConnectMidHandshake<Compat<Compat<T>>>> for HttpConnectAcceptor<T>
 {
     async fn handshake(self) -> Result<HttpConnectMidHandshake<Compat<Compat<T>>>> {
-        let (sender, receiver) = oneshot::channel();
-        let sender = Arc::new(Mutex::new(Some(sender)));
-        let connection = Http::new().serve_connection(
-            Compat::new(self.io),
-            service::service_fn(move |req: Request<Body>| {
-                let sender = sender.clone();
-                async move {
-                    match *req.method() {
-                        Method::CONNECT => {
-                            if let (Some(host), Some(port)) = (
-                                req.uri().host().map(|h| h.to_string()),
-                                req.uri().port_part().map(|p| p.as_u16()),
-                            ) {
-                                sender
-                                    .lock()
-                                    .unwrap()
-                                    .take()
-                                    .unwrap()
-                                    .send(Endpoint::HostName(host, port))
-                                    .unwrap();
-                                Ok::<Response<Body>, _>(future::pending().await)
-                            } else {
-                                Err(HttpError::InvalidConnectUrl(req.uri().to_string()))
-                            }
+        let mid = serve(self.io, self.authenticator, |req: Request<Body>| {
+            future::err(HttpError::InvalidCommand(req.method().to_string()).into())
+        })
+        .await?;
+        mid.ok_or_else(|| HttpError::ClosedWithoutRequest.into())
+    }
+}
+
+const REALM: &str = "freighter";
+
+type Sender = Arc<Mutex<Option<oneshot::Sender<(Endpoint, Option<String>)>>>>;
+
+/// Returns the user if the request carries valid Basic credentials.
+async fn authenticate(
+    req: &Request<Body>,
+    authenticator: &dyn Authenticator,
+) -> Result<Option<String>> {
+    let credential = req
+        .headers()
+        .get(header::PROXY_AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .filter(|value| value.len() > 6 && value[..6].eq_ignore_ascii_case("basic "))
+        .and_then(|value| base64::decode(value[6..].trim()).ok())
+        .and_then(|credential| String::from_utf8(credential).ok());
+
+    let credential = match credential {
+        Some(credential) => credential,
+        None => return Ok(None),
+    };
+
+    let mut parts = credential.splitn(2, ':');
+    match (parts.next(), parts.next()) {
+        (Some(username), Some(password))
+            if authenticator.authenticate(username, password).await? =>
+        {
+            Ok(Some(username.to_owned()))
+        }
+        _ => Ok(None),
+    }
+}
+
+fn proxy_authentication_required() -> Response<Body> {
+    let mut response = Response::new(Body::empty());
+    *response.status_mut() = StatusCode::PROXY_AUTHENTICATION_REQUIRED;
+    response.headers_mut().insert(
+        header::PROXY_AUTHENTICATE,
+        header::HeaderValue::from_str(&format!("Basic realm=\"{}\"", REALM)).unwrap(),
+    );
+    response
+}
+
+async fn connect(
+    req: Request<Body>,
+    sender: Sender,
+    user: Option<String>,
+) -> Result<Response<Body>> {
+    if let (Some(host), Some(port)) = (
+        req.uri().host().map(|h| h.to_string()),
+        req.uri().port_part().map(|p| p.as_u16()),
+    ) {
+        sender
+            .lock()
+            .unwrap()
+            .take()
+            .unwrap()
+            .send((Endpoint::HostName(host, port), user))
+            .unwrap();
+        Ok::<Response<Body>, _>(future::pending().await)
+    } else {
+        Err(HttpError::InvalidConnectUrl(req.uri().to_string()).into())
+    }
+}
+
+/// Serves requests on `io` until a CONNECT request arrives, handing every
+/// other request to `forward`. Returns `None` if the client closes the
+/// connection without sending CONNECT.
+///
+/// With an `authenticator`, requests without valid credentials are answered
+/// with `407 Proxy Authentication Required`.
+pub(super) async fn serve<T, F, Fut>(
+    io: T,
+    authenticator: Option<Arc<dyn Authenticator>>,
+    forward: F,
+) -> Result<Option<HttpConnectMidHandshake<Compat<Compat<T>>>>>
+where
+    T: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+    F: Fn(Request<Body>) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Result<Response<Body>>> + Send + 'static,
+{
+    let (sender, receiver) = oneshot::channel();
+    let sender = Arc::new(Mutex::new(Some(sender)));
+    let forward = Arc::new(forward);
+    let connection = Http::new().serve_connection(
+        Compat::new(io),
+        service::service_fn(move |req: Request<Body>| {
+            let sender = sender.clone();
+            let forward = forward.clone();
+            let authenticator = authenticator.clone();
+            async move {
+                let user = match authenticator {
+                    Some(ref authenticator) => {
+                        match authenticate(&req, authenticator.as_ref()).await? {
+                            Some(user) => Some(user),
+                            None => return Ok(proxy_authentication_required()),
                         }
-                        _ => Err(HttpError::InvalidCommand(req.method().to_string())),
                     }
+                    None => None,
+                };
+
+                match *req.method() {
+                    Method::CONNECT => connect(req, sender, user).await,
+                    _ => forward(req).await,
                 }
-            }),
-        );
+            }
+        }),
+    );
 
-        let res = future::try_select(receiver, connection).await;
-        match res {
-            Ok(Either::Left((endpoint, connection))) => Ok(HttpConnectMidHandshake {
-                io: Compat::new(connection.into_parts().io),
-                endpoint,
-            }),
-            Ok(Either::Right((_, _))) => Err(HttpError::ClosedWithoutRequest.into()),
-            Err(Either::Right((err, _))) => Err(err.into()),
-            _ => unreachable!(),
-        }
+    let res = future::try_select(receiver, connection).await;
+    match res {
+        Ok(Either::Left(((endpoint, user), connection))) => Ok(Some(HttpConnectMidHandshake {
+            io: Compat::new(connection.into_parts().io),
+            endpoint,
+            metadata: ClientMetadata {
+                user,
+                ..Default::default()
+            },
+        })),
+        Ok(Either::Right((_, _))) => Ok(None),
+        Err(Either::Right((err, _))) => Err(err.into()),
+        _ => unreachable!(),
     }
 }
 
 pub struct HttpConnectMidHandshake<T: AsyncRead + AsyncWrite + Send + Unpin + 'static> {
     io: T,
     endpoint: Endpoint,
+    metadata: ClientMetadata,
+}
+
+pub(crate) fn status_for_error(err: &Error) -> StatusCode {
+    match err.downcast_ref::<io::Error>().map(io::Error::kind) {
+        Some(io::ErrorKind::TimedOut) => StatusCode::GATEWAY_TIMEOUT,
+        Some(io::ErrorKind::PermissionDenied) => StatusCode::FORBIDDEN,
+        _ => StatusCode::BAD_GATEWAY,
+    }
 }
 
-impl<T: AsyncRead + AsyncWrite + Send + Unpin + 'static> HttpConnectMidHandshake<T> {
-    pub fn target_endpoint(&self) -> &Endpoint {
+#[async_trait]
+impl<T: AsyncRead + AsyncWrite + Send + Unpin + 'static> MidHandshake<T>
+    for HttpConnectMidHandshake<T>
+{
+    fn target_endpoint(&self) -> &Endpoint {
         &self.endpoint
     }
 
-    pub async fn finalize(mut self) -> Result<T> {
+    fn metadata(&self) -> &ClientMetadata {
+        &self.metadata
+    }
+
+    async fn finalize(mut self, _bound_addr: Option<SocketAddr>) -> Result<T> {
         let response = "HTTP/1.1 200 Connection Established\r\n\r\n";
         self.io
             .write_all(response.as_bytes())
@@ -109,4 +237,87 @@ impl<T: AsyncRead + AsyncWrite + Send + Unpin + 'static> HttpConnectMidHandshake
             .await?;
         Ok(self.io)
     }
+
+    async fn fail(mut self, err: &Error) -> Result<()> {
+        let status = status_for_error(err);
+        let response = format!(
+            "HTTP/1.1 {} {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+            status.as_str(),
+            status.canonical_reason().unwrap_or("")
+        );
+        self.io
+            .write_all(response.as_bytes())
+            .err_into::<Error>()
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::acceptor::StaticAuthenticator;
+    use futures::executor::block_on;
+
+    fn authenticate_with(authorization: Option<&str>) -> Option<String> {
+        let mut authenticator = StaticAuthenticator::new();
+        authenticator.insert("user", "pa:ss");
+
+        let mut req = Request::connect("example.com:443");
+        if let Some(authorization) = authorization {
+            req.header(header::PROXY_AUTHORIZATION, authorization);
+        }
+        block_on(authenticate(
+            &req.body(Body::empty()).unwrap(),
+            &authenticator,
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn accepts_valid_credential() {
+        let authorization = format!("Basic {}", base64::encode("user:pa:ss"));
+        assert_eq!(
+            authenticate_with(Some(&authorization)),
+            Some("user".to_owned())
+        );
+
+        let authorization = format!("basic  {}", base64::encode("user:pa:ss"));
+        assert_eq!(
+            authenticate_with(Some(&authorization)),
+            Some("user".to_owned())
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_credential() {
+        assert_eq!(authenticate_with(None), None);
+        assert_eq!(
+            authenticate_with(Some(&format!("Basic {}", base64::encode("user:wrong")))),
+            None
+        );
+        assert_eq!(
+            authenticate_with(Some(&format!("Bearer {}", base64::encode("user:pa:ss")))),
+            None
+        );
+        assert_eq!(authenticate_with(Some("Basic !!!")), None);
+        assert_eq!(
+            authenticate_with(Some(&format!("Basic {}", base64::encode("user")))),
+            None
+        );
+    }
+
+    #[test]
+    fn maps_errors_to_status() {
+        let status = |kind: io::ErrorKind| status_for_error(&io::Error::from(kind).into());
+
+        assert_eq!(status(io::ErrorKind::TimedOut), StatusCode::GATEWAY_TIMEOUT);
+        assert_eq!(
+            status(io::ErrorKind::PermissionDenied),
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(
+            status(io::ErrorKind::ConnectionRefused),
+            StatusCode::BAD_GATEWAY
+        );
+    }
 }
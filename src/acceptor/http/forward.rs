@@ -0,0 +1,273 @@
+// MIT License
+
+// Copyright (c) 2019 Zhuhao Wang
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use crate::{
+    acceptor::http::HttpError,
+    connector::Connector,
+    core::{Endpoint, Result},
+};
+use futures::{
+    future::{self, FutureExt},
+    io::{AsyncRead, AsyncWrite},
+};
+use futures_tokio_compat::Compat;
+use hyper::{
+    client::conn::{self, SendRequest},
+    http::{
+        header::{self, HeaderMap, HeaderName, HeaderValue},
+        Request, Response, StatusCode, Uri,
+    },
+    Body,
+};
+use std::net::{IpAddr, SocketAddr};
+use std::{collections::HashMap, marker::PhantomData, sync::Mutex};
+
+/// Removes the headers that only apply to a single hop, including the ones
+/// listed in `Connection` and every `Proxy-*` header.
+fn strip_hop_by_hop_headers(headers: &mut HeaderMap) {
+    let listed: Vec<String> = headers
+        .get_all(header::CONNECTION)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|name| name.trim().to_ascii_lowercase())
+        .collect();
+    for name in listed {
+        headers.remove(name.as_str());
+    }
+
+    for name in &[
+        header::CONNECTION,
+        header::PROXY_AUTHENTICATE,
+        header::PROXY_AUTHORIZATION,
+        header::TE,
+        header::TRAILER,
+        header::TRANSFER_ENCODING,
+        header::UPGRADE,
+    ] {
+        headers.remove(name);
+    }
+    headers.remove("keep-alive");
+
+    let proxy_headers: Vec<HeaderName> = headers
+        .keys()
+        .filter(|name| name.as_str().starts_with("proxy-"))
+        .cloned()
+        .collect();
+    for name in proxy_headers {
+        headers.remove(name);
+    }
+}
+
+fn target_endpoint(uri: &Uri) -> Result<Endpoint> {
+    let host = match (uri.scheme_str(), uri.host()) {
+        (Some("http"), Some(host)) => host,
+        _ => return Err(HttpError::InvalidUrl(uri.to_string()).into()),
+    };
+    let port = uri.port_part().map(|p| p.as_u16()).unwrap_or(80);
+
+    // IPv6 literals keep their brackets in the URI.
+    let literal = host.trim_start_matches('[').trim_end_matches(']');
+    Ok(match literal.parse::<IpAddr>() {
+        Ok(ip) => Endpoint::new_from_addr(SocketAddr::new(ip, port)),
+        Err(_) => Endpoint::new_from_hostname(host, port),
+    })
+}
+
+/// Rewrites an absolute-form proxy request to the origin-form expected by
+/// the origin server.
+fn into_origin_request(mut req: Request<Body>) -> Result<Request<Body>> {
+    if !req.headers().contains_key(header::HOST) {
+        if let Some(authority) = req.uri().authority_part() {
+            let host = HeaderValue::from_str(authority.as_str())?;
+            req.headers_mut().insert(header::HOST, host);
+        }
+    }
+
+    let path = req
+        .uri()
+        .path_and_query()
+        .map(|p| p.as_str())
+        .unwrap_or("/")
+        .parse::<Uri>()?;
+    *req.uri_mut() = path;
+
+    strip_hop_by_hop_headers(req.headers_mut());
+    Ok(req)
+}
+
+/// Returns the origin server to send `req` to, along with the request
+/// rewritten for it.
+fn parse_request(req: Request<Body>) -> Result<(Endpoint, Request<Body>)> {
+    let endpoint = target_endpoint(req.uri())?;
+    Ok((endpoint, into_origin_request(req)?))
+}
+
+fn error_response(status: StatusCode) -> Response<Body> {
+    let mut response = Response::new(Body::empty());
+    *response.status_mut() = status;
+    response
+}
+
+/// Forwards plain HTTP requests from a single client connection, keeping one
+/// upstream connection alive for each host the client talks to.
+pub(super) struct Forwarder<C, S> {
+    connector: C,
+    connections: Mutex<HashMap<String, SendRequest<Body>>>,
+    last_endpoint: Mutex<Option<Endpoint>>,
+    _marker: PhantomData<fn() -> S>,
+}
+
+impl<C, S> Forwarder<C, S>
+where
+    C: Connector<S> + Clone + Send + Sync + 'static,
+    S: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+{
+    pub(super) fn new(connector: C) -> Self {
+        Forwarder {
+            connector,
+            connections: Mutex::new(HashMap::new()),
+            last_endpoint: Mutex::new(None),
+            _marker: PhantomData,
+        }
+    }
+
+    /// The target of the last request that could be parsed, if any.
+    pub(super) fn last_endpoint(&self) -> Option<Endpoint> {
+        self.last_endpoint.lock().unwrap().clone()
+    }
+
+    async fn sender(&self, endpoint: &Endpoint, key: &str) -> Result<SendRequest<Body>> {
+        let cached = self.connections.lock().unwrap().remove(key);
+        if let Some(mut sender) = cached {
+            if future::poll_fn(|cx| sender.poll_ready(cx)).await.is_ok() {
+                return Ok(sender);
+            }
+        }
+
+        let io = self.connector.clone().connect(endpoint).await?;
+        let (sender, connection) = conn::handshake(Compat::new(io)).await?;
+        tokio::spawn(connection.map(|_| ()));
+        Ok(sender)
+    }
+
+    async fn try_forward(&self, endpoint: Endpoint, req: Request<Body>) -> Result<Response<Body>> {
+        let key = endpoint.to_string();
+
+        let mut sender = self.sender(&endpoint, &key).await?;
+        let mut response = sender.send_request(req).await?;
+        self.connections.lock().unwrap().insert(key, sender);
+
+        strip_hop_by_hop_headers(response.headers_mut());
+        Ok(response)
+    }
+
+    /// Answers requests that can't be turned into an origin request with
+    /// `400 Bad Request` and failures to reach the origin with
+    /// `502 Bad Gateway`.
+    pub(super) async fn forward(&self, req: Request<Body>) -> Result<Response<Body>> {
+        let (endpoint, req) = match parse_request(req) {
+            Ok(parsed) => parsed,
+            Err(_) => return Ok(error_response(StatusCode::BAD_REQUEST)),
+        };
+        *self.last_endpoint.lock().unwrap() = Some(endpoint.clone());
+
+        match self.try_forward(endpoint, req).await {
+            Ok(response) => Ok(response),
+            Err(_) => Ok(error_response(StatusCode::BAD_GATEWAY)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(uri: &str) -> Request<Body> {
+        Request::get(uri).body(Body::empty()).unwrap()
+    }
+
+    #[test]
+    fn strips_hop_by_hop_headers() {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::CONNECTION, HeaderValue::from_static("close, x-hop"));
+        headers.insert("x-hop", HeaderValue::from_static("1"));
+        headers.insert("keep-alive", HeaderValue::from_static("timeout=5"));
+        headers.insert(
+            header::PROXY_AUTHORIZATION,
+            HeaderValue::from_static("Basic Zm9v"),
+        );
+        headers.insert("proxy-connection", HeaderValue::from_static("keep-alive"));
+        headers.insert(header::ACCEPT, HeaderValue::from_static("*/*"));
+
+        strip_hop_by_hop_headers(&mut headers);
+        assert_eq!(headers.len(), 1);
+        assert!(headers.contains_key(header::ACCEPT));
+    }
+
+    #[test]
+    fn rewrites_to_origin_form() {
+        let (endpoint, req) = parse_request(request("http://example.com:8080/a?b=c")).unwrap();
+        assert_eq!(endpoint.to_string(), "example.com:8080");
+        assert_eq!(req.uri(), "/a?b=c");
+        assert_eq!(req.headers()[header::HOST], "example.com:8080");
+    }
+
+    #[test]
+    fn parses_ip_literals() {
+        let (endpoint, req) = parse_request(request("http://[::1]:8080/")).unwrap();
+        match endpoint {
+            Endpoint::Ip(addr) => assert_eq!(addr, "[::1]:8080".parse().unwrap()),
+            _ => panic!("unexpected endpoint {}", endpoint),
+        }
+        assert_eq!(req.headers()[header::HOST], "[::1]:8080");
+
+        let (endpoint, _) = parse_request(request("http://127.0.0.1/")).unwrap();
+        match endpoint {
+            Endpoint::Ip(addr) => assert_eq!(addr, "127.0.0.1:80".parse().unwrap()),
+            _ => panic!("unexpected endpoint {}", endpoint),
+        }
+    }
+
+    #[test]
+    fn defaults_to_port_80() {
+        let (endpoint, req) = parse_request(request("http://example.com")).unwrap();
+        assert_eq!(endpoint.to_string(), "example.com:80");
+        assert_eq!(req.uri(), "/");
+    }
+
+    #[test]
+    fn keeps_existing_host_header() {
+        let mut req = request("http://example.com/");
+        req.headers_mut()
+            .insert(header::HOST, HeaderValue::from_static("other.example"));
+
+        let (_, req) = parse_request(req).unwrap();
+        assert_eq!(req.headers()[header::HOST], "other.example");
+    }
+
+    #[test]
+    fn rejects_non_http_urls() {
+        assert!(parse_request(request("https://example.com/")).is_err());
+        assert!(parse_request(request("/relative")).is_err());
+    }
+}
@@ -21,7 +21,11 @@
 // SOFTWARE.
 
 mod connect_acceptor;
-pub use connect_acceptor::HttpConnectAcceptor;
+mod forward;
+mod proxy_acceptor;
+pub(crate) use connect_acceptor::status_for_error;
+pub use connect_acceptor::{HttpConnectAcceptor, HttpConnectMidHandshake};
+pub use proxy_acceptor::{HttpProxyAcceptor, HttpProxyMidHandshake};
 
 #[derive(Debug)]
 pub enum HttpError {
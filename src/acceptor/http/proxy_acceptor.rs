@@ -0,0 +1,210 @@
+// MIT License
+
+// Copyright (c) 2019 Zhuhao Wang
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use crate::{
+    acceptor::{
+        http::{connect_acceptor::serve, forward::Forwarder, HttpConnectMidHandshake, HttpError},
+        Acceptor, Authenticator, ClientMetadata, MidHandshake,
+    },
+    connector::Connector,
+    core::{Endpoint, Error, Result},
+};
+use async_trait::async_trait;
+use futures::io::{AsyncRead, AsyncWrite};
+use futures_tokio_compat::Compat;
+use hyper::{http::Request, Body};
+use std::{marker::PhantomData, net::SocketAddr, sync::Arc};
+
+/// An HTTP proxy that forwards plain requests through `connector` and hands
+/// the tunnel back once the client sends a CONNECT request.
+///
+/// Requests on one client connection may target different hosts. If the
+/// client closes the connection without ever sending CONNECT, the handshake
+/// yields `HttpProxyMidHandshake::Forwarded`, which needs no tunnel.
+pub struct HttpProxyAcceptor<T, C, S>
+where
+    T: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+    C: Connector<S> + Clone + Send + Sync + 'static,
+    S: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+{
+    io: T,
+    connector: C,
+    authenticator: Option<Arc<dyn Authenticator>>,
+    _marker: PhantomData<fn() -> S>,
+}
+
+impl<T, C, S> HttpProxyAcceptor<T, C, S>
+where
+    T: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+    C: Connector<S> + Clone + Send + Sync + 'static,
+    S: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+{
+    pub fn new(io: T, connector: C) -> Self {
+        HttpProxyAcceptor {
+            io,
+            connector,
+            authenticator: None,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Requires the client to authenticate with `Proxy-Authorization: Basic`,
+    /// both for CONNECT and forwarded requests.
+    pub fn with_authenticator(io: T, connector: C, authenticator: Arc<dyn Authenticator>) -> Self {
+        HttpProxyAcceptor {
+            io,
+            connector,
+            authenticator: Some(authenticator),
+            _marker: PhantomData,
+        }
+    }
+}
+
+#[async_trait]
+impl<T, C, S> Acceptor<HttpProxyMidHandshake<Compat<Compat<T>>>> for HttpProxyAcceptor<T, C, S>
+where
+    T: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+    C: Connector<S> + Clone + Send + Sync + 'static,
+    S: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+{
+    async fn handshake(self) -> Result<HttpProxyMidHandshake<Compat<Compat<T>>>> {
+        let forwarder = Arc::new(Forwarder::new(self.connector));
+        let session = forwarder.clone();
+        let mid = serve(self.io, self.authenticator, move |req: Request<Body>| {
+            let forwarder = forwarder.clone();
+            async move { forwarder.forward(req).await }
+        })
+        .await?;
+
+        match (mid, session.last_endpoint()) {
+            (Some(mid), _) => Ok(HttpProxyMidHandshake::Connect(mid)),
+            (None, Some(endpoint)) => Ok(HttpProxyMidHandshake::Forwarded {
+                endpoint,
+                metadata: ClientMetadata::default(),
+            }),
+            (None, None) => Err(HttpError::ClosedWithoutRequest.into()),
+        }
+    }
+}
+
+/// The result of an `HttpProxyAcceptor` handshake.
+pub enum HttpProxyMidHandshake<T: AsyncRead + AsyncWrite + Send + Unpin + 'static> {
+    /// The client sent CONNECT and waits for the tunnel.
+    Connect(HttpConnectMidHandshake<T>),
+    /// The client closed the connection after a session of forwarded
+    /// requests, the last one to `endpoint`.
+    Forwarded {
+        endpoint: Endpoint,
+        metadata: ClientMetadata,
+    },
+}
+
+#[async_trait]
+impl<T: AsyncRead + AsyncWrite + Send + Unpin + 'static> MidHandshake<T>
+    for HttpProxyMidHandshake<T>
+{
+    fn target_endpoint(&self) -> &Endpoint {
+        match self {
+            HttpProxyMidHandshake::Connect(mid) => mid.target_endpoint(),
+            HttpProxyMidHandshake::Forwarded { endpoint, .. } => endpoint,
+        }
+    }
+
+    fn metadata(&self) -> &ClientMetadata {
+        match self {
+            HttpProxyMidHandshake::Connect(mid) => mid.metadata(),
+            HttpProxyMidHandshake::Forwarded { metadata, .. } => metadata,
+        }
+    }
+
+    fn needs_tunnel(&self) -> bool {
+        match self {
+            HttpProxyMidHandshake::Connect(_) => true,
+            HttpProxyMidHandshake::Forwarded { .. } => false,
+        }
+    }
+
+    async fn finalize(self, bound_addr: Option<SocketAddr>) -> Result<T> {
+        match self {
+            HttpProxyMidHandshake::Connect(mid) => mid.finalize(bound_addr).await,
+            HttpProxyMidHandshake::Forwarded { .. } => Err(HttpError::ClosedWithoutRequest.into()),
+        }
+    }
+
+    async fn fail(self, err: &Error) -> Result<()> {
+        match self {
+            HttpProxyMidHandshake::Connect(mid) => mid.fail(err).await,
+            HttpProxyMidHandshake::Forwarded { .. } => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        acceptor::relay_mid_handshake, connector::mock::MockConnector, io::mock::MockStream,
+    };
+    use tokio::runtime::Runtime;
+
+    fn handshake(input: &[u8]) -> Result<HttpProxyMidHandshake<Compat<Compat<MockStream>>>> {
+        let connector = MockConnector::new(MockStream::new(b""));
+        let acceptor =
+            HttpProxyAcceptor::<_, _, MockStream>::new(MockStream::new(input), connector);
+        Runtime::new().unwrap().block_on(acceptor.handshake())
+    }
+
+    #[test]
+    fn yields_connect_tunnel() {
+        let mid = handshake(b"CONNECT example.com:443 HTTP/1.1\r\nHost: example.com:443\r\n\r\n")
+            .unwrap();
+        assert!(mid.needs_tunnel());
+        assert_eq!(mid.target_endpoint().to_string(), "example.com:443");
+    }
+
+    #[test]
+    fn fails_without_request() {
+        let err = handshake(b"").err().unwrap();
+        match err.downcast_ref::<HttpError>() {
+            Some(HttpError::ClosedWithoutRequest) => {}
+            _ => panic!("unexpected error: {}", err),
+        }
+    }
+
+    #[test]
+    fn relays_nothing_after_forwarding() {
+        let mid: HttpProxyMidHandshake<MockStream> = HttpProxyMidHandshake::Forwarded {
+            endpoint: Endpoint::new_from_hostname("example.com", 80),
+            metadata: ClientMetadata::default(),
+        };
+        assert!(!mid.needs_tunnel());
+
+        let stream = MockStream::new(b"");
+        let output = stream.output();
+        let connector = MockConnector::new(stream);
+        Runtime::new()
+            .unwrap()
+            .block_on(relay_mid_handshake(mid, connector))
+            .unwrap();
+        assert!(output.lock().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,231 @@
+// MIT License
+
+// Copyright (c) 2019 Zhuhao Wang
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use crate::{
+    acceptor::{http::status_for_error, Acceptor, ClientMetadata, MidHandshake},
+    core::{Endpoint, Error, Result},
+    io::H2Stream,
+};
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures::io::{AsyncRead, AsyncWrite};
+use futures_tokio_compat::Compat;
+use h2::server::{self, Connection, SendResponse};
+use http::{Method, Request, Response, StatusCode};
+use std::net::SocketAddr;
+
+/// Accepts an HTTP/2 connection whose streams are CONNECT requests.
+///
+/// Only the plain `CONNECT` of RFC 7540 is understood. The extended `CONNECT`
+/// of RFC 8441 would need the `:protocol` pseudo-header and
+/// `SETTINGS_ENABLE_CONNECT_PROTOCOL`, which the h2 version in use lacks.
+pub struct H2ConnectAcceptor<T: AsyncRead + AsyncWrite + Send + Unpin + 'static> {
+    io: T,
+}
+
+impl<T: AsyncRead + AsyncWrite + Send + Unpin + 'static> H2ConnectAcceptor<T> {
+    pub fn new(io: T) -> Self {
+        H2ConnectAcceptor { io }
+    }
+}
+
+#[async_trait]
+impl<T: AsyncRead + AsyncWrite + Send + Unpin + 'static> Acceptor<H2Incoming<T>>
+    for H2ConnectAcceptor<T>
+{
+    async fn handshake(self) -> Result<H2Incoming<T>> {
+        let connection = server::handshake(Compat::new(self.io)).await?;
+        Ok(H2Incoming { connection })
+    }
+}
+
+/// The CONNECT requests of an HTTP/2 connection.
+///
+/// The connection only makes progress, including for the streams already
+/// returned, while `next` is being awaited, so it should be called in a
+/// loop that spawns the handling of each stream.
+pub struct H2Incoming<T: AsyncRead + AsyncWrite + Send + Unpin + 'static> {
+    connection: Connection<Compat<T>, Bytes>,
+}
+
+fn empty_response(status: StatusCode) -> Response<()> {
+    let mut response = Response::new(());
+    *response.status_mut() = status;
+    response
+}
+
+fn connect_endpoint(req: &Request<h2::RecvStream>) -> Option<Endpoint> {
+    if req.method() != Method::CONNECT {
+        return None;
+    }
+
+    let authority = req.uri().authority_part()?;
+    let port = authority.port_part()?.as_u16();
+    Some(Endpoint::new_from_hostname(authority.host(), port))
+}
+
+impl<T: AsyncRead + AsyncWrite + Send + Unpin + 'static> H2Incoming<T> {
+    /// Returns the next CONNECT request, or `None` once the client closes
+    /// the connection. Other requests are answered with `400 Bad Request`.
+    pub async fn next(&mut self) -> Option<Result<H2ConnectMidHandshake>> {
+        loop {
+            let (request, mut respond) = match self.connection.accept().await? {
+                Ok(stream) => stream,
+                Err(err) => return Some(Err(err.into())),
+            };
+
+            let endpoint = match connect_endpoint(&request) {
+                Some(endpoint) => endpoint,
+                None => {
+                    let _ = respond.send_response(empty_response(StatusCode::BAD_REQUEST), true);
+                    continue;
+                }
+            };
+
+            return Some(Ok(H2ConnectMidHandshake {
+                recv: request.into_body(),
+                respond,
+                endpoint,
+                metadata: ClientMetadata::default(),
+            }));
+        }
+    }
+}
+
+pub struct H2ConnectMidHandshake {
+    recv: h2::RecvStream,
+    respond: SendResponse<Bytes>,
+    endpoint: Endpoint,
+    metadata: ClientMetadata,
+}
+
+#[async_trait]
+impl MidHandshake<H2Stream> for H2ConnectMidHandshake {
+    fn target_endpoint(&self) -> &Endpoint {
+        &self.endpoint
+    }
+
+    fn metadata(&self) -> &ClientMetadata {
+        &self.metadata
+    }
+
+    async fn finalize(mut self, _bound_addr: Option<SocketAddr>) -> Result<H2Stream> {
+        let send = self
+            .respond
+            .send_response(empty_response(StatusCode::OK), false)?;
+        Ok(H2Stream::new(self.recv, send))
+    }
+
+    async fn fail(mut self, err: &Error) -> Result<()> {
+        self.respond
+            .send_response(empty_response(status_for_error(err)), true)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        connector::{
+            mock::DuplexConnector, Connector, H2ConnectConnector, H2ConnectConnectorError,
+        },
+        io::mock::{duplex, DuplexStream},
+    };
+    use futures::{channel::mpsc, stream::StreamExt};
+    use h2::client;
+    use std::io;
+    use tokio::runtime::Runtime;
+
+    fn connect_request(authority: &str) -> Request<()> {
+        Request::builder()
+            .method(Method::CONNECT)
+            .uri(authority)
+            .body(())
+            .unwrap()
+    }
+
+    #[test]
+    fn yields_connect_streams() {
+        Runtime::new().unwrap().block_on(async {
+            let (client_io, server_io) = duplex();
+            let (endpoints, mut accepted) = mpsc::unbounded();
+            tokio::spawn(async move {
+                let mut incoming = H2ConnectAcceptor::new(server_io).handshake().await.unwrap();
+                while let Some(Ok(mid)) = incoming.next().await {
+                    endpoints
+                        .unbounded_send(mid.target_endpoint().to_string())
+                        .unwrap();
+                }
+            });
+
+            let (send_request, connection) =
+                client::handshake(Compat::new(client_io)).await.unwrap();
+            tokio::spawn(async move {
+                let _ = connection.await;
+            });
+
+            let get = Request::builder()
+                .uri("http://example.com/")
+                .body(())
+                .unwrap();
+            let mut send_request = send_request.ready().await.unwrap();
+            let (response, _) = send_request.send_request(get, true).unwrap();
+            assert_eq!(response.await.unwrap().status(), StatusCode::BAD_REQUEST);
+
+            let mut send_request = send_request.ready().await.unwrap();
+            let (_first, _) = send_request
+                .send_request(connect_request("first.example:443"), false)
+                .unwrap();
+            let mut send_request = send_request.ready().await.unwrap();
+            let (_second, _) = send_request
+                .send_request(connect_request("second.example:443"), false)
+                .unwrap();
+            assert_eq!(accepted.next().await.unwrap(), "first.example:443");
+            assert_eq!(accepted.next().await.unwrap(), "second.example:443");
+        });
+    }
+
+    #[test]
+    fn reports_failure_status() {
+        Runtime::new().unwrap().block_on(async {
+            let (inner, mut peers) = DuplexConnector::new();
+            tokio::spawn(async move {
+                let io = peers.next().await.unwrap();
+                let mut incoming = H2ConnectAcceptor::new(io).handshake().await.unwrap();
+                while let Some(Ok(mid)) = incoming.next().await {
+                    let err: Error = io::Error::from(io::ErrorKind::TimedOut).into();
+                    mid.fail(&err).await.unwrap();
+                }
+            });
+
+            let server = Endpoint::new_from_hostname("proxy.example", 443);
+            let connector = H2ConnectConnector::<_, DuplexStream>::new(inner, server);
+            let target = Endpoint::new_from_hostname("example.com", 443);
+            let err = connector.connect(&target).await.err().unwrap();
+            match err.downcast_ref::<H2ConnectConnectorError>() {
+                Some(H2ConnectConnectorError::Rejected { status: 504 }) => {}
+                _ => panic!("unexpected error: {}", err),
+            }
+        });
+    }
+}
@@ -0,0 +1,207 @@
+// MIT License
+
+// Copyright (c) 2019 Zhuhao Wang
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use crate::{
+    acceptor::{
+        http::{HttpConnectAcceptor, HttpConnectMidHandshake},
+        socks4::{Socks4Acceptor, Socks4MidHandshake},
+        socks5::{Socks5Acceptor, Socks5MidHandshake},
+        Acceptor, Authenticator, ClientMetadata, MidHandshake,
+    },
+    core::{Endpoint, Error, Result},
+    io::Prefixed,
+};
+use async_trait::async_trait;
+use futures::{
+    future::{Either, TryFutureExt},
+    io::{AsyncRead, AsyncReadExt, AsyncWrite},
+};
+use futures_tokio_compat::Compat;
+use std::{net::SocketAddr, sync::Arc};
+
+#[derive(Debug)]
+enum MixedError {
+    AuthenticationUnsupported,
+}
+
+impl std::fmt::Display for MixedError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::result::Result<(), std::fmt::Error> {
+        write!(f, "{:#?}", self)
+    }
+}
+
+impl std::error::Error for MixedError {}
+
+/// Serves SOCKS5, SOCKS4 and HTTP CONNECT on the same port by looking at the
+/// first byte sent by the client.
+pub struct MixedAcceptor<T: AsyncRead + AsyncWrite + Send + Unpin + 'static> {
+    io: T,
+    authenticator: Option<Arc<dyn Authenticator>>,
+}
+
+impl<T: AsyncRead + AsyncWrite + Send + Unpin + 'static> MixedAcceptor<T> {
+    pub fn new(io: T) -> Self {
+        MixedAcceptor {
+            io,
+            authenticator: None,
+        }
+    }
+
+    /// Requires the client to authenticate. SOCKS4 is refused since it can't
+    /// carry a password.
+    pub fn with_authenticator(io: T, authenticator: Arc<dyn Authenticator>) -> Self {
+        MixedAcceptor {
+            io,
+            authenticator: Some(authenticator),
+        }
+    }
+}
+
+#[async_trait]
+impl<T: AsyncRead + AsyncWrite + Send + Unpin + 'static> Acceptor<MixedMidHandshake<T>>
+    for MixedAcceptor<T>
+{
+    async fn handshake(self) -> Result<MixedMidHandshake<T>> {
+        let mut buf = [0; 1];
+        let mut io = self.io;
+        io.read_exact(&mut buf).err_into::<Error>().await?;
+
+        let io = Prefixed::new(io, buf.to_vec());
+        match (buf[0], self.authenticator) {
+            (5, Some(authenticator)) => Ok(MixedMidHandshake::Socks5(
+                Socks5Acceptor::with_authenticator(io, authenticator)
+                    .handshake()
+                    .await?,
+            )),
+            (5, None) => Ok(MixedMidHandshake::Socks5(
+                Socks5Acceptor::new(io).handshake().await?,
+            )),
+            (4, Some(_)) => {
+                // Read the whole request so the client gets a rejection
+                // instead of a dropped connection.
+                let mid = Socks4Acceptor::new(io).handshake().await?;
+                let err: Error = MixedError::AuthenticationUnsupported.into();
+                mid.fail(&err).await?;
+                Err(err)
+            }
+            (4, None) => Ok(MixedMidHandshake::Socks4(
+                Socks4Acceptor::new(io).handshake().await?,
+            )),
+            (_, Some(authenticator)) => Ok(MixedMidHandshake::Http(
+                HttpConnectAcceptor::with_authenticator(io, authenticator)
+                    .handshake()
+                    .await?,
+            )),
+            (_, None) => Ok(MixedMidHandshake::Http(
+                HttpConnectAcceptor::new(io).handshake().await?,
+            )),
+        }
+    }
+}
+
+pub enum MixedMidHandshake<T: AsyncRead + AsyncWrite + Send + Unpin + 'static> {
+    Socks5(Socks5MidHandshake<Prefixed<T>>),
+    Socks4(Socks4MidHandshake<Prefixed<T>>),
+    Http(HttpConnectMidHandshake<Compat<Compat<Prefixed<T>>>>),
+}
+
+#[async_trait]
+impl<T: AsyncRead + AsyncWrite + Send + Unpin + 'static>
+    MidHandshake<Either<Prefixed<T>, Compat<Compat<Prefixed<T>>>>> for MixedMidHandshake<T>
+{
+    fn target_endpoint(&self) -> &Endpoint {
+        match self {
+            MixedMidHandshake::Socks5(mid) => mid.target_endpoint(),
+            MixedMidHandshake::Socks4(mid) => mid.target_endpoint(),
+            MixedMidHandshake::Http(mid) => mid.target_endpoint(),
+        }
+    }
+
+    fn metadata(&self) -> &ClientMetadata {
+        match self {
+            MixedMidHandshake::Socks5(mid) => mid.metadata(),
+            MixedMidHandshake::Socks4(mid) => mid.metadata(),
+            MixedMidHandshake::Http(mid) => mid.metadata(),
+        }
+    }
+
+    async fn finalize(
+        self,
+        bound_addr: Option<SocketAddr>,
+    ) -> Result<Either<Prefixed<T>, Compat<Compat<Prefixed<T>>>>> {
+        match self {
+            MixedMidHandshake::Socks5(mid) => mid.finalize(bound_addr).await.map(Either::Left),
+            MixedMidHandshake::Socks4(mid) => mid.finalize(bound_addr).await.map(Either::Left),
+            MixedMidHandshake::Http(mid) => mid.finalize(bound_addr).await.map(Either::Right),
+        }
+    }
+
+    async fn fail(self, err: &Error) -> Result<()> {
+        match self {
+            MixedMidHandshake::Socks5(mid) => mid.fail(err).await,
+            MixedMidHandshake::Socks4(mid) => mid.fail(err).await,
+            MixedMidHandshake::Http(mid) => mid.fail(err).await,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{acceptor::StaticAuthenticator, io::mock::MockStream};
+    use futures::executor::block_on;
+
+    #[test]
+    fn detects_socks5() {
+        let io = MockStream::new(&[5, 1, 0, 5, 1, 0, 1, 127, 0, 0, 1, 0, 80]);
+
+        match block_on(MixedAcceptor::new(io).handshake()).unwrap() {
+            MixedMidHandshake::Socks5(mid) => {
+                assert_eq!(mid.target_endpoint().to_string(), "127.0.0.1:80")
+            }
+            _ => panic!("not detected as SOCKS5"),
+        }
+    }
+
+    #[test]
+    fn detects_socks4() {
+        let io = MockStream::new(b"\x04\x01\x00\x50\x7f\x00\x00\x01\x00");
+
+        match block_on(MixedAcceptor::new(io).handshake()).unwrap() {
+            MixedMidHandshake::Socks4(mid) => {
+                assert_eq!(mid.target_endpoint().to_string(), "127.0.0.1:80")
+            }
+            _ => panic!("not detected as SOCKS4"),
+        }
+    }
+
+    #[test]
+    fn refuses_socks4_with_authenticator() {
+        let io = MockStream::new(b"\x04\x01\x00\x50\x7f\x00\x00\x01\x00");
+        let output = io.output();
+        let acceptor = MixedAcceptor::with_authenticator(io, Arc::new(StaticAuthenticator::new()));
+
+        let err = block_on(acceptor.handshake()).err().unwrap();
+        assert!(err.is::<MixedError>());
+        assert_eq!(*output.lock().unwrap(), [0, 0x5b, 0, 0, 0, 0, 0, 0]);
+    }
+}
@@ -20,13 +20,109 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
-use crate::core::Result;
+use crate::{
+    connector::Connector,
+    core::{Endpoint, Error, Result},
+    io::forward,
+    proxy_protocol::ProxyHeader,
+};
 use async_trait::async_trait;
+use futures::io::{AsyncRead, AsyncWrite};
+use std::net::SocketAddr;
 
+mod auth;
 pub mod http;
+pub mod http2;
+pub mod mixed;
+pub mod proxy_protocol;
+pub mod shadowsocks;
+pub mod socks4;
 pub mod socks5;
+pub mod tls;
+pub mod trojan;
+pub mod websocket;
+
+pub use self::auth::{Authenticator, FnAuthenticator, StaticAuthenticator};
 
 #[async_trait]
 pub trait Acceptor<T> {
     async fn handshake(self) -> Result<T>;
 }
+
+/// What an acceptor learned about the client during the handshake.
+#[derive(Clone, Debug, Default)]
+pub struct ClientMetadata {
+    /// The user the client authenticated as, or the SOCKS4 USERID.
+    pub user: Option<String>,
+    /// The server name the client asked for in the TLS handshake.
+    pub sni: Option<String>,
+    /// The protocol negotiated with ALPN in the TLS handshake.
+    pub alpn: Option<Vec<u8>>,
+    /// The PROXY protocol header sent by the load balancer in front of us,
+    /// with the addresses of the original client.
+    pub proxy_header: Option<ProxyHeader>,
+}
+
+/// The result of a handshake, waiting for the target to be connected before
+/// telling the client whether it succeeded.
+#[async_trait]
+pub trait MidHandshake<T>: Send {
+    fn target_endpoint(&self) -> &Endpoint;
+
+    fn metadata(&self) -> &ClientMetadata;
+
+    /// Whether the client still needs a tunnel to `target_endpoint`, or was
+    /// already served during the handshake, e.g., by forwarding its plain
+    /// HTTP requests.
+    fn needs_tunnel(&self) -> bool {
+        true
+    }
+
+    /// Tells the client the tunnel is established and returns the stream to
+    /// relay. `bound_addr` is the local address of the outbound connection,
+    /// reported to the client if the protocol carries it.
+    async fn finalize(self, bound_addr: Option<SocketAddr>) -> Result<T>;
+
+    /// Tells the client that connecting to the target failed with `err`.
+    async fn fail(self, err: &Error) -> Result<()>;
+}
+
+/// Accepts a client, connects to its target with `connector` and relays data
+/// between the two until either side closes.
+pub async fn relay<A, M, T, C, S>(acceptor: A, connector: C) -> Result<()>
+where
+    A: Acceptor<M>,
+    M: MidHandshake<T>,
+    T: AsyncRead + AsyncWrite + Send + Unpin,
+    C: Connector<S> + Send + 'static,
+    S: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+{
+    relay_mid_handshake(acceptor.handshake().await?, connector).await
+}
+
+/// Like `relay`, for a client whose handshake is already done, e.g., one of
+/// the streams of an HTTP/2 connection.
+pub async fn relay_mid_handshake<M, T, C, S>(mid: M, connector: C) -> Result<()>
+where
+    M: MidHandshake<T>,
+    T: AsyncRead + AsyncWrite + Send + Unpin,
+    C: Connector<S> + Send + 'static,
+    S: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+{
+    if !mid.needs_tunnel() {
+        return Ok(());
+    }
+
+    let result = connector
+        .connect_with_local_addr(mid.target_endpoint())
+        .await;
+    let (stream, local_addr) = match result {
+        Ok(connected) => connected,
+        Err(err) => {
+            mid.fail(&err).await?;
+            return Err(err);
+        }
+    };
+
+    forward(mid.finalize(local_addr).await?, stream).await
+}
@@ -0,0 +1,109 @@
+// MIT License
+
+// Copyright (c) 2019 Zhuhao Wang
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use crate::{
+    acceptor::{Acceptor, ClientMetadata, MidHandshake},
+    core::{Endpoint, Error, Result},
+    proxy_protocol::read_header,
+};
+use async_trait::async_trait;
+use futures::io::{AsyncRead, AsyncWrite};
+use std::{marker::PhantomData, net::SocketAddr};
+
+/// Reads the PROXY protocol header a load balancer sends ahead of the
+/// client's data, then hands the stream to the acceptor created by
+/// `make_inner`.
+///
+/// The header is required; connections without one are rejected, since
+/// they did not come through the load balancer.
+pub struct ProxyProtocolAcceptor<T, F> {
+    io: T,
+    make_inner: F,
+}
+
+impl<T: AsyncRead + AsyncWrite + Send + Unpin + 'static, F> ProxyProtocolAcceptor<T, F> {
+    pub fn new(io: T, make_inner: F) -> Self {
+        ProxyProtocolAcceptor { io, make_inner }
+    }
+}
+
+#[async_trait]
+impl<T, F, A, M, I> Acceptor<ProxyProtocolMidHandshake<M, I>> for ProxyProtocolAcceptor<T, F>
+where
+    T: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+    F: FnOnce(T) -> A + Send + 'static,
+    A: Acceptor<M> + Send + 'static,
+    M: MidHandshake<I> + 'static,
+    I: 'static,
+{
+    async fn handshake(mut self) -> Result<ProxyProtocolMidHandshake<M, I>> {
+        let header = read_header(&mut self.io).await?;
+
+        let inner = (self.make_inner)(self.io).handshake().await?;
+        let metadata = ClientMetadata {
+            proxy_header: Some(header),
+            ..inner.metadata().clone()
+        };
+
+        Ok(ProxyProtocolMidHandshake {
+            inner,
+            metadata,
+            _marker: PhantomData,
+        })
+    }
+}
+
+pub struct ProxyProtocolMidHandshake<M, I> {
+    inner: M,
+    metadata: ClientMetadata,
+    _marker: PhantomData<fn() -> I>,
+}
+
+impl<M, I> ProxyProtocolMidHandshake<M, I> {
+    pub fn into_inner(self) -> M {
+        self.inner
+    }
+}
+
+#[async_trait]
+impl<M, I> MidHandshake<I> for ProxyProtocolMidHandshake<M, I>
+where
+    M: MidHandshake<I>,
+    I: 'static,
+{
+    fn target_endpoint(&self) -> &Endpoint {
+        self.inner.target_endpoint()
+    }
+
+    /// The metadata of the inner acceptor, with the PROXY protocol header.
+    fn metadata(&self) -> &ClientMetadata {
+        &self.metadata
+    }
+
+    async fn finalize(self, bound_addr: Option<SocketAddr>) -> Result<I> {
+        self.inner.finalize(bound_addr).await
+    }
+
+    async fn fail(self, err: &Error) -> Result<()> {
+        self.inner.fail(err).await
+    }
+}
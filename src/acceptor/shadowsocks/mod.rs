@@ -0,0 +1,235 @@
+// MIT License
+
+// Copyright (c) 2019 Zhuhao Wang
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use crate::{
+    acceptor::{Acceptor, ClientMetadata, MidHandshake},
+    core::{read_socks_addr, Endpoint, Error, Result},
+    shadowsocks::{Role, ShadowsocksKey, ShadowsocksStream},
+};
+use async_trait::async_trait;
+use futures::{
+    future::TryFutureExt,
+    io::{AsyncRead, AsyncReadExt, AsyncWrite},
+};
+use std::{
+    collections::HashSet,
+    mem,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+};
+
+#[derive(Debug)]
+enum ShadowsocksAcceptorError {
+    ReplayedSalt,
+}
+
+impl std::fmt::Display for ShadowsocksAcceptorError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::result::Result<(), std::fmt::Error> {
+        write!(f, "{:#?}", self)
+    }
+}
+
+impl std::error::Error for ShadowsocksAcceptorError {}
+
+/// Remembers the salts of recent sessions to reject replayed ones.
+///
+/// Salts are kept in two generations of `capacity` entries each; when the
+/// current one is full it replaces the previous one, so at least the last
+/// `capacity` salts are always remembered.
+pub struct SaltFilter {
+    capacity: usize,
+    generations: Mutex<(HashSet<Vec<u8>>, HashSet<Vec<u8>>)>,
+}
+
+impl SaltFilter {
+    pub fn new(capacity: usize) -> Self {
+        SaltFilter {
+            capacity,
+            generations: Mutex::new((HashSet::new(), HashSet::new())),
+        }
+    }
+
+    /// Records `salt`, returning `false` if it has been seen before.
+    pub fn insert(&self, salt: &[u8]) -> bool {
+        let mut generations = self.generations.lock().unwrap();
+        let (ref mut current, ref mut previous) = *generations;
+
+        if current.contains(salt) || previous.contains(salt) {
+            return false;
+        }
+
+        if current.len() >= self.capacity {
+            *previous = mem::replace(current, HashSet::new());
+        }
+        current.insert(salt.to_vec());
+        true
+    }
+}
+
+pub struct ShadowsocksAcceptor<T: AsyncRead + AsyncWrite + Send + Unpin + 'static> {
+    io: T,
+    key: ShadowsocksKey,
+    salt_filter: Arc<SaltFilter>,
+}
+
+impl<T: AsyncRead + AsyncWrite + Send + Unpin + 'static> ShadowsocksAcceptor<T> {
+    /// `salt_filter` should be shared by all the connections using `key`.
+    pub fn new(io: T, key: ShadowsocksKey, salt_filter: Arc<SaltFilter>) -> Self {
+        ShadowsocksAcceptor {
+            io,
+            key,
+            salt_filter,
+        }
+    }
+}
+
+#[async_trait]
+impl<T: AsyncRead + AsyncWrite + Send + Unpin + 'static> Acceptor<ShadowsocksMidHandshake<T>>
+    for ShadowsocksAcceptor<T>
+{
+    async fn handshake(self) -> Result<ShadowsocksMidHandshake<T>> {
+        let is_2022 = self.key.method().is_2022();
+        let mut io = ShadowsocksStream::new(self.io, self.key, Role::Server);
+
+        // Reading the address authenticates the header, so a salt is only
+        // recorded once it's known to come from a client holding the key.
+        let target_endpoint = read_socks_addr(&mut io).await?;
+
+        if !self.salt_filter.insert(io.peer_salt()) {
+            return Err(ShadowsocksAcceptorError::ReplayedSalt.into());
+        }
+
+        if is_2022 {
+            let mut buf = [0; 2];
+            io.read_exact(&mut buf).err_into::<Error>().await?;
+            let mut padding = vec![0; u16::from_be_bytes(buf).into()];
+            io.read_exact(&mut padding).err_into::<Error>().await?;
+        }
+
+        Ok(ShadowsocksMidHandshake {
+            io,
+            target_endpoint,
+            metadata: ClientMetadata::default(),
+        })
+    }
+}
+
+pub struct ShadowsocksMidHandshake<T: AsyncRead + AsyncWrite + Send + Unpin + 'static> {
+    io: ShadowsocksStream<T>,
+    target_endpoint: Endpoint,
+    metadata: ClientMetadata,
+}
+
+#[async_trait]
+impl<T: AsyncRead + AsyncWrite + Send + Unpin + 'static> MidHandshake<ShadowsocksStream<T>>
+    for ShadowsocksMidHandshake<T>
+{
+    fn target_endpoint(&self) -> &Endpoint {
+        &self.target_endpoint
+    }
+
+    fn metadata(&self) -> &ClientMetadata {
+        &self.metadata
+    }
+
+    /// Shadowsocks has no reply, the response simply starts with the data
+    /// from the target.
+    async fn finalize(self, _bound_addr: Option<SocketAddr>) -> Result<ShadowsocksStream<T>> {
+        Ok(self.io)
+    }
+
+    /// The connection is closed, which is all the client could learn.
+    async fn fail(self, _err: &Error) -> Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{io::mock::MockStream, shadowsocks::ShadowsocksMethod};
+    use futures::{executor::block_on, io::AsyncWriteExt};
+
+    #[test]
+    fn filter_rejects_seen_salts() {
+        let filter = SaltFilter::new(2);
+        assert!(filter.insert(b"a"));
+        assert!(!filter.insert(b"a"));
+    }
+
+    #[test]
+    fn filter_remembers_previous_generation() {
+        let filter = SaltFilter::new(2);
+        assert!(filter.insert(b"a"));
+        assert!(filter.insert(b"b"));
+        // Starts a new generation, "a" and "b" are still remembered.
+        assert!(filter.insert(b"c"));
+        assert!(!filter.insert(b"a"));
+        assert!(filter.insert(b"d"));
+        // Drops the generation holding "a" and "b".
+        assert!(filter.insert(b"e"));
+        assert!(filter.insert(b"a"));
+        assert!(!filter.insert(b"d"));
+    }
+
+    fn request(key: &ShadowsocksKey) -> Vec<u8> {
+        let io = MockStream::new(&[]);
+        let sent = io.output();
+        let mut client = ShadowsocksStream::new(io, key.clone(), Role::Client);
+        block_on(client.send_request(&Endpoint::new_from_hostname("example.com", 443))).unwrap();
+        block_on(client.write_all(b"hello")).unwrap();
+
+        let mut buf = Vec::new();
+        buf.extend_from_slice(&sent.lock().unwrap());
+        buf
+    }
+
+    fn accept_twice(key: ShadowsocksKey) {
+        let filter = Arc::new(SaltFilter::new(16));
+        let request = request(&key);
+
+        let acceptor =
+            ShadowsocksAcceptor::new(MockStream::new(&request), key.clone(), filter.clone());
+        let mid = block_on(acceptor.handshake()).unwrap();
+        assert_eq!(mid.target_endpoint().to_string(), "example.com:443");
+
+        let mut io = block_on(mid.finalize(None)).unwrap();
+        let mut buf = Vec::new();
+        block_on(io.read_to_end(&mut buf)).unwrap();
+        assert_eq!(buf, b"hello");
+
+        let acceptor = ShadowsocksAcceptor::new(MockStream::new(&request), key, filter);
+        let err = block_on(acceptor.handshake()).err().unwrap();
+        assert!(err.is::<ShadowsocksAcceptorError>());
+    }
+
+    #[test]
+    fn rejects_replayed_sip004_session() {
+        accept_twice(ShadowsocksKey::new(ShadowsocksMethod::Aes256Gcm, "password").unwrap());
+    }
+
+    #[test]
+    fn rejects_replayed_sip022_session() {
+        let key = base64::encode(&[3; 32]);
+        accept_twice(ShadowsocksKey::new(ShadowsocksMethod::Blake3Aes256Gcm, &key).unwrap());
+    }
+}
@@ -0,0 +1,227 @@
+// MIT License
+
+// Copyright (c) 2019 Zhuhao Wang
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use crate::{
+    acceptor::{Acceptor, ClientMetadata, MidHandshake},
+    core::{Endpoint, Error, Result},
+};
+use async_trait::async_trait;
+use futures::{
+    future::TryFutureExt,
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+};
+use std::net::{Ipv4Addr, SocketAddr};
+
+#[derive(Debug)]
+enum Socks4Error {
+    UnsupportedVersion,
+    UnsupportedCommand,
+    FieldTooLong,
+}
+
+impl std::fmt::Display for Socks4Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::result::Result<(), std::fmt::Error> {
+        write!(f, "{:#?}", self)
+    }
+}
+
+impl std::error::Error for Socks4Error {}
+
+const REQUEST_GRANTED: u8 = 90;
+const REQUEST_REJECTED: u8 = 91;
+
+pub struct Socks4Acceptor<T: AsyncRead + AsyncWrite + Send + Unpin + 'static> {
+    io: T,
+}
+
+impl<T: AsyncRead + AsyncWrite + Send + Unpin + 'static> Socks4Acceptor<T> {
+    pub fn new(io: T) -> Self {
+        Socks4Acceptor { io }
+    }
+}
+
+async fn read_null_terminated<T: AsyncRead + Unpin>(io: &mut T) -> Result<String> {
+    let mut string = vec![];
+    let mut buf = [0; 1];
+    loop {
+        io.read_exact(&mut buf).err_into::<Error>().await?;
+        if buf[0] == 0 {
+            return String::from_utf8(string).map_err(Into::into);
+        }
+
+        if string.len() == 255 {
+            return Err(Socks4Error::FieldTooLong.into());
+        }
+        string.push(buf[0]);
+    }
+}
+
+#[async_trait]
+impl<T: AsyncRead + AsyncWrite + Send + Unpin + 'static> Acceptor<Socks4MidHandshake<T>>
+    for Socks4Acceptor<T>
+{
+    async fn handshake(self) -> Result<Socks4MidHandshake<T>> {
+        let mut buf = [0; 8];
+        let mut io = self.io;
+        io.read_exact(&mut buf).err_into::<Error>().await?;
+
+        if buf[0] != 4 {
+            return Err(Socks4Error::UnsupportedVersion.into());
+        }
+
+        if buf[1] != 1 {
+            write_reply(&mut io, REQUEST_REJECTED).await?;
+            return Err(Socks4Error::UnsupportedCommand.into());
+        }
+
+        let port = u16::from_be_bytes([buf[2], buf[3]]);
+        let ip = Ipv4Addr::new(buf[4], buf[5], buf[6], buf[7]);
+
+        let user_id = read_null_terminated(&mut io).await?;
+
+        // SOCKS4a marks a request carrying a hostname with the address 0.0.0.x.
+        let octets = ip.octets();
+        let target_endpoint = if octets[..3] == [0, 0, 0] && octets[3] != 0 {
+            Endpoint::new_from_hostname(&read_null_terminated(&mut io).await?, port)
+        } else {
+            Endpoint::new_from_addr(SocketAddr::new(ip.into(), port))
+        };
+
+        Ok(Socks4MidHandshake {
+            io,
+            target_endpoint,
+            metadata: ClientMetadata {
+                user: if user_id.is_empty() {
+                    None
+                } else {
+                    Some(user_id)
+                },
+                ..Default::default()
+            },
+        })
+    }
+}
+
+async fn write_reply<T: AsyncWrite + Unpin>(io: &mut T, code: u8) -> Result<()> {
+    let buf = [0, code, 0, 0, 0, 0, 0, 0];
+    io.write_all(&buf).err_into::<Error>().await
+}
+
+pub struct Socks4MidHandshake<T: AsyncRead + AsyncWrite + Send + Unpin + 'static> {
+    io: T,
+    target_endpoint: Endpoint,
+    metadata: ClientMetadata,
+}
+
+async fn finalize<T: AsyncRead + AsyncWrite + Send + Unpin + 'static>(
+    mut acceptor: Socks4MidHandshake<T>,
+    code: u8,
+) -> Result<T> {
+    write_reply(&mut acceptor.io, code).await?;
+    Ok(acceptor.io)
+}
+
+#[async_trait]
+impl<I: AsyncRead + AsyncWrite + Send + Unpin + 'static> MidHandshake<I> for Socks4MidHandshake<I> {
+    fn target_endpoint(&self) -> &Endpoint {
+        &self.target_endpoint
+    }
+
+    /// The user is the USERID field of the request, unless it is empty.
+    fn metadata(&self) -> &ClientMetadata {
+        &self.metadata
+    }
+
+    /// SOCKS4 ignores the bound address in replies to CONNECT.
+    async fn finalize(self, _bound_addr: Option<SocketAddr>) -> Result<I> {
+        finalize(self, REQUEST_GRANTED).await
+    }
+
+    /// SOCKS4 has no way to tell why the request failed, so any error is
+    /// reported as a rejection.
+    async fn fail(self, _err: &Error) -> Result<()> {
+        finalize(self, REQUEST_REJECTED).await.map(|_| ())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::mock::MockStream;
+    use futures::executor::block_on;
+
+    #[test]
+    fn accepts_socks4_request() {
+        let io = MockStream::new(b"\x04\x01\x00\x50\x7f\x00\x00\x01alice\x00");
+        let output = io.output();
+
+        let mid = block_on(Socks4Acceptor::new(io).handshake()).unwrap();
+        assert_eq!(mid.target_endpoint().to_string(), "127.0.0.1:80");
+        assert_eq!(
+            mid.metadata().user.as_ref().map(String::as_str),
+            Some("alice")
+        );
+
+        block_on(mid.finalize(None)).unwrap();
+        assert_eq!(
+            *output.lock().unwrap(),
+            [0, REQUEST_GRANTED, 0, 0, 0, 0, 0, 0]
+        );
+    }
+
+    #[test]
+    fn accepts_socks4a_request() {
+        let io = MockStream::new(b"\x04\x01\x01\xbb\x00\x00\x00\x01alice\x00example.com\x00");
+
+        let mid = block_on(Socks4Acceptor::new(io).handshake()).unwrap();
+        assert_eq!(mid.target_endpoint().to_string(), "example.com:443");
+    }
+
+    #[test]
+    fn ignores_empty_user_id() {
+        let io = MockStream::new(b"\x04\x01\x00\x50\x7f\x00\x00\x01\x00");
+
+        let mid = block_on(Socks4Acceptor::new(io).handshake()).unwrap();
+        assert!(mid.metadata().user.is_none());
+    }
+
+    #[test]
+    fn rejects_bind() {
+        let io = MockStream::new(b"\x04\x02\x00\x50\x7f\x00\x00\x01\x00");
+        let output = io.output();
+
+        assert!(block_on(Socks4Acceptor::new(io).handshake()).is_err());
+        assert_eq!(
+            *output.lock().unwrap(),
+            [0, REQUEST_REJECTED, 0, 0, 0, 0, 0, 0]
+        );
+    }
+
+    #[test]
+    fn rejects_long_user_id() {
+        let mut input = b"\x04\x01\x00\x50\x7f\x00\x00\x01".to_vec();
+        input.extend_from_slice(&[b'a'; 256]);
+        input.push(0);
+
+        assert!(block_on(Socks4Acceptor::new(MockStream::new(&input)).handshake()).is_err());
+    }
+}
@@ -0,0 +1,218 @@
+// MIT License
+
+// Copyright (c) 2019 Zhuhao Wang
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use super::{
+    reply::write_reply, unspecified_addr, Socks5Command, Socks5Error, Socks5MidHandshake,
+    Socks5Reply,
+};
+use crate::core::{Endpoint, Error, Result};
+use futures::{
+    future::{self, Either, FutureExt},
+    io::{AsyncRead, AsyncReadExt, AsyncWrite},
+};
+use std::net::{IpAddr, SocketAddr};
+use tokio::net::{TcpListener, TcpStream};
+
+impl<I: AsyncRead + AsyncWrite + Send + Unpin + 'static> Socks5MidHandshake<I> {
+    /// Opens a listening socket on `listen_ip` and sends the first reply
+    /// with its address.
+    ///
+    /// `listen_ip` must be reachable by the peer the client is expecting.
+    pub async fn bind(mut self, listen_ip: IpAddr) -> Result<Socks5BindMidHandshake<I>> {
+        if self.command != Socks5Command::Bind {
+            self.fail_with(Socks5Reply::CommandNotSupported).await?;
+            return Err(Socks5Error::UnsupportedCommand.into());
+        }
+
+        let listener = match TcpListener::bind(&SocketAddr::new(listen_ip, 0)) {
+            Ok(listener) => listener,
+            Err(err) => {
+                let err: Error = err.into();
+                self.fail_with(Socks5Reply::from_error(&err)).await?;
+                return Err(err);
+            }
+        };
+        write_reply(&mut self.io, Socks5Reply::Succeeded, listener.local_addr()?).await?;
+
+        Ok(Socks5BindMidHandshake {
+            io: self.io,
+            listener,
+            expected_peer: self.target_endpoint,
+        })
+    }
+}
+
+pub struct Socks5BindMidHandshake<T: AsyncRead + AsyncWrite + Send + Unpin + 'static> {
+    io: T,
+    listener: TcpListener,
+    expected_peer: Endpoint,
+}
+
+impl<T: AsyncRead + AsyncWrite + Send + Unpin + 'static> Socks5BindMidHandshake<T> {
+    pub fn bound_addr(&self) -> Result<SocketAddr> {
+        self.listener.local_addr().map_err(Into::into)
+    }
+
+    /// Waits for the peer to connect and sends the second reply, returning
+    /// the client stream along with the stream from the peer.
+    ///
+    /// Connections from an address other than the one the client asked for
+    /// are dropped. Gives up, with a failure reply, if accepting fails or the
+    /// client sends anything on, or closes, the control connection while
+    /// waiting.
+    pub async fn accept(self) -> Result<(T, TcpStream)> {
+        let Socks5BindMidHandshake {
+            mut io,
+            mut listener,
+            expected_peer,
+        } = self;
+
+        let mut buf = [0; 1];
+        loop {
+            let accepted: Result<_> =
+                match future::select(listener.accept().boxed(), io.read(&mut buf).boxed()).await {
+                    Either::Left((accepted, _)) => accepted.map_err(Into::into),
+                    Either::Right(_) => Err(Socks5Error::BindAborted.into()),
+                };
+
+            let (peer, addr) = match accepted {
+                Ok(accepted) => accepted,
+                Err(err) => {
+                    // The client may be gone already.
+                    let _ = write_reply(&mut io, Socks5Reply::from_error(&err), unspecified_addr())
+                        .await;
+                    return Err(err);
+                }
+            };
+
+            if let Endpoint::Ip(expected) = expected_peer {
+                if !expected.ip().is_unspecified() && expected.ip() != addr.ip() {
+                    continue;
+                }
+            }
+
+            write_reply(&mut io, Socks5Reply::Succeeded, addr).await?;
+            return Ok((io, peer));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{acceptor::ClientMetadata, io::mock::MockStream};
+    use futures_tokio_compat::Compat;
+    use tokio::runtime::Runtime;
+
+    fn mid(io: MockStream, expected_peer: &str) -> Socks5MidHandshake<MockStream> {
+        Socks5MidHandshake {
+            io,
+            command: Socks5Command::Bind,
+            target_endpoint: Endpoint::Ip(expected_peer.parse().unwrap()),
+            metadata: ClientMetadata::default(),
+        }
+    }
+
+    fn reply(reply: Socks5Reply, addr: SocketAddr) -> Vec<u8> {
+        let mut buf = Vec::new();
+        futures::executor::block_on(write_reply(&mut buf, reply, addr)).unwrap();
+        buf
+    }
+
+    #[test]
+    fn replies_twice() {
+        Runtime::new().unwrap().block_on(async {
+            let io = MockStream::open(&[]);
+            let output = io.output();
+            let bind = mid(io, "127.0.0.1:0")
+                .bind("127.0.0.1".parse().unwrap())
+                .await
+                .unwrap();
+            let bound_addr = bind.bound_addr().unwrap();
+            assert_eq!(
+                *output.lock().unwrap(),
+                reply(Socks5Reply::Succeeded, bound_addr)
+            );
+
+            let (accepted, peer) =
+                future::join(bind.accept(), TcpStream::connect(&bound_addr)).await;
+            accepted.unwrap();
+            let peer_addr = peer.unwrap().local_addr().unwrap();
+
+            let mut expected = reply(Socks5Reply::Succeeded, bound_addr);
+            expected.extend_from_slice(&reply(Socks5Reply::Succeeded, peer_addr));
+            assert_eq!(*output.lock().unwrap(), expected);
+        });
+    }
+
+    #[test]
+    fn drops_unexpected_peer() {
+        Runtime::new().unwrap().block_on(async {
+            let io = MockStream::open(&[]);
+            let output = io.output();
+            let bind = mid(io, "192.0.2.1:0")
+                .bind("127.0.0.1".parse().unwrap())
+                .await
+                .unwrap();
+            let bound_addr = bind.bound_addr().unwrap();
+
+            // The connection is closed without the client hearing about it,
+            // and the relay keeps waiting for the expected peer.
+            let rejected = async {
+                let mut peer = Compat::new(TcpStream::connect(&bound_addr).await.unwrap());
+                let mut buf = [0; 1];
+                peer.read(&mut buf).await
+            };
+            match future::select(bind.accept().boxed(), rejected.boxed()).await {
+                Either::Right((read, _)) => assert_eq!(read.unwrap(), 0),
+                Either::Left(_) => panic!("accepted an unexpected peer"),
+            }
+            assert_eq!(
+                *output.lock().unwrap(),
+                reply(Socks5Reply::Succeeded, bound_addr)
+            );
+        });
+    }
+
+    #[test]
+    fn fails_when_client_aborts() {
+        Runtime::new().unwrap().block_on(async {
+            let io = MockStream::new(&[]);
+            let output = io.output();
+            let bind = mid(io, "127.0.0.1:0")
+                .bind("127.0.0.1".parse().unwrap())
+                .await
+                .unwrap();
+            let bound_addr = bind.bound_addr().unwrap();
+
+            let err = bind.accept().await.err().unwrap();
+            match err.downcast_ref::<Socks5Error>() {
+                Some(Socks5Error::BindAborted) => {}
+                _ => panic!("unexpected error {}", err),
+            }
+
+            let mut expected = reply(Socks5Reply::Succeeded, bound_addr);
+            expected.extend_from_slice(&reply(Socks5Reply::GeneralFailure, unspecified_addr()));
+            assert_eq!(*output.lock().unwrap(), expected);
+        });
+    }
+}
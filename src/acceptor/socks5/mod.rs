@@ -21,23 +21,37 @@
 // SOFTWARE.
 
 use crate::{
-    acceptor::Acceptor,
-    core::{Endpoint, Error, Result},
+    acceptor::{Acceptor, Authenticator, ClientMetadata, MidHandshake},
+    core::{read_socks_addr, Endpoint, Error, Result, SocksAddrError},
 };
 use async_trait::async_trait;
 use futures::{
     future::{BoxFuture, FutureExt, TryFutureExt},
     io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
 };
-use std::net::{IpAddr, SocketAddr};
+use std::{
+    net::{Ipv4Addr, SocketAddr},
+    sync::Arc,
+};
+
+mod bind;
+mod reply;
+mod udp;
+pub use self::bind::Socks5BindMidHandshake;
+use self::reply::write_reply;
+pub use self::udp::{decode_udp_packet, encode_udp_packet, Socks5UdpAssociation};
+pub use crate::core::Socks5Reply;
 
 #[derive(Debug)]
 enum Socks5Error {
     UnsupportedVersion,
     InvalidMethodCount,
     UnsupportedAuthMethod,
+    UnsupportedAuthVersion,
+    AuthenticationFailed,
     UnsupportedCommand,
-    UnsupportedAddressType,
+    FragmentedDatagram,
+    BindAborted,
 }
 
 impl std::fmt::Display for Socks5Error {
@@ -48,30 +62,65 @@ impl std::fmt::Display for Socks5Error {
 
 impl std::error::Error for Socks5Error {}
 
-enum IpOrDomain {
-    Ip(IpAddr),
-    Domain(String),
+/// The command requested by the client.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Socks5Command {
+    Connect,
+    Bind,
+    UdpAssociate,
 }
 
-impl From<IpAddr> for IpOrDomain {
-    fn from(ip: IpAddr) -> IpOrDomain {
-        IpOrDomain::Ip(ip)
-    }
+pub struct Socks5Acceptor<T: AsyncRead + AsyncWrite + Send + Unpin + 'static> {
+    io: T,
+    authenticator: Option<Arc<dyn Authenticator>>,
 }
 
-impl From<String> for IpOrDomain {
-    fn from(domain: String) -> IpOrDomain {
-        IpOrDomain::Domain(domain)
+impl<T: AsyncRead + AsyncWrite + Send + Unpin + 'static> Socks5Acceptor<T> {
+    pub fn new(io: T) -> Self {
+        Socks5Acceptor {
+            io,
+            authenticator: None,
+        }
+    }
+
+    /// Requires the client to authenticate with username/password (RFC 1929).
+    pub fn with_authenticator(io: T, authenticator: Arc<dyn Authenticator>) -> Self {
+        Socks5Acceptor {
+            io,
+            authenticator: Some(authenticator),
+        }
     }
 }
 
-pub struct Socks5Acceptor<T: AsyncRead + AsyncWrite + Send + Unpin + 'static> {
-    io: T,
+async fn read_string<T: AsyncRead + Unpin>(io: &mut T) -> Result<String> {
+    let mut buf = [0; 1];
+    io.read_exact(&mut buf).err_into::<Error>().await?;
+
+    let mut buf = vec![0; buf[0].into()];
+    io.read_exact(&mut buf).err_into::<Error>().await?;
+    String::from_utf8(buf).map_err(Into::into)
 }
 
-impl<T: AsyncRead + AsyncWrite + Send + Unpin + 'static> Socks5Acceptor<T> {
-    pub fn new(io: T) -> Self {
-        Socks5Acceptor { io }
+async fn authenticate<T: AsyncRead + AsyncWrite + Unpin>(
+    io: &mut T,
+    authenticator: &dyn Authenticator,
+) -> Result<String> {
+    let mut buf = [0; 1];
+    io.read_exact(&mut buf).err_into::<Error>().await?;
+
+    if buf[0] != 1 {
+        return Err(Socks5Error::UnsupportedAuthVersion.into());
+    }
+
+    let username = read_string(io).await?;
+    let password = read_string(io).await?;
+
+    if authenticator.authenticate(&username, &password).await? {
+        io.write_all(&[1, 0]).err_into::<Error>().await?;
+        Ok(username)
+    } else {
+        io.write_all(&[1, 1]).err_into::<Error>().await?;
+        Err(Socks5Error::AuthenticationFailed.into())
     }
 }
 
@@ -79,7 +128,7 @@ impl<T: AsyncRead + AsyncWrite + Send + Unpin + 'static> Socks5Acceptor<T> {
 impl<T: AsyncRead + AsyncWrite + Send + Unpin + 'static> Acceptor<Socks5MidHandshake<T>>
     for Socks5Acceptor<T>
 {
-    async fn handshake(mut self) -> Result<Socks5MidHandshake<T>> {
+    async fn handshake(self) -> Result<Socks5MidHandshake<T>> {
         let mut buf = [0; 2];
         let mut io = self.io;
         io.read_exact(&mut buf).err_into::<Error>().await?;
@@ -92,61 +141,65 @@ impl<T: AsyncRead + AsyncWrite + Send + Unpin + 'static> Acceptor<Socks5MidHands
             return Err(Socks5Error::InvalidMethodCount.into());
         }
 
-        let mut buf = vec![0, buf[1].into()];
+        let mut buf = vec![0; buf[1].into()];
 
         io.read_exact(&mut buf).err_into::<Error>().await?;
 
-        if !buf.iter().any(|x| *x == 0) {
+        let method = if self.authenticator.is_some() { 2 } else { 0 };
+        if !buf.iter().any(|x| *x == method) {
+            io.write_all(&[5, 0xff]).err_into::<Error>().await?;
             return Err(Socks5Error::UnsupportedAuthMethod.into());
         }
 
-        let buf: [u8; 2] = [5, 0];
+        let buf: [u8; 2] = [5, method];
         io.write_all(&buf).err_into::<Error>().await?;
 
-        let mut buf = [0; 4];
+        let user = match self.authenticator {
+            Some(ref authenticator) => Some(authenticate(&mut io, authenticator.as_ref()).await?),
+            None => None,
+        };
+
+        let mut buf = [0; 3];
         io.read_exact(&mut buf).err_into::<Error>().await?;
 
         if buf[0] != 5 {
             return Err(Socks5Error::UnsupportedVersion.into());
         }
 
-        if buf[1] != 1 {
-            return Err(Socks5Error::UnsupportedCommand.into());
-        }
-
-        let ip_or_domain = match buf[3] {
-            1 => {
-                let mut buf = [0; 4];
-                io.read_exact(&mut buf).err_into::<Error>().await?;
-                IpAddr::from(buf).into()
+        let command = match buf[1] {
+            1 => Socks5Command::Connect,
+            2 => Socks5Command::Bind,
+            3 => Socks5Command::UdpAssociate,
+            _ => {
+                write_reply(
+                    &mut io,
+                    Socks5Reply::CommandNotSupported,
+                    unspecified_addr(),
+                )
+                .await?;
+                return Err(Socks5Error::UnsupportedCommand.into());
             }
-            3 => {
-                let mut buf = [0; 1];
-                io.read_exact(&mut buf).err_into::<Error>().await?;
-
-                let mut buf = vec![0; buf[0].into()];
+        };
 
-                io.read_exact(&mut buf).await?;
-                let domain = String::from_utf8(buf).map_err(Into::<Error>::into)?;
-                IpOrDomain::Domain(domain)
+        let target_endpoint = match read_socks_addr(&mut io).await {
+            Ok(endpoint) => endpoint,
+            Err(err) => {
+                if let Some(SocksAddrError::UnsupportedAddressType(_)) =
+                    err.downcast_ref::<SocksAddrError>()
+                {
+                    write_reply(&mut io, Socks5Reply::from_error(&err), unspecified_addr()).await?;
+                }
+                return Err(err);
             }
-            4 => {
-                let mut buf = [0; 16];
-                io.read_exact(&mut buf).err_into::<Error>().await?;
-                IpAddr::from(buf).into()
-            }
-            _ => return Err(Socks5Error::UnsupportedAddressType.into()),
         };
 
-        let mut buf = [0; 2];
-        io.read_exact(&mut buf).err_into::<Error>().await?;
-
-        let port = u16::from_be_bytes(buf);
         Ok(Socks5MidHandshake {
             io,
-            target_endpoint: match ip_or_domain {
-                IpOrDomain::Ip(ip) => Endpoint::new_from_addr(SocketAddr::new(ip, port)),
-                IpOrDomain::Domain(d) => Endpoint::new_from_hostname(&d, port),
+            command,
+            target_endpoint,
+            metadata: ClientMetadata {
+                user,
+                ..Default::default()
             },
         })
     }
@@ -154,23 +207,171 @@ impl<T: AsyncRead + AsyncWrite + Send + Unpin + 'static> Acceptor<Socks5MidHands
 
 pub struct Socks5MidHandshake<T: AsyncRead + AsyncWrite + Send + Unpin + 'static> {
     io: T,
+    command: Socks5Command,
     target_endpoint: Endpoint,
+    metadata: ClientMetadata,
 }
 
 async fn finalize<T: AsyncRead + AsyncWrite + Send + Unpin + 'static>(
     mut acceptor: Socks5MidHandshake<T>,
+    reply: Socks5Reply,
+    bound_addr: SocketAddr,
 ) -> Result<T> {
-    let buf = [5, 0, 0, 1, 0, 0, 0, 0, 0, 0];
-    acceptor.io.write_all(&buf).err_into::<Error>().await?;
+    write_reply(&mut acceptor.io, reply, bound_addr).await?;
     Ok(acceptor.io)
 }
 
+fn unspecified_addr() -> SocketAddr {
+    SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), 0)
+}
+
 impl<I: AsyncRead + AsyncWrite + Send + Unpin + 'static> Socks5MidHandshake<I> {
-    pub fn target_endpoint(&self) -> &Endpoint {
+    pub fn command(&self) -> Socks5Command {
+        self.command
+    }
+
+    pub fn fail_with(self, reply: Socks5Reply) -> BoxFuture<'static, Result<()>> {
+        finalize(self, reply, unspecified_addr())
+            .map_ok(|_| ())
+            .boxed()
+    }
+}
+
+#[async_trait]
+impl<I: AsyncRead + AsyncWrite + Send + Unpin + 'static> MidHandshake<I> for Socks5MidHandshake<I> {
+    /// For `Bind`, the target endpoint is the address of the peer the client
+    /// expects to connect back. For `UdpAssociate`, it is the address the
+    /// client expects to send datagrams from, which is usually left
+    /// unspecified.
+    fn target_endpoint(&self) -> &Endpoint {
         &self.target_endpoint
     }
 
-    pub fn finalize(self) -> BoxFuture<'static, Result<I>> {
-        finalize(self).boxed()
+    fn metadata(&self) -> &ClientMetadata {
+        &self.metadata
+    }
+
+    /// Only `Connect` is finalized here, `Bind` and `UdpAssociate` have their
+    /// own flows.
+    async fn finalize(self, bound_addr: Option<SocketAddr>) -> Result<I> {
+        if self.command != Socks5Command::Connect {
+            self.fail_with(Socks5Reply::CommandNotSupported).await?;
+            return Err(Socks5Error::UnsupportedCommand.into());
+        }
+
+        finalize(
+            self,
+            Socks5Reply::Succeeded,
+            bound_addr.unwrap_or_else(unspecified_addr),
+        )
+        .await
+    }
+
+    async fn fail(self, err: &Error) -> Result<()> {
+        self.fail_with(Socks5Reply::from_error(err)).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        acceptor::{relay, StaticAuthenticator},
+        connector::mock::MockConnector,
+        io::mock::MockStream,
+    };
+    use futures::executor::block_on;
+
+    const CONNECT_REQUEST: [u8; 10] = [5, 1, 0, 1, 127, 0, 0, 1, 0, 80];
+
+    fn authenticator() -> Arc<dyn Authenticator> {
+        let mut authenticator = StaticAuthenticator::new();
+        authenticator.insert("user", "pass");
+        Arc::new(authenticator)
+    }
+
+    fn auth_request(username: &str, password: &str) -> Vec<u8> {
+        let mut buf = vec![5, 1, 2, 1, username.len() as u8];
+        buf.extend_from_slice(username.as_bytes());
+        buf.push(password.len() as u8);
+        buf.extend_from_slice(password.as_bytes());
+        buf
+    }
+
+    #[test]
+    fn accepts_valid_credential() {
+        let mut input = auth_request("user", "pass");
+        input.extend_from_slice(&CONNECT_REQUEST);
+        let io = MockStream::new(&input);
+        let output = io.output();
+
+        let mid =
+            block_on(Socks5Acceptor::with_authenticator(io, authenticator()).handshake()).unwrap();
+        assert_eq!(
+            mid.metadata().user.as_ref().map(String::as_str),
+            Some("user")
+        );
+        assert_eq!(*output.lock().unwrap(), [5, 2, 1, 0]);
+    }
+
+    #[test]
+    fn rejects_invalid_credential() {
+        let io = MockStream::new(&auth_request("user", "wrong"));
+        let output = io.output();
+
+        assert!(
+            block_on(Socks5Acceptor::with_authenticator(io, authenticator()).handshake()).is_err()
+        );
+        assert_eq!(*output.lock().unwrap(), [5, 2, 1, 1]);
+    }
+
+    #[test]
+    fn requires_authentication_method() {
+        let io = MockStream::new(&[5, 1, 0]);
+        let output = io.output();
+
+        assert!(
+            block_on(Socks5Acceptor::with_authenticator(io, authenticator()).handshake()).is_err()
+        );
+        assert_eq!(*output.lock().unwrap(), [5, 0xff]);
+    }
+
+    #[test]
+    fn replies_to_unsupported_address_type() {
+        let io = MockStream::new(&[5, 1, 0, 5, 1, 0, 2, 0, 0]);
+        let output = io.output();
+
+        assert!(block_on(Socks5Acceptor::new(io).handshake()).is_err());
+        assert_eq!(
+            *output.lock().unwrap(),
+            [5, 0, 5, 8, 0, 1, 0, 0, 0, 0, 0, 0]
+        );
+    }
+
+    #[test]
+    fn accepts_without_authentication() {
+        let mut input = vec![5, 1, 0];
+        input.extend_from_slice(&CONNECT_REQUEST);
+        let io = MockStream::new(&input);
+
+        let mid = block_on(Socks5Acceptor::new(io).handshake()).unwrap();
+        assert!(mid.metadata().user.is_none());
+        assert_eq!(mid.command(), Socks5Command::Connect);
+    }
+
+    #[test]
+    fn replies_with_bound_address() {
+        let mut input = vec![5, 1, 0];
+        input.extend_from_slice(&CONNECT_REQUEST);
+        let io = MockStream::new(&input);
+        let output = io.output();
+        let bound_addr = "192.0.2.7:4321".parse().unwrap();
+        let connector = MockConnector::with_local_addr(MockStream::new(b""), bound_addr);
+
+        block_on(relay(Socks5Acceptor::new(io), connector)).unwrap();
+        assert_eq!(
+            *output.lock().unwrap(),
+            [5, 0, 5, 0, 0, 1, 192, 0, 2, 7, 0x10, 0xe1]
+        );
     }
 }
@@ -0,0 +1,123 @@
+// MIT License
+
+// Copyright (c) 2019 Zhuhao Wang
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use crate::core::{encode_socks_addr, Endpoint, Error, Result, Socks5Reply, SocksAddrError};
+use futures::{
+    future::TryFutureExt,
+    io::{AsyncWrite, AsyncWriteExt},
+};
+use std::{io, net::SocketAddr};
+use trust_dns_resolver::error::ResolveError;
+
+impl Socks5Reply {
+    /// Picks the reply that best describes why connecting to the target
+    /// failed.
+    pub fn from_error(err: &Error) -> Self {
+        if err.is::<ResolveError>() {
+            return Socks5Reply::HostUnreachable;
+        }
+
+        if let Some(SocksAddrError::UnsupportedAddressType(_)) =
+            err.downcast_ref::<SocksAddrError>()
+        {
+            return Socks5Reply::AddressTypeNotSupported;
+        }
+
+        match err.downcast_ref::<io::Error>() {
+            Some(err) => Self::from_io_error(err),
+            None => Socks5Reply::GeneralFailure,
+        }
+    }
+
+    fn from_io_error(err: &io::Error) -> Self {
+        // The resolver reports its failures as `io::Error`.
+        if err
+            .get_ref()
+            .map(|e| e.is::<ResolveError>())
+            .unwrap_or(false)
+        {
+            return Socks5Reply::HostUnreachable;
+        }
+
+        match err.kind() {
+            io::ErrorKind::ConnectionRefused => Socks5Reply::ConnectionRefused,
+            io::ErrorKind::TimedOut => Socks5Reply::TtlExpired,
+            io::ErrorKind::PermissionDenied => Socks5Reply::NotAllowedByRuleset,
+            io::ErrorKind::AddrNotAvailable => Socks5Reply::HostUnreachable,
+            _ => Socks5Reply::GeneralFailure,
+        }
+    }
+}
+
+pub(super) async fn write_reply<T: AsyncWrite + Unpin>(
+    io: &mut T,
+    reply: Socks5Reply,
+    bound_addr: SocketAddr,
+) -> Result<()> {
+    let mut buf = vec![5, reply.code(), 0];
+    encode_socks_addr(&Endpoint::new_from_addr(bound_addr), &mut buf)?;
+    io.write_all(&buf).err_into::<Error>().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::executor::block_on;
+
+    #[test]
+    fn maps_errors_to_replies() {
+        let reply = |err: Error| Socks5Reply::from_error(&err);
+
+        assert_eq!(
+            reply(io::Error::from(io::ErrorKind::ConnectionRefused).into()),
+            Socks5Reply::ConnectionRefused
+        );
+        assert_eq!(
+            reply(io::Error::from(io::ErrorKind::TimedOut).into()),
+            Socks5Reply::TtlExpired
+        );
+        assert_eq!(
+            reply(io::Error::from(io::ErrorKind::PermissionDenied).into()),
+            Socks5Reply::NotAllowedByRuleset
+        );
+        assert_eq!(
+            reply(SocksAddrError::UnsupportedAddressType(2).into()),
+            Socks5Reply::AddressTypeNotSupported
+        );
+        assert_eq!(
+            reply(io::Error::from(io::ErrorKind::Other).into()),
+            Socks5Reply::GeneralFailure
+        );
+    }
+
+    #[test]
+    fn writes_reply_with_bound_address() {
+        let mut buf = Vec::new();
+        block_on(write_reply(
+            &mut buf,
+            Socks5Reply::HostUnreachable,
+            "10.0.0.1:1080".parse().unwrap(),
+        ))
+        .unwrap();
+        assert_eq!(buf, [5, 4, 0, 1, 10, 0, 0, 1, 0x04, 0x38]);
+    }
+}
@@ -0,0 +1,327 @@
+// MIT License
+
+// Copyright (c) 2019 Zhuhao Wang
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use super::{reply::write_reply, Socks5Command, Socks5Error, Socks5MidHandshake, Socks5Reply};
+use crate::{
+    core::{decode_socks_addr, encode_socks_addr, Endpoint, Error, Result, SocksAddrError},
+    resolver::Resolver,
+};
+use futures::{
+    channel::mpsc,
+    future::{self, FutureExt},
+    io::{AsyncRead, AsyncReadExt, AsyncWrite},
+    stream::StreamExt,
+};
+use std::{
+    collections::HashMap,
+    io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+};
+use tokio::net::UdpSocket;
+
+/// How many datagrams to a host name are kept while it is being resolved.
+const MAX_QUEUED_DATAGRAMS: usize = 16;
+/// How many resolved host names are remembered, and how many lookups may be
+/// in flight, since the names are chosen by the client.
+const MAX_RESOLVED_HOSTS: usize = 256;
+const MAX_RESOLVING_HOSTS: usize = 64;
+
+/// Parses a datagram sent by the client to the relay, returning its
+/// destination and payload. Fragmented datagrams are rejected.
+pub fn decode_udp_packet(buf: &[u8]) -> Result<(Endpoint, &[u8])> {
+    if buf.len() < 3 {
+        return Err(SocksAddrError::Truncated.into());
+    }
+
+    if buf[2] != 0 {
+        return Err(Socks5Error::FragmentedDatagram.into());
+    }
+
+    let (endpoint, len) = decode_socks_addr(&buf[3..])?;
+    Ok((endpoint, &buf[3 + len..]))
+}
+
+/// Builds the datagram sent back to the client for a payload received from
+/// `endpoint`.
+pub fn encode_udp_packet(endpoint: &Endpoint, payload: &[u8]) -> Result<Vec<u8>> {
+    let mut buf = vec![0, 0, 0];
+    encode_socks_addr(endpoint, &mut buf)?;
+    buf.extend_from_slice(payload);
+    Ok(buf)
+}
+
+impl<I: AsyncRead + AsyncWrite + Send + Unpin + 'static> Socks5MidHandshake<I> {
+    /// Binds the relay socket on `relay_ip` and replies with its address.
+    ///
+    /// `relay_ip` must be reachable by the client, usually it is the local
+    /// address of the control connection.
+    pub async fn associate_udp(mut self, relay_ip: IpAddr) -> Result<Socks5UdpAssociation<I>> {
+        if self.command != Socks5Command::UdpAssociate {
+            self.fail_with(Socks5Reply::CommandNotSupported).await?;
+            return Err(Socks5Error::UnsupportedCommand.into());
+        }
+
+        let socket = match UdpSocket::bind(&SocketAddr::new(relay_ip, 0)) {
+            Ok(socket) => socket,
+            Err(err) => {
+                let err: Error = err.into();
+                self.fail_with(Socks5Reply::from_error(&err)).await?;
+                return Err(err);
+            }
+        };
+
+        write_reply(&mut self.io, Socks5Reply::Succeeded, socket.local_addr()?).await?;
+
+        let expected_client = match self.target_endpoint {
+            Endpoint::Ip(addr) if !addr.ip().is_unspecified() => Some(addr),
+            _ => None,
+        };
+
+        Ok(Socks5UdpAssociation {
+            io: self.io,
+            socket,
+            expected_client,
+        })
+    }
+}
+
+enum Event {
+    Control(io::Result<usize>),
+    Client(io::Result<(usize, SocketAddr)>),
+    RemoteV4(io::Result<(usize, SocketAddr)>),
+    RemoteV6(io::Result<(usize, SocketAddr)>),
+    Resolved(Option<(String, Result<Vec<SocketAddr>>)>),
+}
+
+/// Sends `payload` to the first of `addrs` that the relay can reach.
+async fn send_to_any(
+    remote_v4: &mut UdpSocket,
+    mut remote_v6: Option<&mut UdpSocket>,
+    addrs: &[SocketAddr],
+    payload: &[u8],
+) {
+    for addr in addrs {
+        let result = match (addr, remote_v6.as_mut()) {
+            (SocketAddr::V4(_), _) => remote_v4.send_to(payload, addr).await,
+            (SocketAddr::V6(_), Some(remote_v6)) => remote_v6.send_to(payload, addr).await,
+            (SocketAddr::V6(_), None) => continue,
+        };
+        if result.is_ok() {
+            return;
+        }
+    }
+}
+
+pub struct Socks5UdpAssociation<T: AsyncRead + AsyncWrite + Send + Unpin + 'static> {
+    io: T,
+    socket: UdpSocket,
+    expected_client: Option<SocketAddr>,
+}
+
+impl<T: AsyncRead + AsyncWrite + Send + Unpin + 'static> Socks5UdpAssociation<T> {
+    pub fn relay_addr(&self) -> Result<SocketAddr> {
+        self.socket.local_addr().map_err(Into::into)
+    }
+
+    /// Relays datagrams between the client and remote hosts until the
+    /// control connection is closed.
+    pub async fn relay<R: Resolver + Clone + Send + 'static>(self, resolver: R) -> Result<()> {
+        let Socks5UdpAssociation {
+            mut io,
+            mut socket,
+            expected_client,
+        } = self;
+
+        let mut remote_v4 = UdpSocket::bind(&SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), 0))?;
+        // The host may not have IPv6 available at all.
+        let mut remote_v6 = UdpSocket::bind(&SocketAddr::new(Ipv6Addr::UNSPECIFIED.into(), 0)).ok();
+
+        let mut client = None;
+        let mut control_buf = [0; 64];
+        let mut client_buf = vec![0; 65536];
+        let mut v4_buf = vec![0; 65536];
+        let mut v6_buf = vec![0; 65536];
+
+        // Host names are resolved in their own tasks so a slow lookup doesn't
+        // hold up the other datagrams. Up to `MAX_RESOLVED_HOSTS` results are
+        // kept, and datagrams sent while a lookup is in flight wait for it.
+        let (resolved_sender, mut resolved_receiver) = mpsc::unbounded();
+        let mut resolved: HashMap<String, Vec<SocketAddr>> = HashMap::new();
+        let mut resolving: HashMap<String, Vec<Vec<u8>>> = HashMap::new();
+
+        loop {
+            let event = {
+                let mut events = vec![
+                    io.read(&mut control_buf).map(Event::Control).boxed(),
+                    socket.recv_from(&mut client_buf).map(Event::Client).boxed(),
+                    remote_v4
+                        .recv_from(&mut v4_buf)
+                        .map(Event::RemoteV4)
+                        .boxed(),
+                    resolved_receiver.next().map(Event::Resolved).boxed(),
+                ];
+                if let Some(ref mut remote_v6) = remote_v6 {
+                    events.push(
+                        remote_v6
+                            .recv_from(&mut v6_buf)
+                            .map(Event::RemoteV6)
+                            .boxed(),
+                    );
+                }
+                future::select_all(events).await.0
+            };
+
+            match event {
+                // The association terminates with the control connection.
+                Event::Control(Ok(0)) | Event::Control(Err(_)) => return Ok(()),
+                Event::Control(Ok(_)) => continue,
+                Event::Client(result) => {
+                    // An error here is about a single datagram, such as an
+                    // ICMP port unreachable for an earlier reply.
+                    let (len, from) = match result {
+                        Ok(received) => received,
+                        Err(_) => continue,
+                    };
+
+                    if let Some(expected) = expected_client {
+                        if expected.ip() != from.ip()
+                            || (expected.port() != 0 && expected.port() != from.port())
+                        {
+                            continue;
+                        }
+                    }
+
+                    match client {
+                        Some(client) if client != from => continue,
+                        _ => client = Some(from),
+                    }
+
+                    let (endpoint, payload) = match decode_udp_packet(&client_buf[..len]) {
+                        Ok(packet) => packet,
+                        Err(_) => continue,
+                    };
+
+                    // Like any UDP sender, drop the datagram if it can't be delivered.
+                    let key = match endpoint {
+                        Endpoint::Ip(addr) => {
+                            send_to_any(&mut remote_v4, remote_v6.as_mut(), &[addr], payload).await;
+                            continue;
+                        }
+                        Endpoint::HostName(..) => endpoint.to_string(),
+                    };
+
+                    if let Some(addrs) = resolved.get(&key) {
+                        send_to_any(&mut remote_v4, remote_v6.as_mut(), addrs, payload).await;
+                    } else if let Some(queued) = resolving.get_mut(&key) {
+                        if queued.len() < MAX_QUEUED_DATAGRAMS {
+                            queued.push(payload.to_vec());
+                        }
+                    } else if resolving.len() < MAX_RESOLVING_HOSTS {
+                        resolving.insert(key.clone(), vec![payload.to_vec()]);
+
+                        let resolver = resolver.clone();
+                        let resolved_sender = resolved_sender.clone();
+                        tokio::spawn(async move {
+                            let result = resolver.resolve_endpoint(&endpoint).await;
+                            let _ = resolved_sender.unbounded_send((key, result));
+                        });
+                    }
+                }
+                Event::Resolved(None) => unreachable!("the relay holds a sender"),
+                Event::Resolved(Some((key, result))) => {
+                    let queued = resolving.remove(&key).unwrap_or_default();
+                    let addrs = match result {
+                        Ok(addrs) => addrs,
+                        // Let the next datagram try again.
+                        Err(_) => continue,
+                    };
+
+                    for payload in queued {
+                        send_to_any(&mut remote_v4, remote_v6.as_mut(), &addrs, &payload).await;
+                    }
+                    if resolved.len() >= MAX_RESOLVED_HOSTS {
+                        // Any entry will do, it is looked up again if needed.
+                        if let Some(evicted) = resolved.keys().next().cloned() {
+                            resolved.remove(&evicted);
+                        }
+                    }
+                    resolved.insert(key, addrs);
+                }
+                Event::RemoteV4(result) | Event::RemoteV6(result) => {
+                    let (len, from) = match result {
+                        Ok(received) => received,
+                        Err(ref err) if err.kind() == io::ErrorKind::ConnectionReset => continue,
+                        Err(err) => return Err(err.into()),
+                    };
+
+                    let client = match client {
+                        Some(client) => client,
+                        None => continue,
+                    };
+
+                    let buf = if from.is_ipv4() { &v4_buf } else { &v6_buf };
+                    let packet = encode_udp_packet(&Endpoint::new_from_addr(from), &buf[..len])?;
+                    socket.send_to(&packet, &client).await?;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_domain_datagram() {
+        let mut buf = vec![0, 0, 0, 3, 11];
+        buf.extend_from_slice(b"example.com");
+        buf.extend_from_slice(&[0, 53]);
+        buf.extend_from_slice(b"payload");
+
+        let (endpoint, payload) = decode_udp_packet(&buf).unwrap();
+        assert_eq!(endpoint.to_string(), "example.com:53");
+        assert_eq!(payload, b"payload");
+    }
+
+    #[test]
+    fn rejects_fragmented_datagram() {
+        assert!(decode_udp_packet(&[0, 0, 1, 1, 127, 0, 0, 1, 0, 53]).is_err());
+    }
+
+    #[test]
+    fn rejects_truncated_datagram() {
+        assert!(decode_udp_packet(&[0, 0]).is_err());
+        assert!(decode_udp_packet(&[0, 0, 0, 1, 127, 0]).is_err());
+    }
+
+    #[test]
+    fn encodes_then_decodes() {
+        let from = Endpoint::new_from_addr("[::1]:8053".parse().unwrap());
+        let buf = encode_udp_packet(&from, b"reply").unwrap();
+        assert_eq!(&buf[..4], &[0, 0, 0, 4]);
+
+        let (endpoint, payload) = decode_udp_packet(&buf).unwrap();
+        assert_eq!(endpoint.to_string(), "[::1]:8053");
+        assert_eq!(payload, b"reply");
+    }
+}
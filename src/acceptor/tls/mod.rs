@@ -0,0 +1,382 @@
+// MIT License
+
+// Copyright (c) 2019 Zhuhao Wang
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use crate::{
+    acceptor::{Acceptor, ClientMetadata, MidHandshake},
+    core::{Endpoint, Error, Result},
+};
+use async_trait::async_trait;
+use futures::io::{AsyncRead, AsyncWrite};
+use futures_tokio_compat::Compat;
+use std::{collections::HashMap, marker::PhantomData, net::SocketAddr, sync::Arc};
+use tokio_rustls::{
+    rustls::{
+        sign::{self, CertifiedKey},
+        Certificate, ClientHello, NoClientAuth, PrivateKey, ResolvesServerCert, ServerConfig,
+        Session,
+    },
+    server,
+};
+
+/// The stream handed to the inner acceptor, with the `futures` I/O traits.
+pub type TlsStream<T> = Compat<server::TlsStream<Compat<T>>>;
+
+#[derive(Debug)]
+pub enum TlsAcceptorError {
+    NoCertificate,
+    InvalidPrivateKey,
+}
+
+impl std::fmt::Display for TlsAcceptorError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::result::Result<(), std::fmt::Error> {
+        write!(f, "{:#?}", self)
+    }
+}
+
+impl std::error::Error for TlsAcceptorError {}
+
+/// Picks the certificate by the server name in SNI, falling back to the
+/// default one.
+struct SniCertResolver {
+    default: Option<CertifiedKey>,
+    by_name: HashMap<String, CertifiedKey>,
+}
+
+impl ResolvesServerCert for SniCertResolver {
+    fn resolve(&self, client_hello: ClientHello) -> Option<CertifiedKey> {
+        client_hello
+            .server_name()
+            .and_then(|name| {
+                let name: &str = name.into();
+                self.by_name.get(&name.to_ascii_lowercase())
+            })
+            .or_else(|| self.default.as_ref())
+            .cloned()
+    }
+}
+
+fn certified_key(chain: Vec<Vec<u8>>, key: Vec<u8>) -> Result<CertifiedKey> {
+    let key = sign::any_supported_type(&PrivateKey(key))
+        .map_err(|_| TlsAcceptorError::InvalidPrivateKey)?;
+    Ok(CertifiedKey::new(
+        chain.into_iter().map(Certificate).collect(),
+        Arc::new(key),
+    ))
+}
+
+/// The server side TLS configuration, shared by all the connections of a
+/// listener.
+#[derive(Clone)]
+pub struct TlsServerConfig {
+    config: Arc<ServerConfig>,
+}
+
+impl TlsServerConfig {
+    pub fn builder() -> TlsServerConfigBuilder {
+        TlsServerConfigBuilder {
+            default: None,
+            by_name: Vec::new(),
+            alpn_protocols: Vec::new(),
+        }
+    }
+}
+
+pub struct TlsServerConfigBuilder {
+    default: Option<(Vec<Vec<u8>>, Vec<u8>)>,
+    by_name: Vec<(String, Vec<Vec<u8>>, Vec<u8>)>,
+    alpn_protocols: Vec<Vec<u8>>,
+}
+
+impl TlsServerConfigBuilder {
+    /// Serves the DER encoded certificate `chain` and PKCS#8 or RSA `key`
+    /// to clients that send no server name, or one without a certificate of
+    /// its own.
+    pub fn certificate(mut self, chain: Vec<Vec<u8>>, key: Vec<u8>) -> Self {
+        self.default = Some((chain, key));
+        self
+    }
+
+    /// Serves `chain` and `key` to clients asking for `server_name`.
+    pub fn sni_certificate(mut self, server_name: &str, chain: Vec<Vec<u8>>, key: Vec<u8>) -> Self {
+        self.by_name
+            .push((server_name.to_ascii_lowercase(), chain, key));
+        self
+    }
+
+    /// Accepts `protocols` with ALPN, in order of preference.
+    pub fn alpn_protocols(mut self, protocols: &[&str]) -> Self {
+        self.alpn_protocols = protocols.iter().map(|p| p.as_bytes().to_vec()).collect();
+        self
+    }
+
+    pub fn build(self) -> Result<TlsServerConfig> {
+        if self.default.is_none() && self.by_name.is_empty() {
+            return Err(TlsAcceptorError::NoCertificate.into());
+        }
+
+        let default = match self.default {
+            Some((chain, key)) => Some(certified_key(chain, key)?),
+            None => None,
+        };
+        let mut by_name = HashMap::new();
+        for (name, chain, key) in self.by_name {
+            by_name.insert(name, certified_key(chain, key)?);
+        }
+
+        let mut config = ServerConfig::new(NoClientAuth::new());
+        config.cert_resolver = Arc::new(SniCertResolver { default, by_name });
+        config.set_protocols(&self.alpn_protocols);
+
+        Ok(TlsServerConfig {
+            config: Arc::new(config),
+        })
+    }
+}
+
+/// Terminates TLS and hands the decrypted stream to the acceptor created by
+/// `make_inner`, e.g., to serve an HTTPS proxy with `HttpConnectAcceptor`.
+pub struct TlsAcceptor<T, F> {
+    io: T,
+    config: TlsServerConfig,
+    make_inner: F,
+}
+
+impl<T: AsyncRead + AsyncWrite + Send + Unpin + 'static, F> TlsAcceptor<T, F> {
+    pub fn new(io: T, config: TlsServerConfig, make_inner: F) -> Self {
+        TlsAcceptor {
+            io,
+            config,
+            make_inner,
+        }
+    }
+}
+
+#[async_trait]
+impl<T, F, A, M, I> Acceptor<TlsMidHandshake<M, I>> for TlsAcceptor<T, F>
+where
+    T: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+    F: FnOnce(TlsStream<T>) -> A + Send + 'static,
+    A: Acceptor<M> + Send + 'static,
+    M: MidHandshake<I> + 'static,
+    I: 'static,
+{
+    async fn handshake(self) -> Result<TlsMidHandshake<M, I>> {
+        let stream = tokio_rustls::TlsAcceptor::from(self.config.config)
+            .accept(Compat::new(self.io))
+            .await?;
+
+        let (sni, alpn) = {
+            let (_, session) = stream.get_ref();
+            (
+                session.get_sni_hostname().map(ToOwned::to_owned),
+                session.get_alpn_protocol().map(ToOwned::to_owned),
+            )
+        };
+
+        let inner = (self.make_inner)(Compat::new(stream)).handshake().await?;
+        let metadata = ClientMetadata {
+            sni,
+            alpn,
+            ..inner.metadata().clone()
+        };
+
+        Ok(TlsMidHandshake {
+            inner,
+            metadata,
+            _marker: PhantomData,
+        })
+    }
+}
+
+pub struct TlsMidHandshake<M, I> {
+    inner: M,
+    metadata: ClientMetadata,
+    _marker: PhantomData<fn() -> I>,
+}
+
+impl<M, I> TlsMidHandshake<M, I> {
+    pub fn into_inner(self) -> M {
+        self.inner
+    }
+}
+
+#[async_trait]
+impl<M, I> MidHandshake<I> for TlsMidHandshake<M, I>
+where
+    M: MidHandshake<I>,
+    I: 'static,
+{
+    fn target_endpoint(&self) -> &Endpoint {
+        self.inner.target_endpoint()
+    }
+
+    /// The metadata of the inner acceptor, with `sni` and `alpn` from the
+    /// TLS handshake.
+    fn metadata(&self) -> &ClientMetadata {
+        &self.metadata
+    }
+
+    async fn finalize(self, bound_addr: Option<SocketAddr>) -> Result<I> {
+        self.inner.finalize(bound_addr).await
+    }
+
+    async fn fail(self, err: &Error) -> Result<()> {
+        self.inner.fail(err).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::future;
+    use tokio::{
+        net::{TcpListener, TcpStream},
+        runtime::Runtime,
+    };
+    use tokio_rustls::{
+        rustls::{ClientConfig, RootCertStore, ServerCertVerified, ServerCertVerifier, TLSError},
+        webpki::DNSNameRef,
+    };
+
+    const CERT: &[u8] = include_bytes!("../../../testdata/example.com.cert.der");
+    const KEY: &[u8] = include_bytes!("../../../testdata/example.com.key.der");
+    const OTHER_CERT: &[u8] = include_bytes!("../../../testdata/other.example.cert.der");
+    const OTHER_KEY: &[u8] = include_bytes!("../../../testdata/other.example.key.der");
+
+    struct AcceptAnyCert;
+
+    impl ServerCertVerifier for AcceptAnyCert {
+        fn verify_server_cert(
+            &self,
+            _roots: &RootCertStore,
+            _presented_certs: &[Certificate],
+            _dns_name: DNSNameRef,
+            _ocsp_response: &[u8],
+        ) -> std::result::Result<ServerCertVerified, TLSError> {
+            Ok(ServerCertVerified::assertion())
+        }
+    }
+
+    /// Connects to a server with `config` asking for `server_name`, or for
+    /// no name if it is `None`. Returns the certificate the server sent.
+    fn served_cert(config: TlsServerConfig, server_name: Option<&str>) -> Option<Vec<u8>> {
+        let mut client_config = ClientConfig::new();
+        client_config
+            .dangerous()
+            .set_certificate_verifier(Arc::new(AcceptAnyCert));
+        client_config.enable_sni = server_name.is_some();
+        let server_name = server_name.unwrap_or("example.com").to_owned();
+
+        Runtime::new().unwrap().block_on(async move {
+            let addr: SocketAddr = "127.0.0.1:0".parse().unwrap();
+            let mut listener = TcpListener::bind(&addr).unwrap();
+            let addr = listener.local_addr().unwrap();
+
+            let server = async move {
+                let (stream, _) = listener.accept().await.unwrap();
+                tokio_rustls::TlsAcceptor::from(config.config)
+                    .accept(stream)
+                    .await
+            };
+            let client = async move {
+                let stream = TcpStream::connect(&addr).await?;
+                let dns_name = DNSNameRef::try_from_ascii_str(&server_name).unwrap();
+                tokio_rustls::TlsConnector::from(Arc::new(client_config))
+                    .connect(dns_name, stream)
+                    .await
+            };
+
+            match future::join(server, client).await {
+                (Ok(_), Ok(stream)) => {
+                    let (_, session) = stream.get_ref();
+                    session
+                        .get_peer_certificates()
+                        .and_then(|certs| certs.into_iter().next())
+                        .map(|cert| cert.0)
+                }
+                _ => None,
+            }
+        })
+    }
+
+    fn by_name() -> TlsServerConfigBuilder {
+        TlsServerConfig::builder()
+            .sni_certificate("Example.com", vec![CERT.to_vec()], KEY.to_vec())
+            .sni_certificate(
+                "other.example",
+                vec![OTHER_CERT.to_vec()],
+                OTHER_KEY.to_vec(),
+            )
+    }
+
+    #[test]
+    fn picks_certificate_by_server_name() {
+        let config = by_name().build().unwrap();
+        assert_eq!(
+            served_cert(config.clone(), Some("other.example")),
+            Some(OTHER_CERT.to_vec())
+        );
+        assert_eq!(
+            served_cert(config, Some("EXAMPLE.COM")),
+            Some(CERT.to_vec())
+        );
+    }
+
+    #[test]
+    fn falls_back_to_default_certificate() {
+        let config = by_name()
+            .certificate(vec![OTHER_CERT.to_vec()], OTHER_KEY.to_vec())
+            .build()
+            .unwrap();
+        assert_eq!(
+            served_cert(config.clone(), Some("unknown.example")),
+            Some(OTHER_CERT.to_vec())
+        );
+        assert_eq!(served_cert(config, None), Some(OTHER_CERT.to_vec()));
+    }
+
+    #[test]
+    fn fails_without_matching_certificate() {
+        let config = by_name().build().unwrap();
+        assert_eq!(served_cert(config.clone(), Some("unknown.example")), None);
+        assert_eq!(served_cert(config, None), None);
+    }
+
+    #[test]
+    fn requires_a_certificate() {
+        let err = TlsServerConfig::builder().build().err().unwrap();
+        match err.downcast_ref::<TlsAcceptorError>() {
+            Some(TlsAcceptorError::NoCertificate) => {}
+            _ => panic!("unexpected error {}", err),
+        }
+
+        let err = TlsServerConfig::builder()
+            .certificate(vec![CERT.to_vec()], b"not a key".to_vec())
+            .build()
+            .err()
+            .unwrap();
+        match err.downcast_ref::<TlsAcceptorError>() {
+            Some(TlsAcceptorError::InvalidPrivateKey) => {}
+            _ => panic!("unexpected error {}", err),
+        }
+    }
+}
@@ -0,0 +1,287 @@
+// MIT License
+
+// Copyright (c) 2019 Zhuhao Wang
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use crate::{
+    acceptor::{Acceptor, ClientMetadata, MidHandshake},
+    connector::Connector,
+    core::{read_socks_addr, Endpoint, Error, Result},
+    io::{forward, Prefixed},
+    trojan::{CMD_CONNECT, HASH_LEN},
+};
+use async_trait::async_trait;
+use futures::{
+    future::TryFutureExt,
+    io::{AsyncRead, AsyncReadExt, AsyncWrite},
+};
+use std::{collections::HashSet, marker::PhantomData, net::SocketAddr, sync::Arc};
+
+#[derive(Debug)]
+pub enum TrojanAcceptorError {
+    /// The client didn't speak Trojan with a known password, so the
+    /// connection was relayed to the fallback server until it closed.
+    FellBack,
+    UnsupportedCommand(u8),
+    InvalidRequest,
+}
+
+impl std::fmt::Display for TrojanAcceptorError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::result::Result<(), std::fmt::Error> {
+        write!(f, "{:#?}", self)
+    }
+}
+
+impl std::error::Error for TrojanAcceptorError {}
+
+/// Accepts Trojan clients, usually on a stream from `TlsAcceptor`.
+///
+/// Anything else, such as a browser or an active prober, is relayed to
+/// `fallback_endpoint` through `fallback` so the server looks like an
+/// ordinary web server.
+pub struct TrojanAcceptor<T, C, S>
+where
+    T: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+    C: Connector<S> + Send + 'static,
+    S: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+{
+    io: T,
+    password_hashes: Arc<HashSet<String>>,
+    fallback: C,
+    fallback_endpoint: Endpoint,
+    _marker: PhantomData<fn() -> S>,
+}
+
+impl<T, C, S> TrojanAcceptor<T, C, S>
+where
+    T: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+    C: Connector<S> + Send + 'static,
+    S: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+{
+    /// `password_hashes` are computed with `trojan::password_hash`.
+    pub fn new(
+        io: T,
+        password_hashes: Arc<HashSet<String>>,
+        fallback: C,
+        fallback_endpoint: Endpoint,
+    ) -> Self {
+        TrojanAcceptor {
+            io,
+            password_hashes,
+            fallback,
+            fallback_endpoint,
+            _marker: PhantomData,
+        }
+    }
+}
+
+/// Whether `buf` may still be the start of `HASH CRLF`.
+fn is_hash_prefix(buf: &[u8]) -> bool {
+    buf.iter().enumerate().all(|(i, b)| match i {
+        i if i < HASH_LEN => b.is_ascii_digit() || (b'a'..=b'f').contains(b),
+        i if i == HASH_LEN => *b == b'\r',
+        _ => *b == b'\n',
+    })
+}
+
+#[async_trait]
+impl<T, C, S> Acceptor<TrojanMidHandshake<T>> for TrojanAcceptor<T, C, S>
+where
+    T: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+    C: Connector<S> + Send + 'static,
+    S: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+{
+    async fn handshake(self) -> Result<TrojanMidHandshake<T>> {
+        let mut io = self.io;
+
+        // Read no further than the hash so that nothing is lost if the
+        // client turns out to be something else. A client closing before
+        // sending a whole hash isn't Trojan either and falls back too, as
+        // probes that send a few bytes must not tell the server apart.
+        let mut buf = vec![0; HASH_LEN + 2];
+        let mut len = 0;
+        while len < buf.len() && is_hash_prefix(&buf[..len]) {
+            let read = io.read(&mut buf[len..]).err_into::<Error>().await?;
+            if read == 0 {
+                break;
+            }
+            len += read;
+        }
+        buf.truncate(len);
+
+        let authenticated = buf.len() == HASH_LEN + 2
+            && is_hash_prefix(&buf)
+            && std::str::from_utf8(&buf[..HASH_LEN])
+                .map(|hash| self.password_hashes.contains(hash))
+                .unwrap_or(false);
+
+        if !authenticated {
+            let stream = self.fallback.connect(&self.fallback_endpoint).await?;
+            forward(Prefixed::new(io, buf), stream).await?;
+            return Err(TrojanAcceptorError::FellBack.into());
+        }
+
+        let mut buf = [0; 1];
+        io.read_exact(&mut buf).err_into::<Error>().await?;
+        if buf[0] != CMD_CONNECT {
+            return Err(TrojanAcceptorError::UnsupportedCommand(buf[0]).into());
+        }
+
+        let target_endpoint = read_socks_addr(&mut io).await?;
+
+        let mut buf = [0; 2];
+        io.read_exact(&mut buf).err_into::<Error>().await?;
+        if &buf != b"\r\n" {
+            return Err(TrojanAcceptorError::InvalidRequest.into());
+        }
+
+        Ok(TrojanMidHandshake {
+            io,
+            target_endpoint,
+            metadata: ClientMetadata::default(),
+        })
+    }
+}
+
+pub struct TrojanMidHandshake<T: AsyncRead + AsyncWrite + Send + Unpin + 'static> {
+    io: T,
+    target_endpoint: Endpoint,
+    metadata: ClientMetadata,
+}
+
+#[async_trait]
+impl<T: AsyncRead + AsyncWrite + Send + Unpin + 'static> MidHandshake<T> for TrojanMidHandshake<T> {
+    fn target_endpoint(&self) -> &Endpoint {
+        &self.target_endpoint
+    }
+
+    fn metadata(&self) -> &ClientMetadata {
+        &self.metadata
+    }
+
+    /// Trojan has no reply, the response simply starts with the data from
+    /// the target.
+    async fn finalize(self, _bound_addr: Option<SocketAddr>) -> Result<T> {
+        Ok(self.io)
+    }
+
+    /// The connection is closed, which is all the client could learn.
+    async fn fail(self, _err: &Error) -> Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        connector::{mock::MockConnector, TrojanConnector},
+        io::mock::MockStream,
+        trojan::password_hash,
+    };
+    use futures::executor::block_on;
+
+    fn request(password: &str) -> Vec<u8> {
+        let io = MockStream::new(&[]);
+        let output = io.output();
+        let connector = TrojanConnector::new(
+            MockConnector::new(io),
+            Endpoint::new_from_hostname("trojan.example", 443),
+            password,
+        );
+        block_on(connector.connect(&Endpoint::new_from_hostname("example.com", 443))).unwrap();
+
+        let mut buf = Vec::new();
+        buf.extend_from_slice(&output.lock().unwrap());
+        buf.extend_from_slice(b"payload");
+        buf
+    }
+
+    fn fall_back(input: &[u8]) -> Vec<u8> {
+        let fallback = MockStream::new(&[]);
+        let output = fallback.output();
+        let hashes = Arc::new(vec![password_hash("password")].into_iter().collect());
+        let acceptor = TrojanAcceptor::new(
+            MockStream::new(input),
+            hashes,
+            MockConnector::new(fallback),
+            Endpoint::new_from_hostname("localhost", 80),
+        );
+
+        let err = block_on(acceptor.handshake()).err().unwrap();
+        match err.downcast_ref::<TrojanAcceptorError>() {
+            Some(TrojanAcceptorError::FellBack) => {}
+            _ => panic!("unexpected error {}", err),
+        }
+
+        let mut buf = Vec::new();
+        buf.extend_from_slice(&output.lock().unwrap());
+        buf
+    }
+
+    #[test]
+    fn checks_hash_prefix() {
+        let hash = password_hash("password");
+        assert!(is_hash_prefix(b""));
+        assert!(is_hash_prefix(&hash.as_bytes()[..10]));
+        assert!(is_hash_prefix(format!("{}\r\n", hash).as_bytes()));
+        assert!(!is_hash_prefix(b"GET / HTTP/1.1"));
+        assert!(!is_hash_prefix(format!("{}\n", hash).as_bytes()));
+    }
+
+    #[test]
+    fn accepts_connector_request() {
+        let input = request("password");
+        let hashes = Arc::new(vec![password_hash("password")].into_iter().collect());
+        let acceptor = TrojanAcceptor::new(
+            MockStream::new(&input),
+            hashes,
+            MockConnector::new(MockStream::new(&[])),
+            Endpoint::new_from_hostname("localhost", 80),
+        );
+
+        let mid = block_on(acceptor.handshake()).unwrap();
+        assert_eq!(mid.target_endpoint().to_string(), "example.com:443");
+
+        let mut io = block_on(mid.finalize(None)).unwrap();
+        let mut buf = Vec::new();
+        block_on(io.read_to_end(&mut buf)).unwrap();
+        assert_eq!(buf, b"payload");
+    }
+
+    #[test]
+    fn falls_back_on_unknown_password() {
+        let input = request("wrong");
+        assert_eq!(fall_back(&input), input);
+    }
+
+    #[test]
+    fn falls_back_on_other_protocols() {
+        let input = b"GET / HTTP/1.1\r\nHost: example.com\r\n\r\n";
+        assert_eq!(fall_back(input), &input[..]);
+    }
+
+    #[test]
+    fn falls_back_on_short_probe() {
+        let hash = password_hash("password");
+        assert_eq!(fall_back(b"\x16\x03"), b"\x16\x03");
+        assert_eq!(fall_back(&hash.as_bytes()[..20]), &hash.as_bytes()[..20]);
+    }
+}
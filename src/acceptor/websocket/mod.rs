@@ -0,0 +1,163 @@
+// MIT License
+
+// Copyright (c) 2019 Zhuhao Wang
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use crate::{
+    acceptor::Acceptor,
+    core::{Error, Result},
+    websocket::{accept_key, Role, WebSocketStream},
+};
+use async_trait::async_trait;
+use futures::{
+    channel::oneshot,
+    future::{self, TryFutureExt},
+    io::{AsyncRead, AsyncWrite},
+};
+use futures_tokio_compat::Compat;
+use hyper::{
+    http::{header, Request, Response, StatusCode},
+    server::conn::Http,
+    service,
+    upgrade::{OnUpgrade, Upgraded},
+    Body,
+};
+use std::sync::{Arc, Mutex};
+
+/// The stream handed to the inner acceptor.
+pub type UpgradedStream = WebSocketStream<Compat<Upgraded>>;
+
+/// Accepts a WebSocket upgrade and hands the stream carried in its frames to
+/// the acceptor created by `make_inner`.
+///
+/// Requests that are not an upgrade, or not for `path` if one is set, are
+/// answered with `400 Bad Request`.
+pub struct WebSocketAcceptor<T, F> {
+    io: T,
+    path: Option<String>,
+    make_inner: F,
+}
+
+impl<T: AsyncRead + AsyncWrite + Send + Unpin + 'static, F> WebSocketAcceptor<T, F> {
+    pub fn new(io: T, path: Option<&str>, make_inner: F) -> Self {
+        WebSocketAcceptor {
+            io,
+            path: path.map(ToOwned::to_owned),
+            make_inner,
+        }
+    }
+}
+
+/// Returns the `Sec-WebSocket-Accept` if `req` is a valid upgrade request.
+fn upgrade_accept_key(req: &Request<Body>, path: Option<&str>) -> Option<String> {
+    let headers = req.headers();
+    let has_token = |name: header::HeaderName, token: &str| {
+        headers
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .map(|value| {
+                value
+                    .split(',')
+                    .any(|t| t.trim().eq_ignore_ascii_case(token))
+            })
+            .unwrap_or(false)
+    };
+
+    if !has_token(header::UPGRADE, "websocket") || !has_token(header::CONNECTION, "upgrade") {
+        return None;
+    }
+
+    if path.map(|path| req.uri().path() != path).unwrap_or(false) {
+        return None;
+    }
+
+    headers
+        .get(header::SEC_WEBSOCKET_KEY)
+        .map(|key| accept_key(key.as_bytes()))
+}
+
+async fn upgrade<T: AsyncRead + AsyncWrite + Send + Unpin + 'static>(
+    io: T,
+    path: Option<String>,
+) -> Result<Upgraded> {
+    let (sender, receiver) = oneshot::channel::<OnUpgrade>();
+    let sender = Arc::new(Mutex::new(Some(sender)));
+    let connection = Http::new()
+        .serve_connection(
+            Compat::new(io),
+            service::service_fn(move |req: Request<Body>| {
+                let sender = sender.clone();
+                let accept = upgrade_accept_key(&req, path.as_ref().map(String::as_str));
+                async move {
+                    let accept = match accept {
+                        Some(accept) => accept,
+                        None => {
+                            let mut response = Response::new(Body::empty());
+                            *response.status_mut() = StatusCode::BAD_REQUEST;
+                            return Ok::<_, Error>(response);
+                        }
+                    };
+
+                    if let Some(sender) = sender.lock().unwrap().take() {
+                        let _ = sender.send(req.into_body().on_upgrade());
+                    }
+
+                    let mut response = Response::new(Body::empty());
+                    *response.status_mut() = StatusCode::SWITCHING_PROTOCOLS;
+                    let headers = response.headers_mut();
+                    headers.insert(
+                        header::UPGRADE,
+                        header::HeaderValue::from_static("websocket"),
+                    );
+                    headers.insert(
+                        header::CONNECTION,
+                        header::HeaderValue::from_static("Upgrade"),
+                    );
+                    // Base64 never produces bytes that are invalid in a header value.
+                    headers.insert(
+                        header::SEC_WEBSOCKET_ACCEPT,
+                        header::HeaderValue::from_str(&accept).unwrap(),
+                    );
+                    Ok(response)
+                }
+            }),
+        )
+        .with_upgrades();
+
+    // The connection finishes once it has handed the stream over.
+    let (_, on_upgrade) =
+        future::try_join(connection.err_into::<Error>(), receiver.err_into::<Error>()).await?;
+    on_upgrade.err_into::<Error>().await
+}
+
+#[async_trait]
+impl<T, F, A, M> Acceptor<M> for WebSocketAcceptor<T, F>
+where
+    T: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+    F: FnOnce(UpgradedStream) -> A + Send + 'static,
+    A: Acceptor<M> + Send + 'static,
+    M: Send + 'static,
+{
+    async fn handshake(self) -> Result<M> {
+        let upgraded = upgrade(self.io, self.path).await?;
+        let stream = WebSocketStream::new(Compat::new(upgraded), Role::Server);
+        (self.make_inner)(stream).handshake().await
+    }
+}
@@ -0,0 +1,262 @@
+// MIT License
+
+// Copyright (c) 2019 Zhuhao Wang
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use super::Connector;
+use crate::{
+    core::{Endpoint, Error, Result},
+    io::BoxedIo,
+};
+use async_trait::async_trait;
+use futures::{
+    future::{BoxFuture, FutureExt},
+    io::{AsyncRead, AsyncWrite},
+};
+use std::{marker::PhantomData, sync::Arc};
+
+/// Reports which hop of the chain failed.
+///
+/// Hop `n` fails if it can't be reached or if it can't establish the tunnel
+/// to the next hop (or the target, for the last one).
+#[derive(Debug)]
+pub struct ChainError {
+    pub hop: usize,
+    pub server: Endpoint,
+    pub error: Error,
+}
+
+impl std::fmt::Display for ChainError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::result::Result<(), std::fmt::Error> {
+        write!(f, "{:#?}", self)
+    }
+}
+
+impl std::error::Error for ChainError {}
+
+/// The stream to a hop, established by the previous hops of the chain.
+///
+/// It stands in for the inner connector of the hop's own connector, and
+/// hands out the stream whatever endpoint it is asked to connect to.
+pub struct HopStream {
+    io: BoxedIo,
+}
+
+#[async_trait]
+impl Connector<BoxedIo> for HopStream {
+    async fn connect(self, _endpoint: &Endpoint) -> Result<BoxedIo> {
+        Ok(self.io)
+    }
+}
+
+type Tunnel = dyn Fn(HopStream, Endpoint) -> BoxFuture<'static, Result<BoxedIo>> + Send + Sync;
+
+/// A proxy in a chain.
+pub struct Hop {
+    server: Endpoint,
+    tunnel: Box<Tunnel>,
+}
+
+impl Hop {
+    /// `connector` builds the tunnelling connector for `server` on top of the
+    /// stream to it, e.g.,
+    /// `Hop::new(server.clone(), move |io| Socks5Connector::new(io, server.clone()))`.
+    pub fn new<F, C, S>(server: Endpoint, connector: F) -> Self
+    where
+        F: Fn(HopStream) -> C + Send + Sync + 'static,
+        C: Connector<S> + Send + 'static,
+        S: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+    {
+        let tunnel = move |io: HopStream, endpoint: Endpoint| {
+            let connector = connector(io);
+            async move {
+                let io: BoxedIo = Box::new(connector.connect(&endpoint).await?);
+                Ok::<_, Error>(io)
+            }
+            .boxed()
+        };
+        Hop {
+            server,
+            tunnel: Box::new(tunnel),
+        }
+    }
+
+    pub fn server(&self) -> &Endpoint {
+        &self.server
+    }
+}
+
+/// Connects to the target through `hops` in order, reaching the first hop
+/// with `inner`. Each hop tunnels to the next over the stream established by
+/// the previous ones.
+pub struct ChainConnector<C, T> {
+    inner: C,
+    hops: Arc<Vec<Hop>>,
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<C, T> ChainConnector<C, T> {
+    pub fn new(inner: C, hops: Vec<Hop>) -> Self {
+        ChainConnector {
+            inner,
+            hops: Arc::new(hops),
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<C: Clone, T> Clone for ChainConnector<C, T> {
+    fn clone(&self) -> Self {
+        ChainConnector {
+            inner: self.inner.clone(),
+            hops: self.hops.clone(),
+            _marker: PhantomData,
+        }
+    }
+}
+
+#[async_trait]
+impl<C, T> Connector<BoxedIo> for ChainConnector<C, T>
+where
+    C: Connector<T> + Send + 'static,
+    T: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+{
+    async fn connect(self, endpoint: &Endpoint) -> Result<BoxedIo> {
+        let first = match self.hops.first() {
+            Some(hop) => hop.server(),
+            None => {
+                let io = self.inner.connect(endpoint).await?;
+                return Ok(Box::new(io));
+            }
+        };
+
+        let mut io: BoxedIo = match self.inner.connect(first).await {
+            Ok(io) => Box::new(io),
+            Err(error) => {
+                return Err(ChainError {
+                    hop: 0,
+                    server: first.clone(),
+                    error,
+                }
+                .into())
+            }
+        };
+
+        for (i, hop) in self.hops.iter().enumerate() {
+            let next = self.hops.get(i + 1).map_or(endpoint, Hop::server);
+            io = match (hop.tunnel)(HopStream { io }, next.clone()).await {
+                Ok(io) => io,
+                Err(error) => {
+                    return Err(ChainError {
+                        hop: i,
+                        server: hop.server().clone(),
+                        error,
+                    }
+                    .into())
+                }
+            };
+        }
+
+        Ok(io)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        connector::{mock::MockConnector, HttpConnectConnector, Socks5Connector},
+        io::mock::MockStream,
+    };
+    use futures::{executor::block_on, io::AsyncReadExt};
+
+    const SOCKS5_SUCCEEDED: [u8; 12] = [5, 0, 5, 0, 0, 1, 0, 0, 0, 0, 0, 0];
+
+    fn socks5_hop(server: Endpoint) -> Hop {
+        Hop::new(server.clone(), move |io| {
+            Socks5Connector::new(io, server.clone())
+        })
+    }
+
+    fn http_connect_hop(server: Endpoint) -> Hop {
+        Hop::new(server.clone(), move |io| {
+            HttpConnectConnector::new(io, server.clone())
+        })
+    }
+
+    fn chain(input: &[u8]) -> (Result<BoxedIo>, Vec<u8>) {
+        let io = MockStream::new(input);
+        let output = io.output();
+        let connector = ChainConnector::new(
+            MockConnector::new(io),
+            vec![
+                socks5_hop(Endpoint::new_from_hostname("socks.example", 1080)),
+                http_connect_hop(Endpoint::new_from_hostname("http.example", 8080)),
+            ],
+        );
+
+        let endpoint = Endpoint::new_from_hostname("example.com", 443);
+        let result = block_on(connector.connect(&endpoint));
+        let output = output.lock().unwrap().clone();
+        (result, output)
+    }
+
+    fn socks5_connect_request(hostname: &str, port: u16) -> Vec<u8> {
+        let mut buf = vec![5, 1, 0, 5, 1, 0, 3, hostname.len() as u8];
+        buf.extend_from_slice(hostname.as_bytes());
+        buf.extend_from_slice(&port.to_be_bytes());
+        buf
+    }
+
+    #[test]
+    fn tunnels_through_every_hop() {
+        let mut input = SOCKS5_SUCCEEDED.to_vec();
+        input.extend_from_slice(b"HTTP/1.1 200 Connection established\r\n\r\nhello");
+        let (result, output) = chain(&input);
+
+        let mut rest = Vec::new();
+        block_on(result.unwrap().read_to_end(&mut rest)).unwrap();
+        assert_eq!(rest, b"hello");
+
+        let mut expected = socks5_connect_request("http.example", 8080);
+        expected.extend_from_slice(
+            b"CONNECT example.com:443 HTTP/1.1\r\nHost: example.com:443\r\n\r\n",
+        );
+        assert_eq!(output, expected);
+    }
+
+    #[test]
+    fn reports_failing_hop() {
+        let mut input = SOCKS5_SUCCEEDED.to_vec();
+        input.extend_from_slice(b"HTTP/1.1 403 Forbidden\r\n\r\n");
+        let (result, _) = chain(&input);
+
+        let err = result.err().unwrap();
+        let err = err.downcast_ref::<ChainError>().unwrap();
+        assert_eq!(err.hop, 1);
+        assert_eq!(err.server.to_string(), "http.example:8080");
+
+        let (result, _) = chain(&[5, 0, 5, 5, 0, 1, 0, 0, 0, 0, 0, 0]);
+        let err = result.err().unwrap();
+        let err = err.downcast_ref::<ChainError>().unwrap();
+        assert_eq!(err.hop, 0);
+        assert_eq!(err.server.to_string(), "socks.example:1080");
+    }
+}
@@ -0,0 +1,60 @@
+// MIT License
+
+// Copyright (c) 2019 Zhuhao Wang
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use super::Connector;
+use crate::core::{Endpoint, Result};
+use async_trait::async_trait;
+use futures_tokio_compat::Compat;
+use std::net::SocketAddr;
+
+/// Adapts the stream of a connector between the `tokio` and `futures` I/O
+/// traits, e.g., to use a `TcpStream` where a `futures::io::AsyncRead` is
+/// expected.
+#[derive(Clone)]
+pub struct CompatConnector<C> {
+    inner: C,
+}
+
+impl<C> CompatConnector<C> {
+    pub fn new(inner: C) -> Self {
+        CompatConnector { inner }
+    }
+}
+
+#[async_trait]
+impl<C, T> Connector<Compat<T>> for CompatConnector<C>
+where
+    C: Connector<T> + Send + 'static,
+    T: Send + 'static,
+{
+    async fn connect(self, endpoint: &Endpoint) -> Result<Compat<T>> {
+        self.inner.connect(endpoint).await.map(Compat::new)
+    }
+
+    async fn connect_with_local_addr(
+        self,
+        endpoint: &Endpoint,
+    ) -> Result<(Compat<T>, Option<SocketAddr>)> {
+        let (io, local_addr) = self.inner.connect_with_local_addr(endpoint).await?;
+        Ok((Compat::new(io), local_addr))
+    }
+}
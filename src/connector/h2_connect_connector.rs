@@ -0,0 +1,289 @@
+// MIT License
+
+// Copyright (c) 2019 Zhuhao Wang
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use super::{http_connect_connector::basic_authorization, Connector};
+use crate::{
+    core::{Endpoint, Result},
+    io::H2Stream,
+};
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures::{
+    io::{AsyncRead, AsyncWrite},
+    lock::Mutex,
+};
+use futures_tokio_compat::Compat;
+use h2::client::{self, SendRequest};
+use http::{
+    header::{HeaderMap, HeaderName, HeaderValue},
+    Method, Request,
+};
+use std::{marker::PhantomData, sync::Arc};
+
+#[derive(Debug)]
+pub enum H2ConnectConnectorError {
+    Rejected { status: u16 },
+}
+
+impl std::fmt::Display for H2ConnectConnectorError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::result::Result<(), std::fmt::Error> {
+        write!(f, "{:#?}", self)
+    }
+}
+
+impl std::error::Error for H2ConnectConnectorError {}
+
+/// The shared connection, with a count of how many times it was established
+/// so a dial that found it broken can tell whether another one has already
+/// replaced it.
+#[derive(Default)]
+struct Connection {
+    generation: u64,
+    send_request: Option<SendRequest<Bytes>>,
+}
+
+/// Connects to the target by sending `CONNECT` to an upstream HTTP/2 proxy.
+///
+/// This is the plain `CONNECT` of RFC 7540, not the extended `CONNECT` of
+/// RFC 8441: the h2 version in use can neither send the `:protocol`
+/// pseudo-header nor negotiate `SETTINGS_ENABLE_CONNECT_PROTOCOL`.
+///
+/// All tunnels share one HTTP/2 connection to the proxy, established with
+/// `inner` when the first tunnel is opened and again whenever the previous
+/// one has gone away. Clones share the connection.
+pub struct H2ConnectConnector<C, T> {
+    inner: C,
+    server: Endpoint,
+    headers: HeaderMap,
+    connection: Arc<Mutex<Connection>>,
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<C, T> H2ConnectConnector<C, T> {
+    pub fn new(inner: C, server: Endpoint) -> Self {
+        H2ConnectConnector {
+            inner,
+            server,
+            headers: HeaderMap::new(),
+            connection: Arc::new(Mutex::new(Connection::default())),
+            _marker: PhantomData,
+        }
+    }
+
+    /// Authenticates to the proxy with `Proxy-Authorization: Basic`.
+    pub fn with_credential(inner: C, server: Endpoint, username: &str, password: &str) -> Self {
+        H2ConnectConnector::new(inner, server).header(
+            http::header::PROXY_AUTHORIZATION,
+            basic_authorization(username, password),
+        )
+    }
+
+    /// Adds a header to every `CONNECT` request.
+    pub fn header(mut self, name: HeaderName, value: HeaderValue) -> Self {
+        self.headers.append(name, value);
+        self
+    }
+}
+
+impl<C: Clone, T> Clone for H2ConnectConnector<C, T> {
+    fn clone(&self) -> Self {
+        H2ConnectConnector {
+            inner: self.inner.clone(),
+            server: self.server.clone(),
+            headers: self.headers.clone(),
+            connection: self.connection.clone(),
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<C, T> H2ConnectConnector<C, T>
+where
+    C: Connector<T> + Send + 'static,
+    T: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+{
+    /// Returns a handle of its own to the shared connection, ready to send
+    /// a request.
+    async fn send_request(self) -> Result<SendRequest<Bytes>> {
+        let (generation, cached) = {
+            let connection = self.connection.lock().await;
+            (connection.generation, connection.send_request.clone())
+        };
+        if let Some(send_request) = cached {
+            if let Ok(send_request) = send_request.ready().await {
+                return Ok(send_request);
+            }
+        }
+
+        // Reconnect while holding the lock so concurrent dials share the new
+        // connection instead of each opening one.
+        let mut connection = self.connection.lock().await;
+        if connection.generation != generation {
+            if let Some(send_request) = connection.send_request.clone() {
+                drop(connection);
+                return Ok(send_request.ready().await?);
+            }
+        }
+
+        let io = self.inner.connect(&self.server).await?;
+        let (send_request, h2_connection) = client::handshake(Compat::new(io)).await?;
+        tokio::spawn(async move {
+            let _ = h2_connection.await;
+        });
+
+        connection.generation += 1;
+        connection.send_request = Some(send_request.clone());
+        drop(connection);
+        Ok(send_request.ready().await?)
+    }
+}
+
+#[async_trait]
+impl<C, T> Connector<H2Stream> for H2ConnectConnector<C, T>
+where
+    C: Connector<T> + Send + 'static,
+    T: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+{
+    async fn connect(self, endpoint: &Endpoint) -> Result<H2Stream> {
+        let mut request = Request::builder()
+            .method(Method::CONNECT)
+            .uri(endpoint.to_string().as_str())
+            .body(())?;
+        request.headers_mut().extend(self.headers.clone());
+
+        let mut send_request = self.send_request().await?;
+        let (response, send) = send_request.send_request(request, false)?;
+        let response = response.await?;
+
+        let status = response.status();
+        if !status.is_success() {
+            return Err(H2ConnectConnectorError::Rejected {
+                status: status.as_u16(),
+            }
+            .into());
+        }
+
+        Ok(H2Stream::new(response.into_body(), send))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        acceptor::{http2::H2ConnectAcceptor, Acceptor, MidHandshake},
+        connector::mock::DuplexConnector,
+        io::mock::DuplexStream,
+    };
+    use futures::{
+        future,
+        io::{AsyncReadExt, AsyncWriteExt},
+        stream::StreamExt,
+    };
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tokio::runtime::Runtime;
+
+    /// Serves CONNECT streams on `io`, echoing each until the client closes
+    /// it. A tunnel to `close.example` drops the whole connection instead.
+    async fn serve_echo(io: DuplexStream) {
+        let mut incoming = H2ConnectAcceptor::new(io).handshake().await.unwrap();
+        while let Some(Ok(mid)) = incoming.next().await {
+            if mid.target_endpoint().to_string() == "close.example:443" {
+                return;
+            }
+            tokio::spawn(async move {
+                let mut stream = mid.finalize(None).await.unwrap();
+                let mut buf = Vec::new();
+                stream.read_to_end(&mut buf).await.unwrap();
+                stream.write_all(&buf).await.unwrap();
+                stream.close().await.unwrap();
+            });
+        }
+    }
+
+    /// Returns a connector to a proxy that runs `serve_echo`, and how many
+    /// connections the proxy accepted.
+    fn echo_proxy() -> (
+        H2ConnectConnector<DuplexConnector, DuplexStream>,
+        Arc<AtomicUsize>,
+    ) {
+        let (inner, mut peers) = DuplexConnector::new();
+        let connections = Arc::new(AtomicUsize::new(0));
+        let accepted = connections.clone();
+        tokio::spawn(async move {
+            while let Some(io) = peers.next().await {
+                accepted.fetch_add(1, Ordering::SeqCst);
+                tokio::spawn(serve_echo(io));
+            }
+        });
+
+        let server = Endpoint::new_from_hostname("proxy.example", 443);
+        (H2ConnectConnector::new(inner, server), connections)
+    }
+
+    async fn echo(stream: &mut H2Stream, data: &[u8]) -> Vec<u8> {
+        stream.write_all(data).await.unwrap();
+        stream.close().await.unwrap();
+        let mut buf = Vec::new();
+        stream.read_to_end(&mut buf).await.unwrap();
+        buf
+    }
+
+    #[test]
+    fn shares_connection_between_streams() {
+        Runtime::new().unwrap().block_on(async {
+            let (connector, connections) = echo_proxy();
+            let first_target = Endpoint::new_from_hostname("first.example", 443);
+            let second_target = Endpoint::new_from_hostname("second.example", 443);
+
+            let (mut first, mut second) = future::try_join(
+                connector.clone().connect(&first_target),
+                connector.clone().connect(&second_target),
+            )
+            .await
+            .unwrap();
+            let (first, second) =
+                future::join(echo(&mut first, b"first"), echo(&mut second, b"second")).await;
+            assert_eq!(first, b"first");
+            assert_eq!(second, b"second");
+            assert_eq!(connections.load(Ordering::SeqCst), 1);
+        });
+    }
+
+    #[test]
+    fn reconnects_after_connection_dies() {
+        Runtime::new().unwrap().block_on(async {
+            let (connector, connections) = echo_proxy();
+            let target = Endpoint::new_from_hostname("example.com", 443);
+
+            let mut stream = connector.clone().connect(&target).await.unwrap();
+            assert_eq!(echo(&mut stream, b"before").await, b"before");
+
+            let close = Endpoint::new_from_hostname("close.example", 443);
+            assert!(connector.clone().connect(&close).await.is_err());
+
+            let mut stream = connector.clone().connect(&target).await.unwrap();
+            assert_eq!(echo(&mut stream, b"after").await, b"after");
+            assert_eq!(connections.load(Ordering::SeqCst), 2);
+        });
+    }
+}
@@ -0,0 +1,201 @@
+// MIT License
+
+// Copyright (c) 2019 Zhuhao Wang
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! The bits of HTTP/1.1 needed to send a request by hand on a raw stream,
+//! where hyper can't be used because the stream is taken over afterwards.
+
+use crate::core::{Error, Result};
+use futures::{
+    future::TryFutureExt,
+    io::{AsyncRead, AsyncReadExt},
+};
+use http::header::HeaderMap;
+
+/// The response header is expected to be a few hundred bytes; anything
+/// beyond this is not from a sane server.
+const MAX_RESPONSE_HEADER_LENGTH: usize = 16 * 1024;
+
+#[derive(Debug)]
+pub enum HttpResponseError {
+    ClosedBeforeResponse,
+    ResponseTooLarge,
+    InvalidResponse(String),
+}
+
+impl std::fmt::Display for HttpResponseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::result::Result<(), std::fmt::Error> {
+        write!(f, "{:#?}", self)
+    }
+}
+
+impl std::error::Error for HttpResponseError {}
+
+pub(super) struct ResponseHead {
+    pub(super) status: u16,
+    pub(super) status_line: String,
+    headers: Vec<(String, String)>,
+}
+
+impl ResponseHead {
+    /// The value of the first header called `name`, ignoring case.
+    pub(super) fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+pub(super) fn encode_headers(headers: &HeaderMap, buf: &mut Vec<u8>) {
+    for (name, value) in headers.iter() {
+        buf.extend_from_slice(name.as_str().as_bytes());
+        buf.extend_from_slice(b": ");
+        buf.extend_from_slice(value.as_bytes());
+        buf.extend_from_slice(b"\r\n");
+    }
+}
+
+fn find_header_end(buf: &[u8]) -> Option<usize> {
+    buf.windows(4)
+        .position(|window| window == b"\r\n\r\n")
+        .map(|pos| pos + 4)
+}
+
+fn parse_response_head(buf: &[u8]) -> Result<ResponseHead> {
+    let head = String::from_utf8_lossy(buf);
+    let mut lines = head.split("\r\n");
+    let status_line = lines.next().unwrap_or_default().to_owned();
+
+    let mut parts = status_line.splitn(3, ' ');
+    let version = parts.next().unwrap_or_default();
+    let status = match parts.next().and_then(|status| status.parse::<u16>().ok()) {
+        Some(status) if version.starts_with("HTTP/1.") => status,
+        _ => return Err(HttpResponseError::InvalidResponse(status_line).into()),
+    };
+
+    let headers = lines
+        .take_while(|line| !line.is_empty())
+        .filter_map(|line| {
+            let mut parts = line.splitn(2, ':');
+            match (parts.next(), parts.next()) {
+                (Some(name), Some(value)) => {
+                    Some((name.trim().to_owned(), value.trim().to_owned()))
+                }
+                _ => None,
+            }
+        })
+        .collect();
+
+    Ok(ResponseHead {
+        status,
+        status_line,
+        headers,
+    })
+}
+
+/// Reads the head of a response from `io`, returning it along with the
+/// bytes that arrived after it.
+pub(super) async fn read_response_head<T: AsyncRead + Unpin>(
+    io: &mut T,
+) -> Result<(ResponseHead, Vec<u8>)> {
+    let mut buf = Vec::new();
+    let mut chunk = [0; 1024];
+    let header_end = loop {
+        let len = io.read(&mut chunk).err_into::<Error>().await?;
+        if len == 0 {
+            return Err(HttpResponseError::ClosedBeforeResponse.into());
+        }
+
+        // The terminator may straddle the previous chunk.
+        let start = buf.len().saturating_sub(3);
+        buf.extend_from_slice(&chunk[..len]);
+        if let Some(end) = find_header_end(&buf[start..]) {
+            break start + end;
+        }
+
+        if buf.len() > MAX_RESPONSE_HEADER_LENGTH {
+            return Err(HttpResponseError::ResponseTooLarge.into());
+        }
+    };
+
+    let rest = buf.split_off(header_end);
+    Ok((parse_response_head(&buf)?, rest))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::mock::MockStream;
+    use futures::executor::block_on;
+
+    #[test]
+    fn reads_response_head() {
+        let mut io = MockStream::new(
+            b"HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\n\
+              sec-websocket-accept: key\r\n\r\nrest",
+        );
+        let (head, rest) = block_on(read_response_head(&mut io)).unwrap();
+
+        assert_eq!(head.status, 101);
+        assert_eq!(head.status_line, "HTTP/1.1 101 Switching Protocols");
+        assert_eq!(head.header("upgrade"), Some("websocket"));
+        assert_eq!(head.header("Sec-WebSocket-Accept"), Some("key"));
+        assert_eq!(head.header("connection"), None);
+        assert_eq!(rest, b"rest");
+    }
+
+    #[test]
+    fn rejects_invalid_responses() {
+        let err = |input: &[u8]| {
+            let mut io = MockStream::new(input);
+            block_on(read_response_head(&mut io))
+                .err()
+                .unwrap()
+                .downcast::<HttpResponseError>()
+                .unwrap()
+        };
+
+        match *err(b"HTTP/1.1 200 OK\r\n") {
+            HttpResponseError::ClosedBeforeResponse => {}
+            ref err => panic!("unexpected error {}", err),
+        }
+        match *err(&[b'a'; MAX_RESPONSE_HEADER_LENGTH + 1][..]) {
+            HttpResponseError::ResponseTooLarge => {}
+            ref err => panic!("unexpected error {}", err),
+        }
+        match *err(b"HTTP/2 200\r\n\r\n") {
+            HttpResponseError::InvalidResponse(ref status_line) => {
+                assert_eq!(status_line, "HTTP/2 200")
+            }
+            ref err => panic!("unexpected error {}", err),
+        }
+    }
+
+    #[test]
+    fn encodes_headers() {
+        let mut headers = HeaderMap::new();
+        headers.insert(http::header::HOST, "example.com".parse().unwrap());
+        let mut buf = Vec::new();
+        encode_headers(&headers, &mut buf);
+        assert_eq!(buf, b"host: example.com\r\n");
+    }
+}
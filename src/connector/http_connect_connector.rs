@@ -0,0 +1,237 @@
+// MIT License
+
+// Copyright (c) 2019 Zhuhao Wang
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use super::{
+    http1::{encode_headers, read_response_head, HttpResponseError},
+    Connector,
+};
+use crate::{
+    core::{Endpoint, Error, Result},
+    io::Prefixed,
+};
+use async_trait::async_trait;
+use futures::{
+    future::TryFutureExt,
+    io::{AsyncRead, AsyncWrite, AsyncWriteExt},
+};
+use http::header::{HeaderMap, HeaderName, HeaderValue};
+
+#[derive(Debug)]
+pub enum HttpConnectConnectorError {
+    ClosedBeforeResponse,
+    ResponseTooLarge,
+    InvalidResponse(String),
+    Rejected { status: u16, status_line: String },
+}
+
+impl std::fmt::Display for HttpConnectConnectorError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::result::Result<(), std::fmt::Error> {
+        write!(f, "{:#?}", self)
+    }
+}
+
+impl std::error::Error for HttpConnectConnectorError {}
+
+impl From<HttpResponseError> for HttpConnectConnectorError {
+    fn from(err: HttpResponseError) -> Self {
+        match err {
+            HttpResponseError::ClosedBeforeResponse => {
+                HttpConnectConnectorError::ClosedBeforeResponse
+            }
+            HttpResponseError::ResponseTooLarge => HttpConnectConnectorError::ResponseTooLarge,
+            HttpResponseError::InvalidResponse(status_line) => {
+                HttpConnectConnectorError::InvalidResponse(status_line)
+            }
+        }
+    }
+}
+
+/// Connects to the target by sending `CONNECT` to an upstream HTTP proxy
+/// reached with `inner`.
+///
+/// The proxy may send the first bytes of the tunnel along with its response,
+/// so the stream is returned wrapped in `Prefixed`.
+#[derive(Clone)]
+pub struct HttpConnectConnector<C> {
+    inner: C,
+    server: Endpoint,
+    headers: HeaderMap,
+}
+
+impl<C> HttpConnectConnector<C> {
+    pub fn new(inner: C, server: Endpoint) -> Self {
+        HttpConnectConnector {
+            inner,
+            server,
+            headers: HeaderMap::new(),
+        }
+    }
+
+    /// Authenticates to the proxy with `Proxy-Authorization: Basic`.
+    pub fn with_credential(inner: C, server: Endpoint, username: &str, password: &str) -> Self {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            http::header::PROXY_AUTHORIZATION,
+            basic_authorization(username, password),
+        );
+        HttpConnectConnector {
+            inner,
+            server,
+            headers,
+        }
+    }
+
+    /// Adds a header to the `CONNECT` request.
+    pub fn header(mut self, name: HeaderName, value: HeaderValue) -> Self {
+        self.headers.append(name, value);
+        self
+    }
+}
+
+pub(super) fn basic_authorization(username: &str, password: &str) -> HeaderValue {
+    let token = base64::encode(&format!("{}:{}", username, password));
+    // Base64 never produces bytes that are invalid in a header value.
+    HeaderValue::from_str(&format!("Basic {}", token)).unwrap()
+}
+
+/// Sends `CONNECT` for `endpoint` on `io` and waits for a 2xx response.
+pub(super) async fn handshake<T: AsyncRead + AsyncWrite + Unpin>(
+    mut io: T,
+    endpoint: &Endpoint,
+    headers: &HeaderMap,
+) -> Result<Prefixed<T>> {
+    let authority = endpoint.to_string();
+    let mut request =
+        format!("CONNECT {} HTTP/1.1\r\nHost: {}\r\n", authority, authority).into_bytes();
+    encode_headers(headers, &mut request);
+    request.extend_from_slice(b"\r\n");
+
+    io.write_all(&request).err_into::<Error>().await?;
+    io.flush().err_into::<Error>().await?;
+
+    let (head, rest) = match read_response_head(&mut io).await {
+        Ok(response) => response,
+        Err(err) => {
+            return Err(match err.downcast::<HttpResponseError>() {
+                Ok(err) => HttpConnectConnectorError::from(*err).into(),
+                Err(err) => err,
+            })
+        }
+    };
+    if !(200..300).contains(&head.status) {
+        return Err(HttpConnectConnectorError::Rejected {
+            status: head.status,
+            status_line: head.status_line,
+        }
+        .into());
+    }
+
+    Ok(Prefixed::new(io, rest))
+}
+
+#[async_trait]
+impl<C, T> Connector<Prefixed<T>> for HttpConnectConnector<C>
+where
+    C: Connector<T> + Send + 'static,
+    T: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+{
+    async fn connect(self, endpoint: &Endpoint) -> Result<Prefixed<T>> {
+        let io = self.inner.connect(&self.server).await?;
+        handshake(io, endpoint, &self.headers).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::mock::MockStream;
+    use futures::{executor::block_on, io::AsyncReadExt};
+
+    #[test]
+    fn sends_connect_and_keeps_early_data() {
+        let io = MockStream::new(b"HTTP/1.1 200 Connection established\r\n\r\nearly");
+        let output = io.output();
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            http::header::PROXY_AUTHORIZATION,
+            basic_authorization("user", "pass"),
+        );
+
+        let endpoint = Endpoint::new_from_hostname("example.com", 443);
+        let mut io = block_on(handshake(io, &endpoint, &headers)).unwrap();
+
+        assert_eq!(
+            String::from_utf8(output.lock().unwrap().clone()).unwrap(),
+            "CONNECT example.com:443 HTTP/1.1\r\nHost: example.com:443\r\n\
+             proxy-authorization: Basic dXNlcjpwYXNz\r\n\r\n"
+        );
+
+        let mut rest = Vec::new();
+        block_on(io.read_to_end(&mut rest)).unwrap();
+        assert_eq!(rest, b"early");
+    }
+
+    #[test]
+    fn reports_rejection() {
+        let io = MockStream::new(b"HTTP/1.1 407 Proxy Authentication Required\r\n\r\n");
+        let endpoint = Endpoint::new_from_hostname("example.com", 443);
+
+        let err = block_on(handshake(io, &endpoint, &HeaderMap::new()))
+            .err()
+            .unwrap();
+        match err.downcast_ref::<HttpConnectConnectorError>() {
+            Some(HttpConnectConnectorError::Rejected {
+                status,
+                status_line,
+            }) => {
+                assert_eq!(*status, 407);
+                assert_eq!(status_line, "HTTP/1.1 407 Proxy Authentication Required");
+            }
+            _ => panic!("unexpected error {}", err),
+        }
+    }
+
+    #[test]
+    fn reports_response_errors_as_its_own() {
+        let endpoint = Endpoint::new_from_hostname("example.com", 443);
+
+        let io = MockStream::new(b"HTTP/1.1 200");
+        let err = block_on(handshake(io, &endpoint, &HeaderMap::new()))
+            .err()
+            .unwrap();
+        match err.downcast_ref::<HttpConnectConnectorError>() {
+            Some(HttpConnectConnectorError::ClosedBeforeResponse) => {}
+            _ => panic!("unexpected error {}", err),
+        }
+
+        let io = MockStream::new(b"SSH-2.0-OpenSSH\r\n\r\n");
+        let err = block_on(handshake(io, &endpoint, &HeaderMap::new()))
+            .err()
+            .unwrap();
+        match err.downcast_ref::<HttpConnectConnectorError>() {
+            Some(HttpConnectConnectorError::InvalidResponse(status_line)) => {
+                assert_eq!(status_line, "SSH-2.0-OpenSSH")
+            }
+            _ => panic!("unexpected error {}", err),
+        }
+    }
+}
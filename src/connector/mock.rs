@@ -0,0 +1,113 @@
+// MIT License
+
+// Copyright (c) 2019 Zhuhao Wang
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use super::Connector;
+use crate::{
+    core::{Endpoint, Result},
+    io::mock::{duplex, DuplexStream, MockStream},
+};
+use async_trait::async_trait;
+use futures::channel::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use futures_tokio_compat::Compat;
+use std::{
+    io,
+    net::{Ipv4Addr, SocketAddr},
+};
+use tokio::net::TcpStream;
+
+/// Hands out a `MockStream` for tests, whatever the endpoint.
+pub(crate) struct MockConnector {
+    stream: MockStream,
+    local_addr: Option<SocketAddr>,
+}
+
+impl MockConnector {
+    pub(crate) fn new(stream: MockStream) -> Self {
+        MockConnector {
+            stream,
+            local_addr: None,
+        }
+    }
+
+    /// Reports `local_addr` as the local address of the connection.
+    pub(crate) fn with_local_addr(stream: MockStream, local_addr: SocketAddr) -> Self {
+        MockConnector {
+            stream,
+            local_addr: Some(local_addr),
+        }
+    }
+}
+
+#[async_trait]
+impl Connector<MockStream> for MockConnector {
+    async fn connect(self, _endpoint: &Endpoint) -> Result<MockStream> {
+        Ok(self.stream)
+    }
+
+    async fn connect_with_local_addr(
+        self,
+        _endpoint: &Endpoint,
+    ) -> Result<(MockStream, Option<SocketAddr>)> {
+        Ok((self.stream, self.local_addr))
+    }
+}
+
+/// Connects to the port of the endpoint on the loopback address, so tests
+/// can use any host name for a local server.
+pub(crate) struct LoopbackConnector;
+
+#[async_trait]
+impl Connector<Compat<TcpStream>> for LoopbackConnector {
+    async fn connect(self, endpoint: &Endpoint) -> Result<Compat<TcpStream>> {
+        let port = match *endpoint {
+            Endpoint::HostName(_, port) => port,
+            Endpoint::Ip(addr) => addr.port(),
+        };
+        let addr = SocketAddr::new(Ipv4Addr::LOCALHOST.into(), port);
+        Ok(Compat::new(TcpStream::connect(&addr).await?))
+    }
+}
+
+/// Connects over a new `duplex` every time, handing the other end of each
+/// connection to the test.
+#[derive(Clone)]
+pub(crate) struct DuplexConnector {
+    peers: UnboundedSender<DuplexStream>,
+}
+
+impl DuplexConnector {
+    pub(crate) fn new() -> (Self, UnboundedReceiver<DuplexStream>) {
+        let (peers, receiver) = mpsc::unbounded();
+        (DuplexConnector { peers }, receiver)
+    }
+}
+
+#[async_trait]
+impl Connector<DuplexStream> for DuplexConnector {
+    async fn connect(self, _endpoint: &Endpoint) -> Result<DuplexStream> {
+        let (io, peer) = duplex();
+        self.peers
+            .unbounded_send(peer)
+            .map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe))?;
+        Ok(io)
+    }
+}
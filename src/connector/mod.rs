@@ -24,11 +24,56 @@ extern crate tokio;
 
 use crate::core::{Endpoint, Result};
 use async_trait::async_trait;
+use std::net::SocketAddr;
 
+mod chain_connector;
+mod compat_connector;
+mod h2_connect_connector;
+mod http1;
+mod http_connect_connector;
+#[cfg(test)]
+pub(crate) mod mock;
+mod mux_connector;
+mod proxy_protocol_connector;
+mod shadowsocks_connector;
+#[cfg(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "macos",
+    target_os = "ios"
+))]
+mod sockopt;
+mod socks5_connector;
 mod tcp_connector;
-pub use self::tcp_connector::TcpConnector;
+mod tls_connector;
+mod trojan_connector;
+mod websocket_connector;
+pub use self::chain_connector::{ChainConnector, ChainError, Hop, HopStream};
+pub use self::compat_connector::CompatConnector;
+pub use self::h2_connect_connector::{H2ConnectConnector, H2ConnectConnectorError};
+pub use self::http1::HttpResponseError;
+pub use self::http_connect_connector::{HttpConnectConnector, HttpConnectConnectorError};
+pub use self::mux_connector::MuxConnector;
+pub use self::proxy_protocol_connector::{ProxyProtocolConnector, ProxyProtocolConnectorError};
+pub use self::shadowsocks_connector::ShadowsocksConnector;
+pub use self::socks5_connector::{Socks5Connector, Socks5ConnectorError};
+pub use self::tcp_connector::{TcpConnector, TcpConnectorBuilder, TcpConnectorError, TcpKeepalive};
+pub use self::tls_connector::{TlsConnector, TlsConnectorBuilder, TlsConnectorError, TlsStream};
+pub use self::trojan_connector::TrojanConnector;
+pub use self::websocket_connector::WebSocketConnector;
 
 #[async_trait]
 pub trait Connector<T> {
     async fn connect(self, endpoint: &Endpoint) -> Result<T>;
+
+    /// Like `connect`, also returning the local address of the outbound
+    /// connection if the connector knows it, e.g., for the bound address an
+    /// acceptor reports to its client.
+    async fn connect_with_local_addr(self, endpoint: &Endpoint) -> Result<(T, Option<SocketAddr>)>
+    where
+        Self: Sized + Send + 'static,
+        T: Send + 'static,
+    {
+        Ok((self.connect(endpoint).await?, None))
+    }
 }
@@ -0,0 +1,160 @@
+// MIT License
+
+// Copyright (c) 2019 Zhuhao Wang
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use super::Connector;
+use crate::{
+    core::{Endpoint, Result},
+    mux::{MuxConfig, MuxStream, Session},
+};
+use async_trait::async_trait;
+use futures::io::{AsyncRead, AsyncWrite};
+use std::{
+    collections::HashMap,
+    marker::PhantomData,
+    sync::{Arc, Mutex},
+};
+
+/// Opens logical streams over a pool of carrier connections to each
+/// endpoint, established with `inner`.
+///
+/// A new carrier is opened when every existing one carries
+/// `max_streams_per_session` streams, up to `max_sessions`; past that the
+/// least loaded one is shared. Clones share the pool.
+pub struct MuxConnector<C, T> {
+    inner: C,
+    config: MuxConfig,
+    sessions: Arc<Mutex<HashMap<String, Vec<Session>>>>,
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<C, T> MuxConnector<C, T> {
+    pub fn new(inner: C, config: MuxConfig) -> Self {
+        MuxConnector {
+            inner,
+            config,
+            sessions: Arc::new(Mutex::new(HashMap::new())),
+            _marker: PhantomData,
+        }
+    }
+
+    /// Returns the session to open the stream on, or `None` if a new one
+    /// should be opened.
+    fn pick_session(&self, key: &str) -> Option<Session> {
+        let mut sessions = self.sessions.lock().unwrap();
+        let pool = sessions.entry(key.to_owned()).or_insert_with(Vec::new);
+        pool.retain(|session| !session.is_closed());
+
+        let session = pool.iter().min_by_key(|session| session.stream_count())?;
+        if session.stream_count() >= self.config.max_streams_per_session
+            && pool.len() < self.config.max_sessions
+        {
+            None
+        } else {
+            Some(session.clone())
+        }
+    }
+}
+
+impl<C: Clone, T> Clone for MuxConnector<C, T> {
+    fn clone(&self) -> Self {
+        MuxConnector {
+            inner: self.inner.clone(),
+            config: self.config.clone(),
+            sessions: self.sessions.clone(),
+            _marker: PhantomData,
+        }
+    }
+}
+
+#[async_trait]
+impl<C, T> Connector<MuxStream> for MuxConnector<C, T>
+where
+    C: Connector<T> + Send + 'static,
+    T: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+{
+    async fn connect(self, endpoint: &Endpoint) -> Result<MuxStream> {
+        let key = endpoint.to_string();
+
+        let session = match self.pick_session(&key) {
+            Some(session) => session,
+            None => {
+                let io = self.inner.connect(endpoint).await?;
+                let session = Session::client(io, self.config.clone());
+                self.sessions
+                    .lock()
+                    .unwrap()
+                    .entry(key)
+                    .or_insert_with(Vec::new)
+                    .push(session.clone());
+                session
+            }
+        };
+
+        session.open()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connector::mock::DuplexConnector;
+    use futures::{io::AsyncReadExt, stream::StreamExt};
+    use tokio::runtime::Runtime;
+
+    #[test]
+    fn pools_carriers() {
+        let config = MuxConfig {
+            keepalive_interval: None,
+            max_sessions: 2,
+            max_streams_per_session: 1,
+            ..Default::default()
+        };
+
+        Runtime::new().unwrap().block_on(async {
+            let (inner, mut peers) = DuplexConnector::new();
+            let connector = MuxConnector::new(inner, config);
+            let endpoint = Endpoint::new_from_hostname("example.com", 443);
+
+            let mut first = connector.clone().connect(&endpoint).await.unwrap();
+            let second = connector.clone().connect(&endpoint).await.unwrap();
+            let first_peer = peers.next().await.unwrap();
+            let _second_peer = peers.next().await.unwrap();
+            assert_eq!((first.id(), second.id()), (1, 1));
+
+            // Past `max_sessions`, the least loaded carrier is shared.
+            let third = connector.clone().connect(&endpoint).await.unwrap();
+            assert_eq!(third.id(), 3);
+            assert!(peers.try_next().is_err());
+
+            // Other endpoints get carriers of their own.
+            let other = Endpoint::new_from_hostname("other.example", 443);
+            let _fourth = connector.clone().connect(&other).await.unwrap();
+            assert!(peers.next().await.is_some());
+
+            // A closed carrier is replaced.
+            drop(first_peer);
+            assert!(first.read(&mut [0; 1]).await.is_err());
+            let _fifth = connector.clone().connect(&endpoint).await.unwrap();
+            assert!(peers.next().await.is_some());
+        });
+    }
+}
@@ -0,0 +1,260 @@
+// MIT License
+
+// Copyright (c) 2019 Zhuhao Wang
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use super::Connector;
+use crate::{
+    acceptor::ClientMetadata,
+    core::{Endpoint, Error, Result},
+    proxy_protocol::{ProxyHeader, ProxyVersion, Tlv},
+};
+use async_trait::async_trait;
+use futures::{
+    future::TryFutureExt,
+    io::{AsyncRead, AsyncWrite, AsyncWriteExt},
+};
+use std::net::SocketAddr;
+
+#[derive(Debug)]
+pub enum ProxyProtocolConnectorError {
+    UnknownDestination,
+}
+
+impl std::fmt::Display for ProxyProtocolConnectorError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::result::Result<(), std::fmt::Error> {
+        write!(f, "{:#?}", self)
+    }
+}
+
+impl std::error::Error for ProxyProtocolConnectorError {}
+
+/// Sends a PROXY protocol header describing the original connection right
+/// after `inner` connects, for backends that expect one.
+///
+/// The addresses come from the connection being relayed, so a connector is
+/// created for each one; wrap only the connectors of the routes whose
+/// backends require the header. The destination defaults to the target
+/// when it is an IP address; for a domain name it must be set, or connecting
+/// fails rather than sending a header without addresses. Without a source,
+/// the header says the connection is not proxied on behalf of anyone.
+#[derive(Clone)]
+pub struct ProxyProtocolConnector<C> {
+    inner: C,
+    header: ProxyHeader,
+}
+
+impl<C> ProxyProtocolConnector<C> {
+    pub fn new(inner: C, version: ProxyVersion) -> Self {
+        ProxyProtocolConnector {
+            inner,
+            header: ProxyHeader {
+                version,
+                source: None,
+                destination: None,
+                tlvs: Vec::new(),
+            },
+        }
+    }
+
+    /// Describes the connection the client made, given its address as seen
+    /// by the acceptor.
+    ///
+    /// If the client came through a load balancer that sent its own PROXY
+    /// protocol header, the addresses in that header are passed on instead.
+    /// A `LOCAL` or `UNKNOWN` header means the load balancer connected on its
+    /// own behalf, so the load balancer itself is described as the client.
+    pub fn with_client(
+        inner: C,
+        version: ProxyVersion,
+        peer_addr: SocketAddr,
+        metadata: &ClientMetadata,
+    ) -> Self {
+        let connector = ProxyProtocolConnector::new(inner, version);
+        match metadata.proxy_header {
+            Some(ProxyHeader {
+                source: Some(source),
+                destination,
+                ..
+            }) => {
+                let connector = connector.source(source);
+                match destination {
+                    Some(destination) => connector.destination(destination),
+                    None => connector,
+                }
+            }
+            _ => connector.source(peer_addr),
+        }
+    }
+
+    pub fn source(mut self, addr: SocketAddr) -> Self {
+        self.header.source = Some(addr);
+        self
+    }
+
+    pub fn destination(mut self, addr: SocketAddr) -> Self {
+        self.header.destination = Some(addr);
+        self
+    }
+
+    /// Adds a TLV to the header, only sent with `ProxyVersion::V2`.
+    /// Connecting fails if the TLVs don't fit in the 64 KiB of a header.
+    pub fn tlv(mut self, kind: u8, value: Vec<u8>) -> Self {
+        self.header.tlvs.push(Tlv { kind, value });
+        self
+    }
+}
+
+#[async_trait]
+impl<C, T> Connector<T> for ProxyProtocolConnector<C>
+where
+    C: Connector<T> + Send + 'static,
+    T: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+{
+    async fn connect(self, endpoint: &Endpoint) -> Result<T> {
+        let (io, _) = Connector::<T>::connect_with_local_addr(self, endpoint).await?;
+        Ok(io)
+    }
+
+    async fn connect_with_local_addr(
+        mut self,
+        endpoint: &Endpoint,
+    ) -> Result<(T, Option<SocketAddr>)> {
+        if let (Some(_), None) = (self.header.source, self.header.destination) {
+            match endpoint {
+                Endpoint::Ip(addr) => self.header.destination = Some(*addr),
+                _ => return Err(ProxyProtocolConnectorError::UnknownDestination.into()),
+            }
+        }
+        let header = self.header.encode()?;
+
+        let (mut io, local_addr) = self.inner.connect_with_local_addr(endpoint).await?;
+        io.write_all(&header).err_into::<Error>().await?;
+        io.flush().err_into::<Error>().await?;
+        Ok((io, local_addr))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{connector::mock::MockConnector, io::mock::MockStream};
+    use futures::executor::block_on;
+
+    fn addr(addr: &str) -> SocketAddr {
+        addr.parse().unwrap()
+    }
+
+    /// The header sent when connecting to `endpoint` with `connector`.
+    fn sent(connector: ProxyProtocolConnector<MockConnector>, endpoint: &Endpoint) -> Vec<u8> {
+        let io: MockStream = block_on(connector.connect(endpoint)).unwrap();
+        let output = io.output();
+        let sent = output.lock().unwrap().clone();
+        sent
+    }
+
+    fn new_connector(version: ProxyVersion) -> ProxyProtocolConnector<MockConnector> {
+        ProxyProtocolConnector::new(MockConnector::new(MockStream::new(b"")), version)
+    }
+
+    #[test]
+    fn fills_destination_from_ip_target() {
+        let connector = new_connector(ProxyVersion::V1).source(addr("192.0.2.1:56324"));
+        let endpoint = Endpoint::Ip(addr("198.51.100.1:443"));
+        assert_eq!(
+            sent(connector, &endpoint),
+            &b"PROXY TCP4 192.0.2.1 198.51.100.1 56324 443\r\n"[..]
+        );
+    }
+
+    #[test]
+    fn refuses_domain_target_without_destination() {
+        let connector = new_connector(ProxyVersion::V2).source(addr("192.0.2.1:56324"));
+        let endpoint = Endpoint::new_from_hostname("example.com", 443);
+        let err = block_on(Connector::<MockStream>::connect(connector, &endpoint))
+            .err()
+            .unwrap();
+        match err.downcast_ref::<ProxyProtocolConnectorError>() {
+            Some(ProxyProtocolConnectorError::UnknownDestination) => {}
+            _ => panic!("unexpected error {}", err),
+        }
+
+        let connector = new_connector(ProxyVersion::V1)
+            .source(addr("192.0.2.1:56324"))
+            .destination(addr("198.51.100.1:443"));
+        assert_eq!(
+            sent(connector, &endpoint),
+            &b"PROXY TCP4 192.0.2.1 198.51.100.1 56324 443\r\n"[..]
+        );
+    }
+
+    #[test]
+    fn sends_unknown_without_source() {
+        let endpoint = Endpoint::new_from_hostname("example.com", 443);
+        assert_eq!(
+            sent(new_connector(ProxyVersion::V1), &endpoint),
+            &b"PROXY UNKNOWN\r\n"[..]
+        );
+    }
+
+    #[test]
+    fn describes_client() {
+        let endpoint = Endpoint::Ip(addr("198.51.100.1:443"));
+        let peer_addr = addr("203.0.113.1:1234");
+        let make = |proxy_header: Option<ProxyHeader>| {
+            let metadata = ClientMetadata {
+                proxy_header,
+                ..ClientMetadata::default()
+            };
+            ProxyProtocolConnector::with_client(
+                MockConnector::new(MockStream::new(b"")),
+                ProxyVersion::V1,
+                peer_addr,
+                &metadata,
+            )
+        };
+
+        assert_eq!(
+            sent(make(None), &endpoint),
+            &b"PROXY TCP4 203.0.113.1 198.51.100.1 1234 443\r\n"[..]
+        );
+
+        let upstream = ProxyHeader {
+            version: ProxyVersion::V2,
+            source: Some(addr("192.0.2.1:56324")),
+            destination: Some(addr("192.0.2.2:8443")),
+            tlvs: Vec::new(),
+        };
+        assert_eq!(
+            sent(make(Some(upstream.clone())), &endpoint),
+            &b"PROXY TCP4 192.0.2.1 192.0.2.2 56324 8443\r\n"[..]
+        );
+
+        let local = ProxyHeader {
+            source: None,
+            destination: None,
+            ..upstream
+        };
+        assert_eq!(
+            sent(make(Some(local)), &endpoint),
+            &b"PROXY TCP4 203.0.113.1 198.51.100.1 1234 443\r\n"[..]
+        );
+    }
+}
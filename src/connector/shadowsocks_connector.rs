@@ -0,0 +1,58 @@
+// MIT License
+
+// Copyright (c) 2019 Zhuhao Wang
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use super::Connector;
+use crate::{
+    core::{Endpoint, Result},
+    shadowsocks::{Role, ShadowsocksKey, ShadowsocksStream},
+};
+use async_trait::async_trait;
+use futures::io::{AsyncRead, AsyncWrite};
+
+/// Connects to the target through a Shadowsocks server reached with
+/// `inner`.
+#[derive(Clone)]
+pub struct ShadowsocksConnector<C> {
+    inner: C,
+    server: Endpoint,
+    key: ShadowsocksKey,
+}
+
+impl<C> ShadowsocksConnector<C> {
+    pub fn new(inner: C, server: Endpoint, key: ShadowsocksKey) -> Self {
+        ShadowsocksConnector { inner, server, key }
+    }
+}
+
+#[async_trait]
+impl<C, T> Connector<ShadowsocksStream<T>> for ShadowsocksConnector<C>
+where
+    C: Connector<T> + Send + 'static,
+    T: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+{
+    async fn connect(self, endpoint: &Endpoint) -> Result<ShadowsocksStream<T>> {
+        let io = self.inner.connect(&self.server).await?;
+        let mut stream = ShadowsocksStream::new(io, self.key, Role::Client);
+        stream.send_request(endpoint).await?;
+        Ok(stream)
+    }
+}
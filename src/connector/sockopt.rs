@@ -0,0 +1,111 @@
+// MIT License
+
+// Copyright (c) 2019 Zhuhao Wang
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Socket options that are not exposed by `tokio` or `socket2`.
+
+use std::{io, os::unix::io::AsRawFd};
+
+fn setsockopt<S: AsRawFd, T: ?Sized>(
+    socket: &S,
+    level: libc::c_int,
+    name: libc::c_int,
+    value: &T,
+) -> io::Result<()> {
+    let ret = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            level,
+            name,
+            value as *const T as *const libc::c_void,
+            std::mem::size_of_val(value) as libc::socklen_t,
+        )
+    };
+
+    if ret == -1 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+pub fn set_keepalive_interval<S: AsRawFd>(socket: &S, interval: u32) -> io::Result<()> {
+    setsockopt(
+        socket,
+        libc::IPPROTO_TCP,
+        libc::TCP_KEEPINTVL,
+        &(interval as libc::c_int),
+    )
+}
+
+pub fn set_keepalive_count<S: AsRawFd>(socket: &S, count: u32) -> io::Result<()> {
+    setsockopt(
+        socket,
+        libc::IPPROTO_TCP,
+        libc::TCP_KEEPCNT,
+        &(count as libc::c_int),
+    )
+}
+
+#[cfg(target_os = "linux")]
+pub fn set_mark<S: AsRawFd>(socket: &S, mark: u32) -> io::Result<()> {
+    setsockopt(
+        socket,
+        libc::SOL_SOCKET,
+        libc::SO_MARK,
+        &(mark as libc::c_int),
+    )
+}
+
+#[cfg(target_os = "linux")]
+pub fn bind_device<S: AsRawFd>(socket: &S, interface: &str) -> io::Result<()> {
+    setsockopt(
+        socket,
+        libc::SOL_SOCKET,
+        libc::SO_BINDTODEVICE,
+        interface.as_bytes(),
+    )
+}
+
+#[cfg(all(test, target_os = "linux"))]
+pub fn get_int<S: AsRawFd>(
+    socket: &S,
+    level: libc::c_int,
+    name: libc::c_int,
+) -> io::Result<libc::c_int> {
+    let mut value: libc::c_int = 0;
+    let mut len = std::mem::size_of_val(&value) as libc::socklen_t;
+    let ret = unsafe {
+        libc::getsockopt(
+            socket.as_raw_fd(),
+            level,
+            name,
+            &mut value as *mut libc::c_int as *mut libc::c_void,
+            &mut len,
+        )
+    };
+
+    if ret == -1 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(value)
+    }
+}
@@ -0,0 +1,267 @@
+// MIT License
+
+// Copyright (c) 2019 Zhuhao Wang
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use super::Connector;
+use crate::core::{encode_socks_addr, read_socks_addr, Endpoint, Error, Result, Socks5Reply};
+use async_trait::async_trait;
+use futures::{
+    future::TryFutureExt,
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+};
+
+#[derive(Debug)]
+pub enum Socks5ConnectorError {
+    UnsupportedVersion(u8),
+    NoAcceptableMethod,
+    UnexpectedMethod(u8),
+    CredentialTooLong,
+    UnsupportedAuthenticationVersion(u8),
+    AuthenticationFailed,
+    Rejected(Socks5Reply),
+    UnknownReply(u8),
+}
+
+impl std::fmt::Display for Socks5ConnectorError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::result::Result<(), std::fmt::Error> {
+        write!(f, "{:#?}", self)
+    }
+}
+
+impl std::error::Error for Socks5ConnectorError {}
+
+/// Connects to the target through an upstream SOCKS5 server reached with
+/// `inner`.
+///
+/// The target is sent as is, so a hostname endpoint is resolved by the
+/// server.
+#[derive(Clone)]
+pub struct Socks5Connector<C> {
+    inner: C,
+    server: Endpoint,
+    credential: Option<(String, String)>,
+}
+
+impl<C> Socks5Connector<C> {
+    pub fn new(inner: C, server: Endpoint) -> Self {
+        Socks5Connector {
+            inner,
+            server,
+            credential: None,
+        }
+    }
+
+    /// Authenticates to the server with username/password (RFC 1929).
+    pub fn with_credential(inner: C, server: Endpoint, username: &str, password: &str) -> Self {
+        Socks5Connector {
+            inner,
+            server,
+            credential: Some((username.to_owned(), password.to_owned())),
+        }
+    }
+}
+
+async fn authenticate<T: AsyncRead + AsyncWrite + Unpin>(
+    io: &mut T,
+    username: &str,
+    password: &str,
+) -> Result<()> {
+    if username.len() > 255 || password.len() > 255 {
+        return Err(Socks5ConnectorError::CredentialTooLong.into());
+    }
+
+    let mut buf = vec![1, username.len() as u8];
+    buf.extend_from_slice(username.as_bytes());
+    buf.push(password.len() as u8);
+    buf.extend_from_slice(password.as_bytes());
+    io.write_all(&buf).err_into::<Error>().await?;
+    io.flush().err_into::<Error>().await?;
+
+    let mut buf = [0; 2];
+    io.read_exact(&mut buf).err_into::<Error>().await?;
+
+    if buf[0] != 1 {
+        return Err(Socks5ConnectorError::UnsupportedAuthenticationVersion(buf[0]).into());
+    }
+    if buf[1] != 0 {
+        return Err(Socks5ConnectorError::AuthenticationFailed.into());
+    }
+
+    Ok(())
+}
+
+/// Runs the client side of the handshake on `io`, asking the server to
+/// connect to `endpoint`.
+pub(super) async fn handshake<T: AsyncRead + AsyncWrite + Unpin>(
+    io: &mut T,
+    endpoint: &Endpoint,
+    credential: Option<&(String, String)>,
+) -> Result<()> {
+    let methods: &[u8] = if credential.is_some() {
+        &[5, 2, 0, 2]
+    } else {
+        &[5, 1, 0]
+    };
+    io.write_all(methods).err_into::<Error>().await?;
+    io.flush().err_into::<Error>().await?;
+
+    let mut buf = [0; 2];
+    io.read_exact(&mut buf).err_into::<Error>().await?;
+
+    if buf[0] != 5 {
+        return Err(Socks5ConnectorError::UnsupportedVersion(buf[0]).into());
+    }
+
+    match (buf[1], credential) {
+        (0, _) => {}
+        (2, Some((username, password))) => authenticate(io, username, password).await?,
+        (0xff, _) => return Err(Socks5ConnectorError::NoAcceptableMethod.into()),
+        (method, _) => return Err(Socks5ConnectorError::UnexpectedMethod(method).into()),
+    }
+
+    let mut buf = vec![5, 1, 0];
+    encode_socks_addr(endpoint, &mut buf)?;
+    io.write_all(&buf).err_into::<Error>().await?;
+    io.flush().err_into::<Error>().await?;
+
+    let mut buf = [0; 3];
+    io.read_exact(&mut buf).err_into::<Error>().await?;
+
+    if buf[0] != 5 {
+        return Err(Socks5ConnectorError::UnsupportedVersion(buf[0]).into());
+    }
+
+    match Socks5Reply::from_code(buf[1]) {
+        Some(Socks5Reply::Succeeded) => {}
+        Some(reply) => return Err(Socks5ConnectorError::Rejected(reply).into()),
+        None => return Err(Socks5ConnectorError::UnknownReply(buf[1]).into()),
+    }
+
+    // The bound address is of no use for CONNECT.
+    read_socks_addr(io).await?;
+
+    Ok(())
+}
+
+#[async_trait]
+impl<C, T> Connector<T> for Socks5Connector<C>
+where
+    C: Connector<T> + Send + 'static,
+    T: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+{
+    async fn connect(self, endpoint: &Endpoint) -> Result<T> {
+        let mut io = self.inner.connect(&self.server).await?;
+        handshake(&mut io, endpoint, self.credential.as_ref()).await?;
+        Ok(io)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::mock::MockStream;
+    use futures::executor::block_on;
+
+    const SUCCEEDED: [u8; 10] = [5, 0, 0, 1, 0, 0, 0, 0, 0, 0];
+
+    fn run(input: &[u8], credential: Option<(&str, &str)>) -> (Result<()>, Vec<u8>) {
+        let mut io = MockStream::new(input);
+        let output = io.output();
+        let credential = credential.map(|(u, p)| (u.to_owned(), p.to_owned()));
+        let endpoint = Endpoint::new_from_hostname("example.com", 443);
+
+        let result = block_on(handshake(&mut io, &endpoint, credential.as_ref()));
+        let output = output.lock().unwrap().clone();
+        (result, output)
+    }
+
+    fn connect_request() -> Vec<u8> {
+        let mut buf = vec![5, 1, 0, 3, 11];
+        buf.extend_from_slice(b"example.com");
+        buf.extend_from_slice(&[1, 187]);
+        buf
+    }
+
+    #[test]
+    fn connects_without_authentication() {
+        let mut input = vec![5, 0];
+        input.extend_from_slice(&SUCCEEDED);
+        let (result, output) = run(&input, None);
+
+        result.unwrap();
+        let mut expected = vec![5, 1, 0];
+        expected.extend(connect_request());
+        assert_eq!(output, expected);
+    }
+
+    #[test]
+    fn connects_with_credential() {
+        let mut input = vec![5, 2, 1, 0];
+        input.extend_from_slice(&SUCCEEDED);
+        let (result, output) = run(&input, Some(("user", "pass")));
+
+        result.unwrap();
+        let mut expected = vec![5, 2, 0, 2, 1, 4];
+        expected.extend_from_slice(b"user");
+        expected.push(4);
+        expected.extend_from_slice(b"pass");
+        expected.extend(connect_request());
+        assert_eq!(output, expected);
+    }
+
+    #[test]
+    fn reports_rejected_credential() {
+        let (result, _) = run(&[5, 2, 1, 1], Some(("user", "pass")));
+        match result.unwrap_err().downcast_ref::<Socks5ConnectorError>() {
+            Some(Socks5ConnectorError::AuthenticationFailed) => {}
+            err => panic!("unexpected error {:?}", err),
+        }
+    }
+
+    #[test]
+    fn rejects_unknown_authentication_version() {
+        let mut input = vec![5, 2, 5, 0];
+        input.extend_from_slice(&SUCCEEDED);
+        let (result, _) = run(&input, Some(("user", "pass")));
+        match result.unwrap_err().downcast_ref::<Socks5ConnectorError>() {
+            Some(Socks5ConnectorError::UnsupportedAuthenticationVersion(5)) => {}
+            err => panic!("unexpected error {:?}", err),
+        }
+    }
+
+    #[test]
+    fn reports_no_acceptable_method() {
+        let (result, _) = run(&[5, 0xff], None);
+        match result.unwrap_err().downcast_ref::<Socks5ConnectorError>() {
+            Some(Socks5ConnectorError::NoAcceptableMethod) => {}
+            err => panic!("unexpected error {:?}", err),
+        }
+    }
+
+    #[test]
+    fn reports_rejection() {
+        let (result, _) = run(&[5, 0, 5, 5, 0, 1, 0, 0, 0, 0, 0, 0], None);
+        match result.unwrap_err().downcast_ref::<Socks5ConnectorError>() {
+            Some(Socks5ConnectorError::Rejected(Socks5Reply::ConnectionRefused)) => {}
+            err => panic!("unexpected error {:?}", err),
+        }
+    }
+}
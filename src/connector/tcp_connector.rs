@@ -26,26 +26,689 @@ use crate::{
     resolver::Resolver,
 };
 use async_trait::async_trait;
-use futures::future::TryFutureExt;
-use tokio::net::TcpStream;
+use futures::{
+    future::{self, Either, FutureExt},
+    stream::{FuturesUnordered, StreamExt},
+};
+use socket2::{Domain, Protocol, SockAddr, Socket, Type};
+use std::{
+    future::Future,
+    io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    ops::RangeInclusive,
+    time::{Duration, Instant},
+};
+use tokio::{
+    net::{driver::Handle, TcpStream},
+    timer::{delay, Timeout},
+};
+
+/// The delay between connection attempts recommended by RFC 8305.
+const CONNECTION_ATTEMPT_DELAY: Duration = Duration::from_millis(250);
+
+/// Returned as the inner error of the `io::Error` reported when no address
+/// could be connected. The `io::Error` carries the kind of the last failure.
+#[derive(Debug)]
+pub enum TcpConnectorError {
+    NoAddress,
+    AllAttemptsFailed(Vec<(SocketAddr, io::Error)>),
+}
+
+impl std::fmt::Display for TcpConnectorError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::result::Result<(), std::fmt::Error> {
+        write!(f, "{:#?}", self)
+    }
+}
 
+impl std::error::Error for TcpConnectorError {}
+
+#[derive(Clone, Copy, Debug)]
+pub struct TcpKeepalive {
+    pub idle: Duration,
+    /// Ignored on platforms without `TCP_KEEPINTVL`.
+    pub interval: Option<Duration>,
+    /// Ignored on platforms without `TCP_KEEPCNT`.
+    pub count: Option<u32>,
+}
+
+#[derive(Clone, Debug, Default)]
+struct SocketOptions {
+    attempt_timeout: Option<Duration>,
+    connect_timeout: Option<Duration>,
+    nodelay: bool,
+    keepalive: Option<TcpKeepalive>,
+    #[cfg(target_os = "linux")]
+    mark: Option<u32>,
+    send_buffer_size: Option<usize>,
+    recv_buffer_size: Option<usize>,
+    bind_addrs: Vec<IpAddr>,
+    bind_port_range: Option<RangeInclusive<u16>>,
+    #[cfg(target_os = "linux")]
+    bind_device: Option<String>,
+}
+
+#[derive(Clone)]
 pub struct TcpConnector<R: Resolver + Send + 'static> {
     resolver: R,
+    options: SocketOptions,
 }
 
 impl<R: Resolver + Send + 'static> TcpConnector<R> {
     pub fn new(resolver: R) -> Self {
-        TcpConnector { resolver }
+        TcpConnector {
+            resolver,
+            options: SocketOptions::default(),
+        }
+    }
+
+    pub fn builder(resolver: R) -> TcpConnectorBuilder<R> {
+        TcpConnectorBuilder {
+            resolver,
+            options: SocketOptions::default(),
+        }
+    }
+}
+
+pub struct TcpConnectorBuilder<R: Resolver + Send + 'static> {
+    resolver: R,
+    options: SocketOptions,
+}
+
+impl<R: Resolver + Send + 'static> TcpConnectorBuilder<R> {
+    /// Limits the time spent on each address.
+    pub fn attempt_timeout(mut self, timeout: Duration) -> Self {
+        self.options.attempt_timeout = Some(timeout);
+        self
+    }
+
+    /// Limits the time spent on the whole connect, including resolving the
+    /// endpoint.
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.options.connect_timeout = Some(timeout);
+        self
+    }
+
+    pub fn nodelay(mut self, nodelay: bool) -> Self {
+        self.options.nodelay = nodelay;
+        self
+    }
+
+    pub fn keepalive(mut self, keepalive: TcpKeepalive) -> Self {
+        self.options.keepalive = Some(keepalive);
+        self
+    }
+
+    /// Sets `SO_MARK`, which requires `CAP_NET_ADMIN`.
+    #[cfg(target_os = "linux")]
+    pub fn mark(mut self, mark: u32) -> Self {
+        self.options.mark = Some(mark);
+        self
+    }
+
+    pub fn send_buffer_size(mut self, size: usize) -> Self {
+        self.options.send_buffer_size = Some(size);
+        self
+    }
+
+    pub fn recv_buffer_size(mut self, size: usize) -> Self {
+        self.options.recv_buffer_size = Some(size);
+        self
+    }
+
+    /// Binds outbound sockets to `ip`. Set one address for each family; the
+    /// addresses of a family without one are skipped.
+    pub fn bind_addr(mut self, ip: IpAddr) -> Self {
+        self.options
+            .bind_addrs
+            .retain(|addr| addr.is_ipv6() != ip.is_ipv6());
+        self.options.bind_addrs.push(ip);
+        self
+    }
+
+    /// Binds outbound sockets to the first free port in `range`.
+    pub fn bind_port_range(mut self, range: RangeInclusive<u16>) -> Self {
+        self.options.bind_port_range = Some(range);
+        self
+    }
+
+    /// Sends traffic through `interface` with `SO_BINDTODEVICE`, which
+    /// requires `CAP_NET_RAW`.
+    #[cfg(target_os = "linux")]
+    pub fn bind_device(mut self, interface: &str) -> Self {
+        self.options.bind_device = Some(interface.to_owned());
+        self
+    }
+
+    pub fn build(self) -> TcpConnector<R> {
+        TcpConnector {
+            resolver: self.resolver,
+            options: self.options,
+        }
+    }
+}
+
+async fn timeout<T, E, F>(duration: Option<Duration>, future: F) -> std::result::Result<T, E>
+where
+    E: From<io::Error>,
+    F: Future<Output = std::result::Result<T, E>>,
+{
+    match duration {
+        Some(duration) => Timeout::new(future, duration)
+            .await
+            .unwrap_or_else(|_| Err(io::Error::from(io::ErrorKind::TimedOut).into())),
+        None => future.await,
+    }
+}
+
+/// Orders addresses by alternating address families, starting with the
+/// family of the first address.
+fn interleave(addrs: Vec<SocketAddr>) -> Vec<SocketAddr> {
+    let first_is_ipv6 = addrs.first().map(SocketAddr::is_ipv6).unwrap_or(true);
+    let (preferred, other): (Vec<_>, Vec<_>) = addrs
+        .into_iter()
+        .partition(|addr| addr.is_ipv6() == first_is_ipv6);
+
+    let mut result = Vec::with_capacity(preferred.len() + other.len());
+    let mut preferred = preferred.into_iter();
+    let mut other = other.into_iter();
+    loop {
+        match (preferred.next(), other.next()) {
+            (None, None) => return result,
+            (a, b) => {
+                result.extend(a);
+                result.extend(b);
+            }
+        }
+    }
+}
+
+fn apply_keepalive(stream: &TcpStream, keepalive: &TcpKeepalive) -> io::Result<()> {
+    stream.set_keepalive(Some(keepalive.idle))?;
+
+    #[cfg(any(
+        target_os = "linux",
+        target_os = "android",
+        target_os = "macos",
+        target_os = "ios"
+    ))]
+    {
+        if let Some(interval) = keepalive.interval {
+            super::sockopt::set_keepalive_interval(stream, interval.as_secs() as u32)?;
+        }
+        if let Some(count) = keepalive.count {
+            super::sockopt::set_keepalive_count(stream, count)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn bind_local(socket: &Socket, target: SocketAddr, options: &SocketOptions) -> io::Result<()> {
+    let ip = options
+        .bind_addrs
+        .iter()
+        .find(|ip| ip.is_ipv6() == target.is_ipv6())
+        .cloned();
+
+    if ip.is_none() && !options.bind_addrs.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::AddrNotAvailable,
+            "no bind address matches the family of the target",
+        ));
+    }
+
+    let ip = ip.unwrap_or_else(|| {
+        if target.is_ipv6() {
+            Ipv6Addr::UNSPECIFIED.into()
+        } else {
+            Ipv4Addr::UNSPECIFIED.into()
+        }
+    });
+
+    let range = match options.bind_port_range {
+        Some(ref range) => range.clone(),
+        None if options.bind_addrs.is_empty() => return Ok(()),
+        None => 0..=0,
+    };
+
+    let mut last_err = io::Error::from(io::ErrorKind::AddrInUse);
+    for port in range {
+        match socket.bind(&SockAddr::from(SocketAddr::new(ip, port))) {
+            Ok(()) => return Ok(()),
+            Err(err) if err.kind() == io::ErrorKind::AddrInUse => last_err = err,
+            Err(err) => return Err(err),
+        }
+    }
+    Err(last_err)
+}
+
+async fn connect_socket(addr: SocketAddr, options: &SocketOptions) -> io::Result<TcpStream> {
+    let domain = if addr.is_ipv6() {
+        Domain::ipv6()
+    } else {
+        Domain::ipv4()
+    };
+    let socket = Socket::new(domain, Type::stream(), Some(Protocol::tcp()))?;
+    socket.set_nonblocking(true)?;
+
+    // Buffer sizes affect the window scale negotiated in the handshake, so
+    // they are set before connecting.
+    if let Some(size) = options.send_buffer_size {
+        socket.set_send_buffer_size(size)?;
+    }
+    if let Some(size) = options.recv_buffer_size {
+        socket.set_recv_buffer_size(size)?;
+    }
+    #[cfg(target_os = "linux")]
+    {
+        if let Some(mark) = options.mark {
+            super::sockopt::set_mark(&socket, mark)?;
+        }
+        if let Some(ref interface) = options.bind_device {
+            super::sockopt::bind_device(&socket, interface)?;
+        }
+    }
+    bind_local(&socket, addr, options)?;
+
+    let stream =
+        TcpStream::connect_std(socket.into_tcp_stream(), &addr, &Handle::default()).await?;
+
+    stream.set_nodelay(options.nodelay)?;
+    if let Some(ref keepalive) = options.keepalive {
+        apply_keepalive(&stream, keepalive)?;
+    }
+
+    Ok(stream)
+}
+
+async fn attempt(addr: SocketAddr, options: &SocketOptions) -> (SocketAddr, io::Result<TcpStream>) {
+    let result = timeout(options.attempt_timeout, connect_socket(addr, options)).await;
+    (addr, result)
+}
+
+/// Drops the addresses of a family without a bind address, which could
+/// only fail and would hold up the others.
+fn bindable(mut addrs: Vec<SocketAddr>, options: &SocketOptions) -> Vec<SocketAddr> {
+    if !options.bind_addrs.is_empty() {
+        addrs.retain(|addr| {
+            options
+                .bind_addrs
+                .iter()
+                .any(|ip| ip.is_ipv6() == addr.is_ipv6())
+        });
+    }
+    addrs
+}
+
+async fn happy_eyeballs(addrs: Vec<SocketAddr>, options: &SocketOptions) -> io::Result<TcpStream> {
+    race(bindable(addrs, options), |addr| attempt(addr, options)).await
+}
+
+/// Races connections to `addrs` as described in RFC 8305, starting a new
+/// attempt whenever the previous one fails or is still pending after
+/// `CONNECTION_ATTEMPT_DELAY`. The first established connection wins and
+/// the others are dropped.
+async fn race<S, F, Fut>(addrs: Vec<SocketAddr>, mut attempt: F) -> io::Result<S>
+where
+    F: FnMut(SocketAddr) -> Fut,
+    Fut: Future<Output = (SocketAddr, io::Result<S>)>,
+{
+    let mut addrs = interleave(addrs).into_iter();
+    let mut attempts = FuturesUnordered::new();
+    let mut errors = vec![];
+
+    loop {
+        if attempts.is_empty() {
+            match addrs.next() {
+                Some(addr) => attempts.push(attempt(addr)),
+                None => break,
+            }
+        }
+
+        let outcome = if addrs.as_slice().is_empty() {
+            attempts.next().await
+        } else {
+            let timer = delay(Instant::now() + CONNECTION_ATTEMPT_DELAY).boxed();
+            match future::select(attempts.next(), timer).await {
+                Either::Left((outcome, _)) => outcome,
+                Either::Right(_) => None,
+            }
+        };
+
+        match outcome {
+            Some((_, Ok(stream))) => return Ok(stream),
+            Some((addr, Err(err))) => errors.push((addr, err)),
+            None => {}
+        }
+
+        if let Some(addr) = addrs.next() {
+            attempts.push(attempt(addr));
+        }
     }
+
+    let kind = errors.last().map(|(_, err)| err.kind());
+    Err(match kind {
+        Some(kind) => io::Error::new(kind, TcpConnectorError::AllAttemptsFailed(errors)),
+        None => io::Error::new(
+            io::ErrorKind::AddrNotAvailable,
+            TcpConnectorError::NoAddress,
+        ),
+    })
 }
 
 #[async_trait]
 impl<R: Resolver + Send + 'static> Connector<TcpStream> for TcpConnector<R> {
     async fn connect(self, endpoint: &Endpoint) -> Result<TcpStream> {
-        let addrs = self.resolver.resolve_endpoint(endpoint).await?;
-        // TODO: Try all IPs
-        Ok(TcpStream::connect(addrs.first().unwrap())
-            .err_into::<Error>()
-            .await?)
+        let TcpConnector { resolver, options } = self;
+        timeout(options.connect_timeout, async {
+            let addrs = resolver.resolve_endpoint(endpoint).await?;
+            Ok::<_, Error>(happy_eyeballs(addrs, &options).await?)
+        })
+        .await
+    }
+
+    async fn connect_with_local_addr(
+        self,
+        endpoint: &Endpoint,
+    ) -> Result<(TcpStream, Option<SocketAddr>)> {
+        let stream = self.connect(endpoint).await?;
+        let local_addr = stream.local_addr().ok();
+        Ok((stream, local_addr))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::future::BoxFuture;
+    use std::{net::TcpListener, sync::Mutex};
+    use tokio::runtime::Runtime;
+
+    /// Resolves every host name to the loopback address, or never if
+    /// `hang` is set.
+    #[derive(Clone)]
+    struct LoopbackResolver {
+        hang: bool,
+    }
+
+    impl Resolver for LoopbackResolver {
+        fn resolve_hostname(self, _hostname: &str) -> BoxFuture<Result<Vec<IpAddr>>> {
+            if self.hang {
+                future::pending().boxed()
+            } else {
+                future::ok(vec![Ipv4Addr::LOCALHOST.into()]).boxed()
+            }
+        }
+    }
+
+    fn builder() -> TcpConnectorBuilder<LoopbackResolver> {
+        TcpConnector::builder(LoopbackResolver { hang: false })
+    }
+
+    fn connect(connector: TcpConnector<LoopbackResolver>, port: u16) -> Result<TcpStream> {
+        Runtime::new().unwrap().block_on(async move {
+            connector
+                .connect(&Endpoint::new_from_hostname("localhost", port))
+                .await
+        })
+    }
+
+    fn addrs(addrs: &[&str]) -> Vec<SocketAddr> {
+        addrs.iter().map(|addr| addr.parse().unwrap()).collect()
+    }
+
+    #[test]
+    fn interleaves_families_starting_with_first() {
+        let result = interleave(addrs(&[
+            "[::1]:80",
+            "[::2]:80",
+            "[::3]:80",
+            "10.0.0.1:80",
+            "10.0.0.2:80",
+        ]));
+        assert_eq!(
+            result,
+            addrs(&[
+                "[::1]:80",
+                "10.0.0.1:80",
+                "[::2]:80",
+                "10.0.0.2:80",
+                "[::3]:80",
+            ])
+        );
+
+        let result = interleave(addrs(&["10.0.0.1:80", "[::1]:80", "10.0.0.2:80"]));
+        assert_eq!(result, addrs(&["10.0.0.1:80", "[::1]:80", "10.0.0.2:80"]));
+    }
+
+    #[test]
+    fn interleaves_single_family_unchanged() {
+        let input = addrs(&["10.0.0.1:80", "10.0.0.2:80"]);
+        assert_eq!(interleave(input.clone()), input);
+        assert!(interleave(vec![]).is_empty());
+    }
+
+    /// Races `input`, where connecting to the addresses in `fail` fails at
+    /// once, to those in `hang` never completes and to the others succeeds
+    /// at once. Returns the result, the order in which the attempts were
+    /// started and how long the race took.
+    fn run_race(
+        input: &[&str],
+        fail: &[&str],
+        hang: &[&str],
+    ) -> (io::Result<SocketAddr>, Vec<SocketAddr>, Duration) {
+        let fail = addrs(fail);
+        let hang = addrs(hang);
+        let started = Mutex::new(vec![]);
+
+        let start = Instant::now();
+        let result = Runtime::new().unwrap().block_on(race(addrs(input), |addr| {
+            started.lock().unwrap().push(addr);
+            let fail = fail.contains(&addr);
+            let hang = hang.contains(&addr);
+            async move {
+                if hang {
+                    future::pending::<()>().await;
+                }
+                if fail {
+                    (addr, Err(io::Error::from(io::ErrorKind::ConnectionRefused)))
+                } else {
+                    (addr, Ok(addr))
+                }
+            }
+        }));
+
+        (result, started.into_inner().unwrap(), start.elapsed())
+    }
+
+    #[test]
+    fn starts_next_attempt_at_once_after_failure() {
+        let (result, started, elapsed) = run_race(&["[::1]:80", "10.0.0.1:80"], &["[::1]:80"], &[]);
+
+        assert_eq!(result.unwrap(), "10.0.0.1:80".parse().unwrap());
+        assert_eq!(started, addrs(&["[::1]:80", "10.0.0.1:80"]));
+        assert!(elapsed < CONNECTION_ATTEMPT_DELAY);
+    }
+
+    #[test]
+    fn starts_next_attempt_after_delay() {
+        let (result, started, elapsed) =
+            run_race(&["[::1]:80", "[::2]:80", "10.0.0.1:80"], &[], &["[::1]:80"]);
+
+        assert_eq!(result.unwrap(), "10.0.0.1:80".parse().unwrap());
+        assert_eq!(started, addrs(&["[::1]:80", "10.0.0.1:80"]));
+        assert!(elapsed >= CONNECTION_ATTEMPT_DELAY);
+        assert!(elapsed < CONNECTION_ATTEMPT_DELAY * 2);
+    }
+
+    #[test]
+    fn reports_every_failure() {
+        let input = ["[::1]:80", "10.0.0.1:80", "[::2]:80"];
+        let (result, started, _) = run_race(&input, &input, &[]);
+
+        assert_eq!(started, addrs(&["[::1]:80", "10.0.0.1:80", "[::2]:80"]));
+        let err = result.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::ConnectionRefused);
+        match err
+            .get_ref()
+            .and_then(|err| err.downcast_ref::<TcpConnectorError>())
+        {
+            Some(TcpConnectorError::AllAttemptsFailed(errors)) => {
+                let failed: Vec<_> = errors.iter().map(|(addr, _)| *addr).collect();
+                assert_eq!(failed, started);
+            }
+            _ => panic!("unexpected error {}", err),
+        }
+    }
+
+    #[test]
+    fn reports_no_address() {
+        let (result, started, _) = run_race(&[], &[], &[]);
+
+        assert!(started.is_empty());
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::AddrNotAvailable);
+    }
+
+    #[test]
+    fn bind_addr_replaces_address_of_same_family() {
+        let connector = builder()
+            .bind_addr("127.0.0.1".parse().unwrap())
+            .bind_addr("::1".parse().unwrap())
+            .bind_addr("127.0.0.2".parse().unwrap())
+            .build();
+        assert_eq!(
+            connector.options.bind_addrs,
+            vec![
+                "::1".parse::<IpAddr>().unwrap(),
+                "127.0.0.2".parse().unwrap()
+            ]
+        );
+    }
+
+    #[test]
+    fn skips_families_without_bind_address() {
+        let options = builder()
+            .bind_addr("127.0.0.1".parse().unwrap())
+            .build()
+            .options;
+        assert_eq!(
+            bindable(addrs(&["[::1]:80", "10.0.0.1:80", "[::2]:80"]), &options),
+            addrs(&["10.0.0.1:80"])
+        );
+
+        let input = addrs(&["[::1]:80", "10.0.0.1:80"]);
+        assert_eq!(bindable(input.clone(), &SocketOptions::default()), input);
+    }
+
+    #[test]
+    fn applies_socket_options() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let connector = builder()
+            .nodelay(true)
+            .keepalive(TcpKeepalive {
+                idle: Duration::from_secs(30),
+                interval: Some(Duration::from_secs(5)),
+                count: Some(3),
+            })
+            .send_buffer_size(64 * 1024)
+            .recv_buffer_size(64 * 1024)
+            .bind_addr("127.0.0.1".parse().unwrap())
+            .build();
+        let stream = connect(connector, port).unwrap();
+
+        assert!(stream.nodelay().unwrap());
+        assert_eq!(stream.keepalive().unwrap(), Some(Duration::from_secs(30)));
+        // The kernel may round the buffer sizes up.
+        assert!(stream.send_buffer_size().unwrap() >= 64 * 1024);
+        assert!(stream.recv_buffer_size().unwrap() >= 64 * 1024);
+        assert_eq!(
+            stream.local_addr().unwrap().ip(),
+            "127.0.0.1".parse::<IpAddr>().unwrap()
+        );
+
+        #[cfg(target_os = "linux")]
+        {
+            use super::super::sockopt::get_int;
+            assert_eq!(
+                get_int(&stream, libc::IPPROTO_TCP, libc::TCP_KEEPINTVL).unwrap(),
+                5
+            );
+            assert_eq!(
+                get_int(&stream, libc::IPPROTO_TCP, libc::TCP_KEEPCNT).unwrap(),
+                3
+            );
+        }
+    }
+
+    #[test]
+    fn binds_port_in_range() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        // Find a port that is free right now.
+        let local_port = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+
+        let connector = builder().bind_port_range(local_port..=local_port).build();
+        let stream = connect(connector, port).unwrap();
+        assert_eq!(stream.local_addr().unwrap().port(), local_port);
+
+        // The port is taken now, so there is nothing left in the range.
+        let connector = builder().bind_port_range(local_port..=local_port).build();
+        let err = connect(connector, port).err().unwrap();
+        match err.downcast_ref::<io::Error>() {
+            Some(err) => assert_eq!(err.kind(), io::ErrorKind::AddrInUse),
+            None => panic!("unexpected error {}", err),
+        }
+    }
+
+    #[test]
+    fn refuses_target_without_bind_address() {
+        let connector = builder().bind_addr("::1".parse().unwrap()).build();
+        let err = connect(connector, 80).err().unwrap();
+        match err.downcast_ref::<io::Error>() {
+            Some(err) => assert_eq!(err.kind(), io::ErrorKind::AddrNotAvailable),
+            None => panic!("unexpected error {}", err),
+        }
+    }
+
+    #[test]
+    fn times_out_whole_connect() {
+        let connector = TcpConnector::builder(LoopbackResolver { hang: true })
+            .connect_timeout(Duration::from_millis(50))
+            .build();
+        let err = connect(connector, 80).err().unwrap();
+        match err.downcast_ref::<io::Error>() {
+            Some(err) => assert_eq!(err.kind(), io::ErrorKind::TimedOut),
+            None => panic!("unexpected error {}", err),
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn applies_mark_and_device() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let connector = builder().mark(42).bind_device("lo").build();
+        match connect(connector, port) {
+            Ok(stream) => {
+                use super::super::sockopt::get_int;
+                assert_eq!(
+                    get_int(&stream, libc::SOL_SOCKET, libc::SO_MARK).unwrap(),
+                    42
+                );
+            }
+            // Both need privileges the tests may not have.
+            Err(err) => match err.downcast_ref::<io::Error>() {
+                Some(err) => assert_eq!(err.kind(), io::ErrorKind::PermissionDenied),
+                None => panic!("unexpected error {}", err),
+            },
+        }
     }
 }
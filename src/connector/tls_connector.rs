@@ -0,0 +1,420 @@
+// MIT License
+
+// Copyright (c) 2019 Zhuhao Wang
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use super::Connector;
+use crate::core::{Endpoint, Result};
+use async_trait::async_trait;
+use futures::io::{AsyncRead, AsyncWrite};
+use futures_tokio_compat::Compat;
+use ring::digest;
+use std::{net::SocketAddr, sync::Arc};
+use tokio_rustls::{
+    client,
+    rustls::{
+        Certificate, ClientConfig, PrivateKey, RootCertStore, ServerCertVerified,
+        ServerCertVerifier, TLSError,
+    },
+    webpki::DNSNameRef,
+};
+
+/// The stream returned by `TlsConnector`, with the `futures` I/O traits.
+pub type TlsStream<T> = Compat<client::TlsStream<Compat<T>>>;
+
+#[derive(Debug)]
+pub enum TlsConnectorError {
+    /// The target is an IP address and no server name is configured.
+    MissingServerName,
+    InvalidServerName(String),
+    InvalidCertificate,
+}
+
+impl std::fmt::Display for TlsConnectorError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::result::Result<(), std::fmt::Error> {
+        write!(f, "{:#?}", self)
+    }
+}
+
+impl std::error::Error for TlsConnectorError {}
+
+/// Accepts the server only if the SHA-256 fingerprint of its certificate is
+/// pinned, regardless of who issued it.
+struct PinnedCertVerifier {
+    fingerprints: Vec<[u8; 32]>,
+}
+
+impl ServerCertVerifier for PinnedCertVerifier {
+    fn verify_server_cert(
+        &self,
+        _roots: &RootCertStore,
+        presented_certs: &[Certificate],
+        _dns_name: DNSNameRef,
+        _ocsp_response: &[u8],
+    ) -> std::result::Result<ServerCertVerified, TLSError> {
+        let cert = presented_certs
+            .first()
+            .ok_or(TLSError::NoCertificatesPresented)?;
+        let fingerprint = digest::digest(&digest::SHA256, &cert.0);
+
+        if self
+            .fingerprints
+            .iter()
+            .any(|pinned| pinned[..] == *fingerprint.as_ref())
+        {
+            Ok(ServerCertVerified::assertion())
+        } else {
+            Err(TLSError::General(
+                "the certificate fingerprint is not pinned".to_owned(),
+            ))
+        }
+    }
+}
+
+/// Wraps the stream of `inner` in TLS.
+///
+/// The server name sent in SNI and verified against the certificate is the
+/// hostname of the target unless one is configured.
+#[derive(Clone)]
+pub struct TlsConnector<C> {
+    inner: C,
+    config: Arc<ClientConfig>,
+    server_name: Option<String>,
+}
+
+impl<C> TlsConnector<C> {
+    /// Verifies the server against the Mozilla root certificates.
+    pub fn new(inner: C) -> Self {
+        let mut config = ClientConfig::new();
+        config
+            .root_store
+            .add_server_trust_anchors(&webpki_roots::TLS_SERVER_ROOTS);
+
+        TlsConnector {
+            inner,
+            config: Arc::new(config),
+            server_name: None,
+        }
+    }
+
+    pub fn builder(inner: C) -> TlsConnectorBuilder<C> {
+        TlsConnectorBuilder {
+            inner,
+            server_name: None,
+            alpn_protocols: Vec::new(),
+            root_certificates: None,
+            pinned_fingerprints: Vec::new(),
+            client_certificate: None,
+        }
+    }
+}
+
+pub struct TlsConnectorBuilder<C> {
+    inner: C,
+    server_name: Option<String>,
+    alpn_protocols: Vec<Vec<u8>>,
+    root_certificates: Option<Vec<Vec<u8>>>,
+    pinned_fingerprints: Vec<[u8; 32]>,
+    client_certificate: Option<(Vec<Vec<u8>>, Vec<u8>)>,
+}
+
+impl<C> TlsConnectorBuilder<C> {
+    /// Overrides the server name sent in SNI and verified against the
+    /// certificate.
+    pub fn server_name(mut self, name: &str) -> Self {
+        self.server_name = Some(name.to_owned());
+        self
+    }
+
+    /// Offers `protocols` with ALPN, in order of preference.
+    pub fn alpn_protocols(mut self, protocols: &[&str]) -> Self {
+        self.alpn_protocols = protocols.iter().map(|p| p.as_bytes().to_vec()).collect();
+        self
+    }
+
+    /// Trusts the DER encoded `cert` instead of the Mozilla root
+    /// certificates.
+    pub fn add_root_certificate(mut self, cert: Vec<u8>) -> Self {
+        self.root_certificates
+            .get_or_insert_with(Vec::new)
+            .push(cert);
+        self
+    }
+
+    /// Accepts only servers whose certificate has the SHA-256
+    /// `fingerprint`. Once a fingerprint is pinned, the root certificates
+    /// are no longer consulted.
+    pub fn pin_certificate(mut self, fingerprint: [u8; 32]) -> Self {
+        self.pinned_fingerprints.push(fingerprint);
+        self
+    }
+
+    /// Authenticates with the DER encoded certificate `chain` and PKCS#8 or
+    /// RSA `key` if the server asks for it.
+    pub fn client_certificate(mut self, chain: Vec<Vec<u8>>, key: Vec<u8>) -> Self {
+        self.client_certificate = Some((chain, key));
+        self
+    }
+
+    pub fn build(self) -> Result<TlsConnector<C>> {
+        if let Some(ref name) = self.server_name {
+            DNSNameRef::try_from_ascii_str(name)
+                .map_err(|_| TlsConnectorError::InvalidServerName(name.clone()))?;
+        }
+
+        let mut config = ClientConfig::new();
+        match self.root_certificates {
+            Some(certs) => {
+                for cert in certs {
+                    config
+                        .root_store
+                        .add(&Certificate(cert))
+                        .map_err(|_| TlsConnectorError::InvalidCertificate)?;
+                }
+            }
+            None => config
+                .root_store
+                .add_server_trust_anchors(&webpki_roots::TLS_SERVER_ROOTS),
+        }
+
+        if !self.pinned_fingerprints.is_empty() {
+            config
+                .dangerous()
+                .set_certificate_verifier(Arc::new(PinnedCertVerifier {
+                    fingerprints: self.pinned_fingerprints,
+                }));
+        }
+
+        config.set_protocols(&self.alpn_protocols);
+
+        if let Some((chain, key)) = self.client_certificate {
+            config.set_single_client_cert(
+                chain.into_iter().map(Certificate).collect(),
+                PrivateKey(key),
+            );
+        }
+
+        Ok(TlsConnector {
+            inner: self.inner,
+            config: Arc::new(config),
+            server_name: self.server_name,
+        })
+    }
+}
+
+#[async_trait]
+impl<C, T> Connector<TlsStream<T>> for TlsConnector<C>
+where
+    C: Connector<T> + Send + 'static,
+    T: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+{
+    async fn connect(self, endpoint: &Endpoint) -> Result<TlsStream<T>> {
+        let (io, _) = Connector::<TlsStream<T>>::connect_with_local_addr(self, endpoint).await?;
+        Ok(io)
+    }
+
+    async fn connect_with_local_addr(
+        self,
+        endpoint: &Endpoint,
+    ) -> Result<(TlsStream<T>, Option<SocketAddr>)> {
+        let server_name = match (self.server_name, endpoint) {
+            (Some(name), _) => name,
+            (None, Endpoint::HostName(hostname, _)) => hostname.clone(),
+            (None, Endpoint::Ip(_)) => return Err(TlsConnectorError::MissingServerName.into()),
+        };
+        let dns_name = DNSNameRef::try_from_ascii_str(&server_name)
+            .map_err(|_| TlsConnectorError::InvalidServerName(server_name.clone()))?;
+
+        let (io, local_addr) = self.inner.connect_with_local_addr(endpoint).await?;
+        let stream = tokio_rustls::TlsConnector::from(self.config)
+            .connect(dns_name, Compat::new(io))
+            .await?;
+        Ok((Compat::new(stream), local_addr))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        connector::mock::{LoopbackConnector, MockConnector},
+        io::mock::MockStream,
+    };
+    use futures::{executor::block_on, future};
+    use std::net::SocketAddr;
+    use tokio::{
+        net::{TcpListener, TcpStream},
+        runtime::Runtime,
+    };
+    use tokio_rustls::rustls::{NoClientAuth, ServerConfig, Session};
+
+    const CERT: &[u8] = include_bytes!("../../testdata/example.com.cert.der");
+    const KEY: &[u8] = include_bytes!("../../testdata/example.com.key.der");
+    const OTHER_CERT: &[u8] = include_bytes!("../../testdata/other.example.cert.der");
+
+    fn fingerprint(cert: &[u8]) -> [u8; 32] {
+        let mut fingerprint = [0; 32];
+        fingerprint.copy_from_slice(digest::digest(&digest::SHA256, cert).as_ref());
+        fingerprint
+    }
+
+    fn verify(verifier: &PinnedCertVerifier, certs: &[&[u8]]) -> bool {
+        let certs: Vec<_> = certs
+            .iter()
+            .map(|cert| Certificate(cert.to_vec()))
+            .collect();
+        verifier
+            .verify_server_cert(
+                &RootCertStore::empty(),
+                &certs,
+                DNSNameRef::try_from_ascii_str("example.com").unwrap(),
+                &[],
+            )
+            .is_ok()
+    }
+
+    #[test]
+    fn verifies_pinned_fingerprint() {
+        let verifier = PinnedCertVerifier {
+            fingerprints: vec![fingerprint(OTHER_CERT), fingerprint(CERT)],
+        };
+        assert!(verify(&verifier, &[CERT]));
+        assert!(verify(&verifier, &[OTHER_CERT, CERT]));
+
+        let verifier = PinnedCertVerifier {
+            fingerprints: vec![fingerprint(OTHER_CERT)],
+        };
+        assert!(!verify(&verifier, &[CERT]));
+        // Only the end-entity certificate counts.
+        assert!(!verify(&verifier, &[CERT, OTHER_CERT]));
+        assert!(!verify(&verifier, &[]));
+    }
+
+    #[test]
+    fn validates_builder_options() {
+        let builder = TlsConnector::builder(MockConnector::new(MockStream::new(b"")));
+        let err = builder.server_name("not a name").build().err().unwrap();
+        match err.downcast_ref::<TlsConnectorError>() {
+            Some(TlsConnectorError::InvalidServerName(name)) => assert_eq!(name, "not a name"),
+            _ => panic!("unexpected error {}", err),
+        }
+
+        let connector = TlsConnector::builder(MockConnector::new(MockStream::new(b"")))
+            .alpn_protocols(&["h2", "http/1.1"])
+            .build()
+            .unwrap();
+        assert_eq!(
+            connector.config.alpn_protocols,
+            vec![b"h2".to_vec(), b"http/1.1".to_vec()]
+        );
+    }
+
+    #[test]
+    fn requires_server_name_for_ip_target() {
+        let connector = TlsConnector::new(MockConnector::new(MockStream::new(b"")));
+        let endpoint = Endpoint::Ip("127.0.0.1:443".parse().unwrap());
+        let err = block_on(Connector::<TlsStream<MockStream>>::connect(
+            connector, &endpoint,
+        ))
+        .err()
+        .unwrap();
+        match err.downcast_ref::<TlsConnectorError>() {
+            Some(TlsConnectorError::MissingServerName) => {}
+            _ => panic!("unexpected error {}", err),
+        }
+    }
+
+    /// Connects to a local TLS server offering `h2` with `connector`,
+    /// returning whether the client succeeded along with the server name
+    /// and ALPN protocol the server saw.
+    fn handshake(
+        connector: TlsConnector<LoopbackConnector>,
+    ) -> (bool, Option<String>, Option<Vec<u8>>) {
+        Runtime::new().unwrap().block_on(async move {
+            let mut config = ServerConfig::new(NoClientAuth::new());
+            config
+                .set_single_cert(vec![Certificate(CERT.to_vec())], PrivateKey(KEY.to_vec()))
+                .unwrap();
+            config.set_protocols(&[b"h2".to_vec()]);
+
+            let addr: SocketAddr = "127.0.0.1:0".parse().unwrap();
+            let mut listener = TcpListener::bind(&addr).unwrap();
+            let addr = listener.local_addr().unwrap();
+
+            let server = async move {
+                let (stream, _) = listener.accept().await.unwrap();
+                let stream = tokio_rustls::TlsAcceptor::from(Arc::new(config))
+                    .accept(stream)
+                    .await
+                    .ok()?;
+                let (_, session) = stream.get_ref();
+                Some((
+                    session.get_sni_hostname().map(ToOwned::to_owned),
+                    session.get_alpn_protocol().map(ToOwned::to_owned),
+                ))
+            };
+            let endpoint = Endpoint::Ip(addr);
+            let client = Connector::<TlsStream<Compat<TcpStream>>>::connect(connector, &endpoint);
+
+            let (server, client) = future::join(server, client).await;
+            let (sni, alpn) = server.unwrap_or((None, None));
+            (client.is_ok(), sni, alpn)
+        })
+    }
+
+    #[test]
+    fn connects_to_pinned_server() {
+        let connector = TlsConnector::builder(LoopbackConnector)
+            .server_name("example.com")
+            .pin_certificate(fingerprint(CERT))
+            .alpn_protocols(&["h2", "http/1.1"])
+            .build()
+            .unwrap();
+
+        let (connected, sni, alpn) = handshake(connector);
+        assert!(connected);
+        assert_eq!(sni.as_ref().map(String::as_str), Some("example.com"));
+        assert_eq!(alpn, Some(b"h2".to_vec()));
+    }
+
+    #[test]
+    fn rejects_unpinned_server() {
+        let connector = TlsConnector::builder(LoopbackConnector)
+            .server_name("example.com")
+            .pin_certificate(fingerprint(OTHER_CERT))
+            .build()
+            .unwrap();
+
+        let (connected, _, _) = handshake(connector);
+        assert!(!connected);
+    }
+
+    #[test]
+    fn rejects_untrusted_server() {
+        // Self-signed, so not issued by any of the Mozilla roots.
+        let connector = TlsConnector::builder(LoopbackConnector)
+            .server_name("example.com")
+            .build()
+            .unwrap();
+
+        let (connected, _, _) = handshake(connector);
+        assert!(!connected);
+    }
+}
@@ -0,0 +1,72 @@
+// MIT License
+
+// Copyright (c) 2019 Zhuhao Wang
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use super::Connector;
+use crate::{
+    core::{encode_socks_addr, Endpoint, Error, Result},
+    trojan::{password_hash, CMD_CONNECT},
+};
+use async_trait::async_trait;
+use futures::{
+    future::TryFutureExt,
+    io::{AsyncRead, AsyncWrite, AsyncWriteExt},
+};
+
+/// Connects to the target through a Trojan server reached with `inner`,
+/// which should be a `TlsConnector`.
+#[derive(Clone)]
+pub struct TrojanConnector<C> {
+    inner: C,
+    server: Endpoint,
+    password_hash: String,
+}
+
+impl<C> TrojanConnector<C> {
+    pub fn new(inner: C, server: Endpoint, password: &str) -> Self {
+        TrojanConnector {
+            inner,
+            server,
+            password_hash: password_hash(password),
+        }
+    }
+}
+
+#[async_trait]
+impl<C, T> Connector<T> for TrojanConnector<C>
+where
+    C: Connector<T> + Send + 'static,
+    T: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+{
+    async fn connect(self, endpoint: &Endpoint) -> Result<T> {
+        let mut io = self.inner.connect(&self.server).await?;
+
+        let mut buf = self.password_hash.into_bytes();
+        buf.extend_from_slice(b"\r\n");
+        buf.push(CMD_CONNECT);
+        encode_socks_addr(endpoint, &mut buf)?;
+        buf.extend_from_slice(b"\r\n");
+
+        io.write_all(&buf).err_into::<Error>().await?;
+        io.flush().err_into::<Error>().await?;
+        Ok(io)
+    }
+}
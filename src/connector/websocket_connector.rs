@@ -0,0 +1,112 @@
+// MIT License
+
+// Copyright (c) 2019 Zhuhao Wang
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use super::{
+    http1::{encode_headers, read_response_head},
+    Connector,
+};
+use crate::{
+    core::{Endpoint, Error, Result},
+    io::Prefixed,
+    websocket::{accept_key, random_bytes, Role, WebSocketError, WebSocketStream},
+};
+use async_trait::async_trait;
+use futures::{
+    future::TryFutureExt,
+    io::{AsyncRead, AsyncWrite, AsyncWriteExt},
+};
+use http::header::{HeaderMap, HeaderName, HeaderValue};
+
+/// Upgrades the stream of `inner` to a WebSocket connection and carries the
+/// data in binary frames.
+///
+/// `Host` is the target endpoint unless one is set, e.g., to go through a
+/// CDN by connecting to its address while naming the origin.
+#[derive(Clone)]
+pub struct WebSocketConnector<C> {
+    inner: C,
+    path: String,
+    host: Option<String>,
+    headers: HeaderMap,
+}
+
+impl<C> WebSocketConnector<C> {
+    pub fn new(inner: C, path: &str) -> Self {
+        WebSocketConnector {
+            inner,
+            path: path.to_owned(),
+            host: None,
+            headers: HeaderMap::new(),
+        }
+    }
+
+    pub fn host(mut self, host: &str) -> Self {
+        self.host = Some(host.to_owned());
+        self
+    }
+
+    /// Adds a header to the upgrade request.
+    pub fn header(mut self, name: HeaderName, value: HeaderValue) -> Self {
+        self.headers.append(name, value);
+        self
+    }
+}
+
+#[async_trait]
+impl<C, T> Connector<WebSocketStream<Prefixed<T>>> for WebSocketConnector<C>
+where
+    C: Connector<T> + Send + 'static,
+    T: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+{
+    async fn connect(self, endpoint: &Endpoint) -> Result<WebSocketStream<Prefixed<T>>> {
+        let mut io = self.inner.connect(endpoint).await?;
+
+        let key = base64::encode(&random_bytes(16));
+        let host = self.host.unwrap_or_else(|| endpoint.to_string());
+        let mut request = format!(
+            "GET {} HTTP/1.1\r\nHost: {}\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+             Sec-WebSocket-Key: {}\r\nSec-WebSocket-Version: 13\r\n",
+            self.path, host, key
+        )
+        .into_bytes();
+        encode_headers(&self.headers, &mut request);
+        request.extend_from_slice(b"\r\n");
+
+        io.write_all(&request).err_into::<Error>().await?;
+        io.flush().err_into::<Error>().await?;
+
+        let (head, rest) = read_response_head(&mut io).await?;
+        if head.status != 101 {
+            return Err(WebSocketError::UpgradeRejected {
+                status: head.status,
+                status_line: head.status_line,
+            }
+            .into());
+        }
+
+        if head.header("Sec-WebSocket-Accept") != Some(accept_key(key.as_bytes()).as_str()) {
+            return Err(WebSocketError::InvalidAcceptKey.into());
+        }
+
+        Ok(WebSocketStream::new(Prefixed::new(io, rest), Role::Client))
+    }
+}
@@ -37,3 +37,53 @@ impl Endpoint {
         Endpoint::Ip(ip)
     }
 }
+
+/// Formats the endpoint as an authority, e.g., `example.com:443` or
+/// `[::1]:443`. A host name containing `:`, i.e., an IPv6 literal that was
+/// not parsed, is bracketed as well.
+impl std::fmt::Display for Endpoint {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::result::Result<(), std::fmt::Error> {
+        match *self {
+            Endpoint::HostName(ref hostname, port)
+                if hostname.contains(':') && !hostname.starts_with('[') =>
+            {
+                write!(f, "[{}]:{}", hostname, port)
+            }
+            Endpoint::HostName(ref hostname, port) => write!(f, "{}:{}", hostname, port),
+            Endpoint::Ip(ref addr) => write!(f, "{}", addr),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_as_authority() {
+        assert_eq!(
+            Endpoint::new_from_hostname("example.com", 443).to_string(),
+            "example.com:443"
+        );
+        assert_eq!(
+            Endpoint::new_from_addr("127.0.0.1:80".parse().unwrap()).to_string(),
+            "127.0.0.1:80"
+        );
+        assert_eq!(
+            Endpoint::new_from_addr("[::1]:80".parse().unwrap()).to_string(),
+            "[::1]:80"
+        );
+    }
+
+    #[test]
+    fn brackets_ipv6_host_names() {
+        assert_eq!(
+            Endpoint::new_from_hostname("2001:db8::1", 443).to_string(),
+            "[2001:db8::1]:443"
+        );
+        assert_eq!(
+            Endpoint::new_from_hostname("[2001:db8::1]", 443).to_string(),
+            "[2001:db8::1]:443"
+        );
+    }
+}
@@ -27,3 +27,9 @@ pub type Result<T> = StdResult<T, Error>;
 
 mod endpoint;
 pub use self::endpoint::Endpoint;
+
+mod socks_addr;
+pub use self::socks_addr::{decode_socks_addr, encode_socks_addr, read_socks_addr, SocksAddrError};
+
+mod socks_reply;
+pub use self::socks_reply::Socks5Reply;
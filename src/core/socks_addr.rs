@@ -0,0 +1,211 @@
+// MIT License
+
+// Copyright (c) 2019 Zhuhao Wang
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! The `ATYP | ADDR | PORT` address format shared by SOCKS5 and the
+//! protocols derived from it.
+
+use crate::core::{Endpoint, Error, Result};
+use futures::{
+    future::TryFutureExt,
+    io::{AsyncRead, AsyncReadExt},
+};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+#[derive(Debug)]
+pub enum SocksAddrError {
+    UnsupportedAddressType(u8),
+    Truncated,
+    DomainTooLong(String),
+}
+
+impl std::fmt::Display for SocksAddrError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::result::Result<(), std::fmt::Error> {
+        write!(f, "{:#?}", self)
+    }
+}
+
+impl std::error::Error for SocksAddrError {}
+
+pub async fn read_socks_addr<T: AsyncRead + Unpin>(io: &mut T) -> Result<Endpoint> {
+    let mut buf = [0; 1];
+    io.read_exact(&mut buf).err_into::<Error>().await?;
+
+    let ip = match buf[0] {
+        1 => {
+            let mut buf = [0; 4];
+            io.read_exact(&mut buf).err_into::<Error>().await?;
+            IpAddr::from(buf)
+        }
+        3 => {
+            let mut buf = [0; 1];
+            io.read_exact(&mut buf).err_into::<Error>().await?;
+
+            let mut buf = vec![0; buf[0].into()];
+            io.read_exact(&mut buf).err_into::<Error>().await?;
+            let domain = String::from_utf8(buf).map_err(Into::<Error>::into)?;
+
+            let mut buf = [0; 2];
+            io.read_exact(&mut buf).err_into::<Error>().await?;
+            return Ok(Endpoint::new_from_hostname(
+                &domain,
+                u16::from_be_bytes(buf),
+            ));
+        }
+        4 => {
+            let mut buf = [0; 16];
+            io.read_exact(&mut buf).err_into::<Error>().await?;
+            IpAddr::from(buf)
+        }
+        t => return Err(SocksAddrError::UnsupportedAddressType(t).into()),
+    };
+
+    let mut buf = [0; 2];
+    io.read_exact(&mut buf).err_into::<Error>().await?;
+    Ok(Endpoint::new_from_addr(SocketAddr::new(
+        ip,
+        u16::from_be_bytes(buf),
+    )))
+}
+
+/// Decodes an address at the start of `buf`, returning it along with the
+/// number of bytes consumed.
+pub fn decode_socks_addr(buf: &[u8]) -> Result<(Endpoint, usize)> {
+    let (ip, len) = match buf.first() {
+        Some(1) if buf.len() >= 7 => {
+            let mut octets = [0; 4];
+            octets.copy_from_slice(&buf[1..5]);
+            (IpAddr::from(Ipv4Addr::from(octets)), 5)
+        }
+        Some(3) if buf.len() >= 2 && buf.len() >= 4 + usize::from(buf[1]) => {
+            let len = 2 + usize::from(buf[1]);
+            let domain = String::from_utf8(buf[2..len].to_vec()).map_err(Into::<Error>::into)?;
+            let port = u16::from_be_bytes([buf[len], buf[len + 1]]);
+            return Ok((Endpoint::new_from_hostname(&domain, port), len + 2));
+        }
+        Some(4) if buf.len() >= 19 => {
+            let mut octets = [0; 16];
+            octets.copy_from_slice(&buf[1..17]);
+            (IpAddr::from(Ipv6Addr::from(octets)), 17)
+        }
+        Some(1) | Some(3) | Some(4) | None => return Err(SocksAddrError::Truncated.into()),
+        Some(t) => return Err(SocksAddrError::UnsupportedAddressType(*t).into()),
+    };
+
+    let port = u16::from_be_bytes([buf[len], buf[len + 1]]);
+    Ok((Endpoint::new_from_addr(SocketAddr::new(ip, port)), len + 2))
+}
+
+pub fn encode_socks_addr(endpoint: &Endpoint, buf: &mut Vec<u8>) -> Result<()> {
+    match *endpoint {
+        Endpoint::Ip(SocketAddr::V4(ref addr)) => {
+            buf.push(1);
+            buf.extend_from_slice(&addr.ip().octets());
+            buf.extend_from_slice(&addr.port().to_be_bytes());
+        }
+        Endpoint::Ip(SocketAddr::V6(ref addr)) => {
+            buf.push(4);
+            buf.extend_from_slice(&addr.ip().octets());
+            buf.extend_from_slice(&addr.port().to_be_bytes());
+        }
+        Endpoint::HostName(ref hostname, port) => {
+            if hostname.len() > 255 {
+                return Err(SocksAddrError::DomainTooLong(hostname.clone()).into());
+            }
+            buf.push(3);
+            buf.push(hostname.len() as u8);
+            buf.extend_from_slice(hostname.as_bytes());
+            buf.extend_from_slice(&port.to_be_bytes());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::executor::block_on;
+
+    fn round_trip(endpoint: Endpoint) {
+        let mut buf = Vec::new();
+        encode_socks_addr(&endpoint, &mut buf).unwrap();
+        buf.extend_from_slice(b"rest");
+
+        let (decoded, len) = decode_socks_addr(&buf).unwrap();
+        assert_eq!(decoded.to_string(), endpoint.to_string());
+        assert_eq!(&buf[len..], b"rest");
+
+        let decoded = block_on(read_socks_addr(&mut &buf[..])).unwrap();
+        assert_eq!(decoded.to_string(), endpoint.to_string());
+    }
+
+    #[test]
+    fn round_trips_ipv4() {
+        round_trip(Endpoint::new_from_addr("127.0.0.1:80".parse().unwrap()));
+    }
+
+    #[test]
+    fn round_trips_ipv6() {
+        round_trip(Endpoint::new_from_addr(
+            "[2001:db8::1]:443".parse().unwrap(),
+        ));
+    }
+
+    #[test]
+    fn round_trips_domain() {
+        round_trip(Endpoint::new_from_hostname("example.com", 8080));
+    }
+
+    #[test]
+    fn rejects_truncated_addresses() {
+        for buf in &[
+            &[][..],
+            &[1, 127, 0, 0, 1, 0][..],
+            &[3, 11, b'e', b'x', 0, 80][..],
+            &[4, 0, 0, 0][..],
+        ] {
+            match decode_socks_addr(buf) {
+                Err(err) => match err.downcast_ref::<SocksAddrError>() {
+                    Some(SocksAddrError::Truncated) => {}
+                    _ => panic!("unexpected error {}", err),
+                },
+                Ok(_) => panic!("decoded truncated address {:?}", buf),
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_unknown_address_type() {
+        let err = decode_socks_addr(&[2, 0, 0]).unwrap_err();
+        match err.downcast_ref::<SocksAddrError>() {
+            Some(SocksAddrError::UnsupportedAddressType(2)) => {}
+            _ => panic!("unexpected error {}", err),
+        }
+
+        assert!(block_on(read_socks_addr(&mut &[2, 0, 0][..])).is_err());
+    }
+
+    #[test]
+    fn rejects_long_domain() {
+        let endpoint = Endpoint::new_from_hostname(&"a".repeat(256), 80);
+        assert!(encode_socks_addr(&endpoint, &mut Vec::new()).is_err());
+    }
+}
@@ -0,0 +1,79 @@
+// MIT License
+
+// Copyright (c) 2019 Zhuhao Wang
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+/// The reply codes defined in RFC 1928.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Socks5Reply {
+    Succeeded,
+    GeneralFailure,
+    NotAllowedByRuleset,
+    NetworkUnreachable,
+    HostUnreachable,
+    ConnectionRefused,
+    TtlExpired,
+    CommandNotSupported,
+    AddressTypeNotSupported,
+}
+
+impl Socks5Reply {
+    pub fn code(self) -> u8 {
+        match self {
+            Socks5Reply::Succeeded => 0,
+            Socks5Reply::GeneralFailure => 1,
+            Socks5Reply::NotAllowedByRuleset => 2,
+            Socks5Reply::NetworkUnreachable => 3,
+            Socks5Reply::HostUnreachable => 4,
+            Socks5Reply::ConnectionRefused => 5,
+            Socks5Reply::TtlExpired => 6,
+            Socks5Reply::CommandNotSupported => 7,
+            Socks5Reply::AddressTypeNotSupported => 8,
+        }
+    }
+
+    pub fn from_code(code: u8) -> Option<Self> {
+        match code {
+            0 => Some(Socks5Reply::Succeeded),
+            1 => Some(Socks5Reply::GeneralFailure),
+            2 => Some(Socks5Reply::NotAllowedByRuleset),
+            3 => Some(Socks5Reply::NetworkUnreachable),
+            4 => Some(Socks5Reply::HostUnreachable),
+            5 => Some(Socks5Reply::ConnectionRefused),
+            6 => Some(Socks5Reply::TtlExpired),
+            7 => Some(Socks5Reply::CommandNotSupported),
+            8 => Some(Socks5Reply::AddressTypeNotSupported),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn codes_round_trip() {
+        for code in 0..=8 {
+            assert_eq!(Socks5Reply::from_code(code).unwrap().code(), code);
+        }
+        assert!(Socks5Reply::from_code(9).is_none());
+    }
+}
@@ -0,0 +1,216 @@
+// MIT License
+
+// Copyright (c) 2019 Zhuhao Wang
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use bytes::Bytes;
+use futures::{
+    io::{AsyncRead, AsyncWrite},
+    ready,
+    task::{Context, Poll},
+};
+use h2::{RecvStream, SendStream};
+use std::{io, pin::Pin};
+
+fn into_io_error(err: h2::Error) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, err)
+}
+
+/// A tunnel carried in the body of an HTTP/2 CONNECT stream.
+pub struct H2Stream {
+    recv: RecvStream,
+    send: SendStream<Bytes>,
+    buf: Bytes,
+}
+
+impl H2Stream {
+    pub(crate) fn new(recv: RecvStream, send: SendStream<Bytes>) -> Self {
+        H2Stream {
+            recv,
+            send,
+            buf: Bytes::new(),
+        }
+    }
+}
+
+impl AsyncRead for H2Stream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        if self.buf.is_empty() {
+            self.buf = match ready!(self.recv.poll_data(cx)) {
+                Some(Ok(data)) => data,
+                Some(Err(err)) => return Poll::Ready(Err(into_io_error(err))),
+                None => return Poll::Ready(Ok(0)),
+            };
+        }
+
+        let len = buf.len().min(self.buf.len());
+        let data = self.buf.split_to(len);
+        buf[..len].copy_from_slice(&data);
+
+        // Let the peer send more once the data has been consumed.
+        self.recv
+            .flow_control()
+            .release_capacity(len)
+            .map_err(into_io_error)?;
+        Poll::Ready(Ok(len))
+    }
+}
+
+impl AsyncWrite for H2Stream {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        if buf.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+
+        // The capacity granted may be more than asked for if an earlier
+        // reservation was larger, or zero if it was taken back. In that case
+        // the task yields, and polling again on the next write parks it until
+        // more is assigned.
+        self.send.reserve_capacity(buf.len());
+        let len = match ready!(self.send.poll_capacity(cx)) {
+            Some(Ok(0)) => {
+                cx.waker().wake_by_ref();
+                return Poll::Pending;
+            }
+            Some(Ok(capacity)) => capacity.min(buf.len()),
+            Some(Err(err)) => return Poll::Ready(Err(into_io_error(err))),
+            None => return Poll::Ready(Err(io::ErrorKind::BrokenPipe.into())),
+        };
+
+        self.send
+            .send_data(Bytes::from(buf[..len].to_vec()), false)
+            .map_err(into_io_error)?;
+        Poll::Ready(Ok(len))
+    }
+
+    /// Data is flushed by the connection, which runs on its own.
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    /// Ends the stream; data from the peer can still be read.
+    fn poll_close(mut self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.send
+            .send_data(Bytes::new(), true)
+            .map_err(into_io_error)?;
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::mock::duplex;
+    use futures::{
+        channel::oneshot,
+        io::{AsyncReadExt, AsyncWriteExt},
+    };
+    use futures_tokio_compat::Compat;
+    use h2::{client, server};
+    use http::{Method, Request, Response};
+    use std::time::{Duration, Instant};
+    use tokio::{runtime::Runtime, timer::delay};
+
+    /// Opens a CONNECT stream between an h2 client and a server whose
+    /// streams start with a window of `window` bytes.
+    async fn connect_stream(window: u32) -> (H2Stream, H2Stream) {
+        let (client_io, server_io) = duplex();
+        let (sender, receiver) = oneshot::channel();
+        tokio::spawn(async move {
+            let mut connection = server::Builder::new()
+                .initial_window_size(window)
+                .handshake(Compat::new(server_io))
+                .await
+                .unwrap();
+            let (request, mut respond) = connection.accept().await.unwrap().unwrap();
+            let send = respond.send_response(Response::new(()), false).unwrap();
+            sender.send(H2Stream::new(request.into_body(), send)).ok();
+            while let Some(_) = connection.accept().await {}
+        });
+
+        let (send_request, connection) = client::handshake(Compat::new(client_io)).await.unwrap();
+        tokio::spawn(async move {
+            let _ = connection.await;
+        });
+        let request = Request::builder()
+            .method(Method::CONNECT)
+            .uri("example.com:443")
+            .body(())
+            .unwrap();
+        let (response, send) = send_request
+            .ready()
+            .await
+            .unwrap()
+            .send_request(request, false)
+            .unwrap();
+        let recv = response.await.unwrap().into_body();
+        (H2Stream::new(recv, send), receiver.await.unwrap())
+    }
+
+    #[test]
+    fn waits_for_window() {
+        Runtime::new().unwrap().block_on(async {
+            let (mut client, mut server) = connect_stream(5).await;
+
+            let (done, mut written) = oneshot::channel();
+            tokio::spawn(async move {
+                client.write_all(b"hello world").await.unwrap();
+                done.send(client).ok();
+            });
+
+            // Only the first five bytes fit in the window until the server
+            // reads them.
+            delay(Instant::now() + Duration::from_millis(50)).await;
+            assert!(written.try_recv().unwrap().is_none());
+
+            let mut buf = [0; 11];
+            server.read_exact(&mut buf).await.unwrap();
+            assert_eq!(&buf, b"hello world");
+            written.await.unwrap();
+        });
+    }
+
+    #[test]
+    fn half_closes() {
+        Runtime::new().unwrap().block_on(async {
+            let (mut client, mut server) = connect_stream(65_535).await;
+
+            client.write_all(b"ping").await.unwrap();
+            client.close().await.unwrap();
+            let mut buf = Vec::new();
+            server.read_to_end(&mut buf).await.unwrap();
+            assert_eq!(buf, b"ping");
+
+            server.write_all(b"pong").await.unwrap();
+            server.close().await.unwrap();
+            buf.clear();
+            client.read_to_end(&mut buf).await.unwrap();
+            assert_eq!(buf, b"pong");
+        });
+    }
+}
@@ -0,0 +1,191 @@
+// MIT License
+
+// Copyright (c) 2019 Zhuhao Wang
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use futures::{
+    io::{AsyncRead, AsyncWrite},
+    task::{Context, Poll, Waker},
+};
+use std::{
+    io,
+    pin::Pin,
+    sync::{Arc, Mutex},
+};
+
+/// An in-memory stream for tests, reading from a fixed input and recording
+/// everything written to it.
+pub(crate) struct MockStream {
+    input: Vec<u8>,
+    pos: usize,
+    open: bool,
+    output: Arc<Mutex<Vec<u8>>>,
+}
+
+impl MockStream {
+    pub(crate) fn new(input: &[u8]) -> Self {
+        MockStream {
+            input: input.to_vec(),
+            pos: 0,
+            open: false,
+            output: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    /// Like `new`, but reading past the input waits forever instead of
+    /// reporting the end of the stream, like a peer that keeps it open.
+    pub(crate) fn open(input: &[u8]) -> Self {
+        MockStream {
+            open: true,
+            ..MockStream::new(input)
+        }
+    }
+
+    /// The bytes written so far, still readable after the stream is moved.
+    pub(crate) fn output(&self) -> Arc<Mutex<Vec<u8>>> {
+        self.output.clone()
+    }
+}
+
+impl AsyncRead for MockStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        if self.open && self.pos == self.input.len() && !buf.is_empty() {
+            return Poll::Pending;
+        }
+
+        let len = buf.len().min(self.input.len() - self.pos);
+        buf[..len].copy_from_slice(&self.input[self.pos..self.pos + len]);
+        self.pos += len;
+        Poll::Ready(Ok(len))
+    }
+}
+
+impl AsyncWrite for MockStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        self.output.lock().unwrap().extend_from_slice(buf);
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// One direction of a `duplex` pair.
+#[derive(Default)]
+struct Pipe {
+    buf: Vec<u8>,
+    closed: bool,
+    read_waker: Option<Waker>,
+}
+
+/// An in-memory connection for tests, created with `duplex`. Dropping or
+/// closing one end lets the other read the end of the stream.
+pub(crate) struct DuplexStream {
+    read: Arc<Mutex<Pipe>>,
+    write: Arc<Mutex<Pipe>>,
+}
+
+/// Returns both ends of an in-memory connection.
+pub(crate) fn duplex() -> (DuplexStream, DuplexStream) {
+    let a = Arc::new(Mutex::new(Pipe::default()));
+    let b = Arc::new(Mutex::new(Pipe::default()));
+    (
+        DuplexStream {
+            read: a.clone(),
+            write: b.clone(),
+        },
+        DuplexStream { read: b, write: a },
+    )
+}
+
+fn close(pipe: &Mutex<Pipe>) {
+    let mut pipe = pipe.lock().unwrap();
+    pipe.closed = true;
+    if let Some(waker) = pipe.read_waker.take() {
+        waker.wake();
+    }
+}
+
+impl AsyncRead for DuplexStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let mut pipe = self.read.lock().unwrap();
+        if pipe.buf.is_empty() && !pipe.closed && !buf.is_empty() {
+            pipe.read_waker = Some(cx.waker().clone());
+            return Poll::Pending;
+        }
+
+        let len = buf.len().min(pipe.buf.len());
+        buf[..len].copy_from_slice(&pipe.buf[..len]);
+        pipe.buf.drain(..len);
+        Poll::Ready(Ok(len))
+    }
+}
+
+impl AsyncWrite for DuplexStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let mut pipe = self.write.lock().unwrap();
+        if pipe.closed {
+            return Poll::Ready(Err(io::ErrorKind::BrokenPipe.into()));
+        }
+
+        pipe.buf.extend_from_slice(buf);
+        if let Some(waker) = pipe.read_waker.take() {
+            waker.wake();
+        }
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        close(&self.write);
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl Drop for DuplexStream {
+    fn drop(&mut self) {
+        close(&self.read);
+        close(&self.write);
+    }
+}
@@ -26,6 +26,21 @@ use futures::{
     io::{AsyncRead, AsyncReadExt, AsyncWrite},
 };
 
+mod h2_stream;
+#[cfg(test)]
+pub(crate) mod mock;
+mod prefixed;
+pub use self::h2_stream::H2Stream;
+pub use self::prefixed::Prefixed;
+
+/// A byte stream whose concrete type has been erased, e.g., when the
+/// layering is only known at runtime.
+pub trait Io: AsyncRead + AsyncWrite + Send + Unpin {}
+
+impl<T: AsyncRead + AsyncWrite + Send + Unpin> Io for T {}
+
+pub type BoxedIo = Box<dyn Io>;
+
 pub async fn forward<P1: AsyncRead + AsyncWrite + Send, P2: AsyncRead + AsyncWrite + Send>(
     p1: P1,
     p2: P2,
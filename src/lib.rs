@@ -24,4 +24,9 @@ pub mod acceptor;
 pub mod connector;
 pub mod core;
 pub mod io;
+pub mod mux;
+pub mod proxy_protocol;
 pub mod resolver;
+pub mod shadowsocks;
+pub mod trojan;
+pub mod websocket;
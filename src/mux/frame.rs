@@ -0,0 +1,225 @@
+// MIT License
+
+// Copyright (c) 2019 Zhuhao Wang
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use super::MuxError;
+use crate::core::{Error, Result};
+use futures::{
+    future::TryFutureExt,
+    io::{AsyncRead, AsyncReadExt},
+};
+
+/// The frame layout is the one of yamux: a 12 byte header of version, type,
+/// flags, stream ID and length, followed by the payload for data frames.
+pub(super) const HEADER_LEN: usize = 12;
+const VERSION: u8 = 0;
+
+pub(super) const TYPE_DATA: u8 = 0;
+pub(super) const TYPE_WINDOW_UPDATE: u8 = 1;
+pub(super) const TYPE_PING: u8 = 2;
+pub(super) const TYPE_GO_AWAY: u8 = 3;
+
+/// The window every stream starts with on both ends. A larger window is
+/// announced with the SYN or ACK of the stream.
+pub(super) const DEFAULT_WINDOW: u32 = 256 * 1024;
+
+pub(super) const FLAG_SYN: u16 = 1;
+pub(super) const FLAG_ACK: u16 = 2;
+pub(super) const FLAG_FIN: u16 = 4;
+pub(super) const FLAG_RST: u16 = 8;
+
+pub(super) struct Frame {
+    pub(super) frame_type: u8,
+    pub(super) flags: u16,
+    pub(super) stream_id: u32,
+    /// The payload length of data frames, the window increment of window
+    /// updates and the opaque value of pings.
+    pub(super) length: u32,
+    pub(super) payload: Vec<u8>,
+}
+
+impl Frame {
+    pub(super) fn data(stream_id: u32, flags: u16, payload: Vec<u8>) -> Self {
+        Frame {
+            frame_type: TYPE_DATA,
+            flags,
+            stream_id,
+            length: payload.len() as u32,
+            payload,
+        }
+    }
+
+    pub(super) fn window_update(stream_id: u32, flags: u16, delta: u32) -> Self {
+        Frame {
+            frame_type: TYPE_WINDOW_UPDATE,
+            flags,
+            stream_id,
+            length: delta,
+            payload: Vec::new(),
+        }
+    }
+
+    pub(super) fn ping(flags: u16, opaque: u32) -> Self {
+        Frame {
+            frame_type: TYPE_PING,
+            flags,
+            stream_id: 0,
+            length: opaque,
+            payload: Vec::new(),
+        }
+    }
+
+    pub(super) fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(HEADER_LEN + self.payload.len());
+        buf.push(VERSION);
+        buf.push(self.frame_type);
+        buf.extend_from_slice(&self.flags.to_be_bytes());
+        buf.extend_from_slice(&self.stream_id.to_be_bytes());
+        buf.extend_from_slice(&self.length.to_be_bytes());
+        buf.extend_from_slice(&self.payload);
+        buf
+    }
+}
+
+/// Reads a frame, rejecting data frames longer than `max_payload_len`.
+pub(super) async fn read_frame<T: AsyncRead + Unpin>(
+    io: &mut T,
+    max_payload_len: u32,
+) -> Result<Frame> {
+    let mut buf = [0; HEADER_LEN];
+    io.read_exact(&mut buf).err_into::<Error>().await?;
+
+    if buf[0] != VERSION {
+        return Err(MuxError::UnsupportedVersion(buf[0]).into());
+    }
+
+    let frame_type = buf[1];
+    if frame_type > TYPE_GO_AWAY {
+        return Err(MuxError::UnknownFrameType(frame_type).into());
+    }
+
+    let flags = u16::from_be_bytes([buf[2], buf[3]]);
+    let stream_id = u32::from_be_bytes([buf[4], buf[5], buf[6], buf[7]]);
+    let length = u32::from_be_bytes([buf[8], buf[9], buf[10], buf[11]]);
+
+    let payload = if frame_type == TYPE_DATA {
+        if length > max_payload_len {
+            return Err(MuxError::FrameTooLarge(length).into());
+        }
+        let mut payload = vec![0; length as usize];
+        io.read_exact(&mut payload).err_into::<Error>().await?;
+        payload
+    } else {
+        Vec::new()
+    };
+
+    Ok(Frame {
+        frame_type,
+        flags,
+        stream_id,
+        length,
+        payload,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::mock::MockStream;
+    use futures::executor::block_on;
+
+    fn read(input: &[u8], max_payload_len: u32) -> Result<Frame> {
+        block_on(read_frame(&mut MockStream::new(input), max_payload_len))
+    }
+
+    #[test]
+    fn encodes_frames() {
+        assert_eq!(
+            Frame::data(3, FLAG_SYN, b"hi".to_vec()).encode(),
+            [0, 0, 0, 1, 0, 0, 0, 3, 0, 0, 0, 2, b'h', b'i']
+        );
+        assert_eq!(
+            Frame::window_update(5, FLAG_ACK, 0x0001_0000).encode(),
+            [0, 1, 0, 2, 0, 0, 0, 5, 0, 1, 0, 0]
+        );
+        assert_eq!(
+            Frame::ping(FLAG_SYN, 7).encode(),
+            [0, 2, 0, 1, 0, 0, 0, 0, 0, 0, 0, 7]
+        );
+    }
+
+    #[test]
+    fn reads_encoded_frames() {
+        let mut input = Frame::data(1, FLAG_FIN, b"data".to_vec()).encode();
+        input.extend_from_slice(&Frame::window_update(1, 0, 1024).encode());
+        let mut io = MockStream::new(&input);
+
+        let frame = block_on(read_frame(&mut io, 16)).unwrap();
+        assert_eq!(frame.frame_type, TYPE_DATA);
+        assert_eq!(frame.flags, FLAG_FIN);
+        assert_eq!(frame.stream_id, 1);
+        assert_eq!(frame.length, 4);
+        assert_eq!(frame.payload, b"data");
+
+        let frame = block_on(read_frame(&mut io, 16)).unwrap();
+        assert_eq!(frame.frame_type, TYPE_WINDOW_UPDATE);
+        assert_eq!(frame.length, 1024);
+        assert!(frame.payload.is_empty());
+    }
+
+    #[test]
+    fn rejects_invalid_frames() {
+        let err = read(&[1, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0], 16)
+            .err()
+            .unwrap();
+        match err.downcast_ref::<MuxError>() {
+            Some(MuxError::UnsupportedVersion(1)) => {}
+            _ => panic!("unexpected error {}", err),
+        }
+
+        let err = read(&[0, 4, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0], 16)
+            .err()
+            .unwrap();
+        match err.downcast_ref::<MuxError>() {
+            Some(MuxError::UnknownFrameType(4)) => {}
+            _ => panic!("unexpected error {}", err),
+        }
+
+        let err = read(&Frame::data(1, 0, vec![0; 17]).encode(), 16)
+            .err()
+            .unwrap();
+        match err.downcast_ref::<MuxError>() {
+            Some(MuxError::FrameTooLarge(17)) => {}
+            _ => panic!("unexpected error {}", err),
+        }
+
+        // A window update carries no payload however large its length is.
+        let frame = read(&Frame::window_update(1, 0, 1 << 20).encode(), 16).unwrap();
+        assert_eq!(frame.length, 1 << 20);
+    }
+
+    #[test]
+    fn fails_on_truncated_frames() {
+        let input = Frame::data(1, 0, b"data".to_vec()).encode();
+        assert!(read(&input[..HEADER_LEN - 1], 16).is_err());
+        assert!(read(&input[..HEADER_LEN + 2], 16).is_err());
+    }
+}
@@ -0,0 +1,550 @@
+// MIT License
+
+// Copyright (c) 2019 Zhuhao Wang
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Carries many logical streams over one connection, so that a proxied flow
+//! doesn't pay for a new TCP and TLS handshake to the remote node.
+//!
+//! The wire format is the one of yamux, with per-stream flow control,
+//! half-close and keepalive pings.
+
+mod frame;
+mod stream;
+pub use self::stream::MuxStream;
+
+use self::{
+    frame::{
+        read_frame, Frame, DEFAULT_WINDOW, FLAG_ACK, FLAG_FIN, FLAG_RST, FLAG_SYN, TYPE_DATA,
+        TYPE_GO_AWAY, TYPE_PING, TYPE_WINDOW_UPDATE,
+    },
+    stream::StreamState,
+};
+use crate::core::{Error, Result};
+use futures::{
+    channel::mpsc::{self, UnboundedReceiver, UnboundedSender},
+    future::{self, FutureExt, TryFutureExt},
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    stream::StreamExt,
+};
+use std::{
+    collections::HashMap,
+    future::Future,
+    sync::{
+        atomic::{AtomicBool, AtomicU32, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+use tokio::timer::delay;
+
+#[derive(Debug)]
+pub enum MuxError {
+    SessionClosed,
+    UnsupportedVersion(u8),
+    UnknownFrameType(u8),
+    FrameTooLarge(u32),
+}
+
+impl std::fmt::Display for MuxError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::result::Result<(), std::fmt::Error> {
+        write!(f, "{:#?}", self)
+    }
+}
+
+impl std::error::Error for MuxError {}
+
+#[derive(Clone, Debug)]
+pub struct MuxConfig {
+    /// The receive window of each stream, which is also the most a stream
+    /// buffers. Smaller values are raised to the yamux default of 256 KiB,
+    /// which the peer assumes for every new stream.
+    pub window: u32,
+    /// How often the peer is pinged. The session is closed if a ping is not
+    /// answered before the next one is due.
+    pub keepalive_interval: Option<Duration>,
+    /// The most carrier connections `MuxConnector` opens to an endpoint.
+    pub max_sessions: usize,
+    /// How many streams a carrier connection gets before `MuxConnector`
+    /// opens another one.
+    pub max_streams_per_session: usize,
+}
+
+impl MuxConfig {
+    fn recv_window(&self) -> u32 {
+        self.window.max(DEFAULT_WINDOW)
+    }
+}
+
+impl Default for MuxConfig {
+    fn default() -> Self {
+        MuxConfig {
+            window: DEFAULT_WINDOW,
+            keepalive_interval: Some(Duration::from_secs(30)),
+            max_sessions: 4,
+            max_streams_per_session: 64,
+        }
+    }
+}
+
+struct Shared {
+    config: MuxConfig,
+    streams: Mutex<HashMap<u32, Arc<Mutex<StreamState>>>>,
+    frames: UnboundedSender<Vec<u8>>,
+    next_id: AtomicU32,
+    closed: AtomicBool,
+    ping_outstanding: AtomicBool,
+}
+
+impl Shared {
+    /// Queues `frame` to be written, returning `false` if the session is
+    /// closed.
+    fn send(&self, frame: Frame) -> bool {
+        self.frames.unbounded_send(frame.encode()).is_ok()
+    }
+
+    /// Creates the state of a new stream, with the window update that
+    /// announces it to the peer along with our receive window.
+    fn new_stream(&self, id: u32, flags: u16) -> (Arc<Mutex<StreamState>>, Frame) {
+        let window = self.config.recv_window();
+        let state = Arc::new(Mutex::new(StreamState::new(window)));
+        self.streams.lock().unwrap().insert(id, state.clone());
+        (
+            state,
+            Frame::window_update(id, flags, window - DEFAULT_WINDOW),
+        )
+    }
+
+    fn close(&self) {
+        self.closed.store(true, Ordering::SeqCst);
+        for (_, state) in self.streams.lock().unwrap().drain() {
+            state.lock().unwrap().reset();
+        }
+        self.frames.close_channel();
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    Client,
+    Server,
+}
+
+/// One end of a carrier connection.
+#[derive(Clone)]
+pub struct Session {
+    shared: Arc<Shared>,
+}
+
+impl Session {
+    /// Starts a session on `io` that opens streams. The session runs in a
+    /// task of its own until the carrier closes.
+    pub fn client<T>(io: T, config: MuxConfig) -> Session
+    where
+        T: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+    {
+        start(io, config, Mode::Client).0
+    }
+
+    pub fn open(&self) -> Result<MuxStream> {
+        if self.is_closed() {
+            return Err(MuxError::SessionClosed.into());
+        }
+
+        let id = self.shared.next_id.fetch_add(2, Ordering::SeqCst);
+        let (state, syn) = self.shared.new_stream(id, FLAG_SYN);
+        if !self.shared.send(syn) {
+            return Err(MuxError::SessionClosed.into());
+        }
+
+        Ok(MuxStream::new(id, state, self.shared.clone()))
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.shared.closed.load(Ordering::SeqCst)
+    }
+
+    pub fn stream_count(&self) -> usize {
+        self.shared.streams.lock().unwrap().len()
+    }
+}
+
+/// Demultiplexes `io`, spawning `handler` with every stream the client
+/// opens, e.g., to run an acceptor on it. Returns once the carrier closes.
+pub async fn serve<T, F, Fut>(io: T, config: MuxConfig, handler: F)
+where
+    T: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+    F: Fn(MuxStream) -> Fut,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    let (_session, mut incoming) = start(io, config, Mode::Server);
+    while let Some(stream) = incoming.next().await {
+        tokio::spawn(handler(stream));
+    }
+}
+
+fn start<T>(io: T, config: MuxConfig, mode: Mode) -> (Session, UnboundedReceiver<MuxStream>)
+where
+    T: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+{
+    let (frame_sender, frame_receiver) = mpsc::unbounded();
+    let (stream_sender, stream_receiver) = mpsc::unbounded();
+    let keepalive_interval = config.keepalive_interval;
+
+    let shared = Arc::new(Shared {
+        config,
+        streams: Mutex::new(HashMap::new()),
+        frames: frame_sender,
+        // Clients use odd IDs and servers even ones, as in yamux.
+        next_id: AtomicU32::new(if mode == Mode::Client { 1 } else { 2 }),
+        closed: AtomicBool::new(false),
+        ping_outstanding: AtomicBool::new(false),
+    });
+
+    let (reader, writer) = io.split();
+    let incoming = if mode == Mode::Server {
+        Some(stream_sender)
+    } else {
+        None
+    };
+
+    let read = read_loop(reader, shared.clone(), incoming).boxed();
+    let write = write_loop(writer, frame_receiver).boxed();
+    let keepalive = match keepalive_interval {
+        Some(interval) => keepalive(shared.clone(), interval).boxed(),
+        None => future::pending().boxed(),
+    };
+
+    let driver_shared = shared.clone();
+    tokio::spawn(
+        future::select(read, future::select(write, keepalive)).map(move |_| driver_shared.close()),
+    );
+
+    (Session { shared }, stream_receiver)
+}
+
+async fn read_loop<R: AsyncRead + Unpin>(
+    mut reader: R,
+    shared: Arc<Shared>,
+    incoming: Option<UnboundedSender<MuxStream>>,
+) -> Result<()> {
+    loop {
+        let frame = read_frame(&mut reader, shared.config.recv_window()).await?;
+
+        match frame.frame_type {
+            TYPE_DATA | TYPE_WINDOW_UPDATE => handle_stream_frame(&shared, frame, &incoming),
+            TYPE_PING if frame.flags & FLAG_SYN != 0 => {
+                shared.send(Frame::ping(FLAG_ACK, frame.length));
+            }
+            TYPE_PING => shared.ping_outstanding.store(false, Ordering::SeqCst),
+            TYPE_GO_AWAY => return Ok(()),
+            _ => unreachable!(),
+        }
+    }
+}
+
+fn handle_stream_frame(
+    shared: &Arc<Shared>,
+    frame: Frame,
+    incoming: &Option<UnboundedSender<MuxStream>>,
+) {
+    let state = shared
+        .streams
+        .lock()
+        .unwrap()
+        .get(&frame.stream_id)
+        .cloned();
+
+    let state = match (state, incoming) {
+        (Some(state), _) => state,
+        (None, Some(incoming)) if frame.flags & FLAG_SYN != 0 && frame.stream_id % 2 == 1 => {
+            let (state, ack) = shared.new_stream(frame.stream_id, FLAG_ACK);
+            shared.send(ack);
+
+            let stream = MuxStream::new(frame.stream_id, state.clone(), shared.clone());
+            if incoming.unbounded_send(stream).is_err() {
+                return;
+            }
+            state
+        }
+        (None, _) => {
+            if frame.flags & FLAG_RST == 0 {
+                shared.send(Frame::window_update(frame.stream_id, FLAG_RST, 0));
+            }
+            return;
+        }
+    };
+
+    let mut state = state.lock().unwrap();
+    if frame.frame_type == TYPE_DATA {
+        if !state.receive(&frame.payload) {
+            state.reset();
+            shared.send(Frame::window_update(frame.stream_id, FLAG_RST, 0));
+            return;
+        }
+    } else {
+        state.grant(frame.length);
+    }
+
+    if frame.flags & FLAG_FIN != 0 {
+        state.remote_close();
+    }
+    if frame.flags & FLAG_RST != 0 {
+        state.reset();
+    }
+}
+
+async fn write_loop<W: AsyncWrite + Unpin>(
+    mut writer: W,
+    mut frames: UnboundedReceiver<Vec<u8>>,
+) -> Result<()> {
+    while let Some(frame) = frames.next().await {
+        writer.write_all(&frame).err_into::<Error>().await?;
+        writer.flush().err_into::<Error>().await?;
+    }
+    writer.close().err_into::<Error>().await
+}
+
+async fn keepalive(shared: Arc<Shared>, interval: Duration) {
+    let mut opaque = 0u32;
+    loop {
+        delay(Instant::now() + interval).await;
+
+        if shared.ping_outstanding.swap(true, Ordering::SeqCst) {
+            return;
+        }
+
+        opaque = opaque.wrapping_add(1);
+        if !shared.send(Frame::ping(FLAG_SYN, opaque)) {
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::mock::{duplex, DuplexStream};
+    use futures::{channel::oneshot, future::Either};
+    use std::io;
+    use tokio::runtime::Runtime;
+
+    fn config() -> MuxConfig {
+        MuxConfig {
+            keepalive_interval: None,
+            ..Default::default()
+        }
+    }
+
+    async fn next_frame(io: &mut DuplexStream) -> Frame {
+        read_frame(io, u32::max_value()).await.unwrap()
+    }
+
+    async fn send_frame(io: &mut DuplexStream, frame: Frame) {
+        io.write_all(&frame.encode()).await.unwrap();
+    }
+
+    /// Serves a session on `io` that echoes every stream until the client
+    /// closes it.
+    fn spawn_echo_server(io: DuplexStream) {
+        tokio::spawn(serve(io, config(), |mut stream: MuxStream| async move {
+            let mut buf = Vec::new();
+            stream.read_to_end(&mut buf).await.unwrap();
+            stream.write_all(&buf).await.unwrap();
+            stream.close().await.unwrap();
+        }));
+    }
+
+    #[test]
+    fn opens_and_accepts_streams() {
+        Runtime::new().unwrap().block_on(async {
+            let (client, server) = duplex();
+            spawn_echo_server(server);
+            let session = Session::client(client, config());
+
+            let mut first = session.open().unwrap();
+            let mut second = session.open().unwrap();
+            assert_eq!((first.id(), second.id()), (1, 3));
+            assert_eq!(session.stream_count(), 2);
+
+            second.write_all(b"world").await.unwrap();
+            first.write_all(b"hello").await.unwrap();
+            first.close().await.unwrap();
+            second.close().await.unwrap();
+
+            let mut buf = Vec::new();
+            first.read_to_end(&mut buf).await.unwrap();
+            assert_eq!(buf, b"hello");
+            buf.clear();
+            second.read_to_end(&mut buf).await.unwrap();
+            assert_eq!(buf, b"world");
+
+            drop(first);
+            assert_eq!(session.stream_count(), 1);
+        });
+    }
+
+    #[test]
+    fn half_closes_with_fin() {
+        Runtime::new().unwrap().block_on(async {
+            let (client, mut server) = duplex();
+            let session = Session::client(client, config());
+            let mut stream = session.open().unwrap();
+            next_frame(&mut server).await;
+
+            stream.write_all(b"hi").await.unwrap();
+            stream.close().await.unwrap();
+            let frame = next_frame(&mut server).await;
+            assert_eq!(frame.payload, b"hi");
+            let frame = next_frame(&mut server).await;
+            assert_eq!((frame.frame_type, frame.flags), (TYPE_DATA, FLAG_FIN));
+
+            let err = stream.write_all(b"more").await.err().unwrap();
+            assert_eq!(err.kind(), io::ErrorKind::BrokenPipe);
+
+            // The peer can still send until it closes its side too.
+            send_frame(&mut server, Frame::data(1, 0, b"bye".to_vec())).await;
+            send_frame(&mut server, Frame::data(1, FLAG_FIN, Vec::new())).await;
+            let mut buf = Vec::new();
+            stream.read_to_end(&mut buf).await.unwrap();
+            assert_eq!(buf, b"bye");
+        });
+    }
+
+    #[test]
+    fn resets_streams() {
+        Runtime::new().unwrap().block_on(async {
+            let (client, mut server) = duplex();
+            let session = Session::client(client, config());
+
+            drop(session.open().unwrap());
+            next_frame(&mut server).await;
+            let frame = next_frame(&mut server).await;
+            assert_eq!((frame.stream_id, frame.flags), (1, FLAG_RST));
+            assert_eq!(session.stream_count(), 0);
+
+            let mut stream = session.open().unwrap();
+            next_frame(&mut server).await;
+            send_frame(&mut server, Frame::window_update(3, FLAG_RST, 0)).await;
+            let err = stream.read(&mut [0; 1]).await.err().unwrap();
+            assert_eq!(err.kind(), io::ErrorKind::ConnectionReset);
+            let err = stream.write(b"data").await.err().unwrap();
+            assert_eq!(err.kind(), io::ErrorKind::ConnectionReset);
+        });
+    }
+
+    #[test]
+    fn waits_for_window_update() {
+        Runtime::new().unwrap().block_on(async {
+            let (client, mut server) = duplex();
+            let session = Session::client(client, config());
+            let mut stream = session.open().unwrap();
+            next_frame(&mut server).await;
+
+            let (done, mut written) = oneshot::channel();
+            tokio::spawn(async move {
+                let data = vec![7u8; DEFAULT_WINDOW as usize + 10];
+                stream.write_all(&data).await.unwrap();
+                done.send(stream).ok();
+            });
+
+            let mut received = 0;
+            while received < DEFAULT_WINDOW {
+                received += next_frame(&mut server).await.length;
+            }
+            assert_eq!(received, DEFAULT_WINDOW);
+
+            // Nothing more is sent until the window is updated.
+            let timeout = delay(Instant::now() + Duration::from_millis(50)).boxed();
+            match future::select(next_frame(&mut server).boxed(), timeout).await {
+                Either::Right(_) => {}
+                Either::Left(_) => panic!("sent past the window"),
+            }
+            assert!(written.try_recv().unwrap().is_none());
+
+            send_frame(&mut server, Frame::window_update(1, 0, 10)).await;
+            let frame = next_frame(&mut server).await;
+            assert_eq!(frame.payload, [7; 10]);
+            written.await.unwrap();
+        });
+    }
+
+    #[test]
+    fn announces_window_beyond_default() {
+        let config = MuxConfig {
+            window: DEFAULT_WINDOW * 2,
+            ..config()
+        };
+
+        Runtime::new().unwrap().block_on(async {
+            let (client, mut server) = duplex();
+            let session = Session::client(client, config.clone());
+            let _stream = session.open().unwrap();
+            let frame = next_frame(&mut server).await;
+            assert_eq!(
+                (frame.frame_type, frame.flags, frame.length),
+                (TYPE_WINDOW_UPDATE, FLAG_SYN, DEFAULT_WINDOW)
+            );
+
+            let (mut client, server) = duplex();
+            tokio::spawn(serve(server, config, |_| future::ready(())));
+            send_frame(&mut client, Frame::window_update(1, FLAG_SYN, 0)).await;
+            let frame = next_frame(&mut client).await;
+            assert_eq!(
+                (frame.frame_type, frame.flags, frame.length),
+                (TYPE_WINDOW_UPDATE, FLAG_ACK, DEFAULT_WINDOW)
+            );
+        });
+    }
+
+    #[test]
+    fn answers_pings() {
+        Runtime::new().unwrap().block_on(async {
+            let (client, mut server) = duplex();
+            let _session = Session::client(client, config());
+
+            send_frame(&mut server, Frame::ping(FLAG_SYN, 42)).await;
+            let frame = next_frame(&mut server).await;
+            assert_eq!(
+                (frame.frame_type, frame.flags, frame.length),
+                (TYPE_PING, FLAG_ACK, 42)
+            );
+        });
+    }
+
+    #[test]
+    fn closes_when_keepalive_is_unanswered() {
+        let config = MuxConfig {
+            keepalive_interval: Some(Duration::from_millis(20)),
+            ..config()
+        };
+
+        Runtime::new().unwrap().block_on(async {
+            let (client, mut server) = duplex();
+            let session = Session::client(client, config);
+
+            let frame = next_frame(&mut server).await;
+            assert_eq!((frame.frame_type, frame.flags), (TYPE_PING, FLAG_SYN));
+            assert!(read_frame(&mut server, 0).await.is_err());
+            assert!(session.is_closed());
+            assert!(session.open().is_err());
+        });
+    }
+}
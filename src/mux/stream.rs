@@ -0,0 +1,238 @@
+// MIT License
+
+// Copyright (c) 2019 Zhuhao Wang
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use super::{
+    frame::{Frame, DEFAULT_WINDOW, FLAG_FIN, FLAG_RST},
+    Shared,
+};
+use futures::{
+    io::{AsyncRead, AsyncWrite},
+    task::{Context, Poll, Waker},
+};
+use std::{
+    io,
+    pin::Pin,
+    sync::{Arc, Mutex},
+};
+
+/// The largest data frame sent, so that one stream can't hog the carrier.
+const MAX_FRAME_PAYLOAD_LEN: u32 = 16 * 1024;
+
+pub(super) struct StreamState {
+    recv_buf: Vec<u8>,
+    recv_pos: usize,
+    /// How much more the peer may send before it gets a window update.
+    recv_window: u32,
+    /// Bytes read since the last window update.
+    consumed: u32,
+    send_window: u32,
+    remote_closed: bool,
+    local_closed: bool,
+    reset: bool,
+    read_waker: Option<Waker>,
+    write_waker: Option<Waker>,
+}
+
+fn wake(waker: &mut Option<Waker>) {
+    if let Some(waker) = waker.take() {
+        waker.wake();
+    }
+}
+
+impl StreamState {
+    /// `recv_window` is our own window; the peer's starts at the default and
+    /// grows with its SYN or ACK.
+    pub(super) fn new(recv_window: u32) -> Self {
+        StreamState {
+            recv_buf: Vec::new(),
+            recv_pos: 0,
+            recv_window,
+            consumed: 0,
+            send_window: DEFAULT_WINDOW,
+            remote_closed: false,
+            local_closed: false,
+            reset: false,
+            read_waker: None,
+            write_waker: None,
+        }
+    }
+
+    /// Buffers `payload` from the peer, returning `false` if it overruns
+    /// the window.
+    pub(super) fn receive(&mut self, payload: &[u8]) -> bool {
+        if payload.len() as u64 > u64::from(self.recv_window) {
+            return false;
+        }
+
+        self.recv_window -= payload.len() as u32;
+        self.recv_buf.extend_from_slice(payload);
+        wake(&mut self.read_waker);
+        true
+    }
+
+    pub(super) fn grant(&mut self, delta: u32) {
+        self.send_window = self.send_window.saturating_add(delta);
+        wake(&mut self.write_waker);
+    }
+
+    pub(super) fn remote_close(&mut self) {
+        self.remote_closed = true;
+        wake(&mut self.read_waker);
+    }
+
+    pub(super) fn reset(&mut self) {
+        self.reset = true;
+        wake(&mut self.read_waker);
+        wake(&mut self.write_waker);
+    }
+}
+
+/// A logical stream carried over a `Session`.
+pub struct MuxStream {
+    id: u32,
+    state: Arc<Mutex<StreamState>>,
+    shared: Arc<Shared>,
+}
+
+impl MuxStream {
+    pub(super) fn new(id: u32, state: Arc<Mutex<StreamState>>, shared: Arc<Shared>) -> Self {
+        MuxStream { id, state, shared }
+    }
+
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+}
+
+impl AsyncRead for MuxStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let mut state = self.state.lock().unwrap();
+
+        if state.recv_pos < state.recv_buf.len() {
+            let len = buf.len().min(state.recv_buf.len() - state.recv_pos);
+            buf[..len].copy_from_slice(&state.recv_buf[state.recv_pos..state.recv_pos + len]);
+            state.recv_pos += len;
+            if state.recv_pos == state.recv_buf.len() {
+                state.recv_buf.clear();
+                state.recv_pos = 0;
+            }
+
+            // Hand the window back once half of it has been read, rather
+            // than for every read.
+            state.consumed += len as u32;
+            if state.consumed >= self.shared.config.recv_window() / 2 && !state.remote_closed {
+                let delta = state.consumed;
+                state.consumed = 0;
+                state.recv_window += delta;
+                self.shared.send(Frame::window_update(self.id, 0, delta));
+            }
+
+            return Poll::Ready(Ok(len));
+        }
+
+        if state.reset {
+            return Poll::Ready(Err(io::ErrorKind::ConnectionReset.into()));
+        }
+
+        if state.remote_closed {
+            return Poll::Ready(Ok(0));
+        }
+
+        state.read_waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+impl AsyncWrite for MuxStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let mut state = self.state.lock().unwrap();
+
+        if state.reset {
+            return Poll::Ready(Err(io::ErrorKind::ConnectionReset.into()));
+        }
+
+        if state.local_closed {
+            return Poll::Ready(Err(io::ErrorKind::BrokenPipe.into()));
+        }
+
+        if buf.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+
+        if state.send_window == 0 {
+            state.write_waker = Some(cx.waker().clone());
+            return Poll::Pending;
+        }
+
+        let len = (buf.len() as u32)
+            .min(state.send_window)
+            .min(MAX_FRAME_PAYLOAD_LEN);
+        state.send_window -= len;
+        drop(state);
+
+        if self
+            .shared
+            .send(Frame::data(self.id, 0, buf[..len as usize].to_vec()))
+        {
+            Poll::Ready(Ok(len as usize))
+        } else {
+            Poll::Ready(Err(io::ErrorKind::BrokenPipe.into()))
+        }
+    }
+
+    /// Frames are written by the session as soon as possible, there is
+    /// nothing to flush.
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    /// Sends FIN; data from the peer can still be read until it closes too.
+    fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let mut state = self.state.lock().unwrap();
+        if !state.local_closed && !state.reset {
+            state.local_closed = true;
+            self.shared.send(Frame::data(self.id, FLAG_FIN, Vec::new()));
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl Drop for MuxStream {
+    /// Dropping a stream that isn't closed aborts it.
+    fn drop(&mut self) {
+        let state = self.state.lock().unwrap();
+        if !state.local_closed && !state.reset {
+            self.shared.send(Frame::data(self.id, FLAG_RST, Vec::new()));
+        }
+        drop(state);
+
+        self.shared.streams.lock().unwrap().remove(&self.id);
+    }
+}
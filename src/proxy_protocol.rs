@@ -0,0 +1,596 @@
+// MIT License
+
+// Copyright (c) 2019 Zhuhao Wang
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! The PROXY protocol, with which a load balancer tells the server behind it
+//! the addresses of the connection it accepted.
+//!
+//! See https://www.haproxy.org/download/2.0/doc/proxy-protocol.txt.
+
+use crate::core::{Error, Result};
+use futures::{
+    future::TryFutureExt,
+    io::{AsyncRead, AsyncReadExt},
+};
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    str,
+};
+
+const V2_SIGNATURE: [u8; 12] = *b"\r\n\r\n\0\r\nQUIT\n";
+const V1_MAX_LEN: usize = 107;
+
+const V2_COMMAND_LOCAL: u8 = 0x20;
+const V2_COMMAND_PROXY: u8 = 0x21;
+
+const V2_FAMILY_UNSPEC: u8 = 0x00;
+const V2_FAMILY_TCP4: u8 = 0x11;
+const V2_FAMILY_UDP4: u8 = 0x12;
+const V2_FAMILY_TCP6: u8 = 0x21;
+const V2_FAMILY_UDP6: u8 = 0x22;
+const V2_FAMILY_UNIX_STREAM: u8 = 0x31;
+const V2_FAMILY_UNIX_DGRAM: u8 = 0x32;
+
+#[derive(Debug)]
+pub enum ProxyProtocolError {
+    InvalidSignature,
+    UnsupportedVersion(u8),
+    UnsupportedCommand(u8),
+    UnsupportedFamily(u8),
+    InvalidHeader,
+    HeaderTooLong,
+}
+
+impl std::fmt::Display for ProxyProtocolError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::result::Result<(), std::fmt::Error> {
+        write!(f, "{:#?}", self)
+    }
+}
+
+impl std::error::Error for ProxyProtocolError {}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ProxyVersion {
+    /// The human readable text format.
+    V1,
+    /// The binary format, which can also carry TLVs.
+    V2,
+}
+
+/// A type-length-value extension of a v2 header.
+#[derive(Clone, Debug)]
+pub struct Tlv {
+    pub kind: u8,
+    pub value: Vec<u8>,
+}
+
+/// A PROXY protocol header.
+///
+/// `source` and `destination` are `None` for connections the sender made on
+/// its own behalf (v1 `UNKNOWN`, v2 `LOCAL`) and for address families other
+/// than TCP/UDP over IPv4/IPv6.
+#[derive(Clone, Debug)]
+pub struct ProxyHeader {
+    pub version: ProxyVersion,
+    /// The address of the original client.
+    pub source: Option<SocketAddr>,
+    /// The address the original client connected to.
+    pub destination: Option<SocketAddr>,
+    pub tlvs: Vec<Tlv>,
+}
+
+impl ProxyHeader {
+    /// Returns the value of the first TLV of `kind`.
+    pub fn tlv(&self, kind: u8) -> Option<&[u8]> {
+        self.tlvs
+            .iter()
+            .find(|tlv| tlv.kind == kind)
+            .map(|tlv| tlv.value.as_slice())
+    }
+
+    /// Encodes the header for sending.
+    ///
+    /// Without both addresses, v1 sends `UNKNOWN` and v2 sends `LOCAL`. If
+    /// only one of the addresses is IPv6, the other is sent IPv4-mapped. TLVs
+    /// are only sent in v2, and fail the encoding if they don't fit in the
+    /// 16 bit lengths of the format.
+    pub fn encode(&self) -> Result<Vec<u8>> {
+        let addresses = match (self.source, self.destination) {
+            (Some(source), Some(destination)) if source.is_ipv4() != destination.is_ipv4() => {
+                Some((to_ipv6(source), to_ipv6(destination)))
+            }
+            (Some(source), Some(destination)) => Some((source, destination)),
+            _ => None,
+        };
+
+        match self.version {
+            ProxyVersion::V1 => Ok(encode_v1(addresses)),
+            ProxyVersion::V2 => encode_v2(addresses, &self.tlvs),
+        }
+    }
+}
+
+fn to_ipv6(addr: SocketAddr) -> SocketAddr {
+    match addr {
+        SocketAddr::V4(v4) => SocketAddr::new(IpAddr::V6(v4.ip().to_ipv6_mapped()), v4.port()),
+        SocketAddr::V6(_) => addr,
+    }
+}
+
+fn encode_v1(addresses: Option<(SocketAddr, SocketAddr)>) -> Vec<u8> {
+    let line = match addresses {
+        Some((source, destination)) => format!(
+            "PROXY {} {} {} {} {}\r\n",
+            if source.is_ipv4() { "TCP4" } else { "TCP6" },
+            source.ip(),
+            destination.ip(),
+            source.port(),
+            destination.port()
+        ),
+        None => "PROXY UNKNOWN\r\n".to_owned(),
+    };
+    line.into_bytes()
+}
+
+fn encode_v2(addresses: Option<(SocketAddr, SocketAddr)>, tlvs: &[Tlv]) -> Result<Vec<u8>> {
+    let mut payload = Vec::new();
+    let (command, family) = match addresses {
+        Some((SocketAddr::V4(source), SocketAddr::V4(destination))) => {
+            payload.extend_from_slice(&source.ip().octets());
+            payload.extend_from_slice(&destination.ip().octets());
+            payload.extend_from_slice(&source.port().to_be_bytes());
+            payload.extend_from_slice(&destination.port().to_be_bytes());
+            (V2_COMMAND_PROXY, V2_FAMILY_TCP4)
+        }
+        Some((source, destination)) => {
+            for addr in &[source, destination] {
+                if let IpAddr::V6(ip) = addr.ip() {
+                    payload.extend_from_slice(&ip.octets());
+                }
+            }
+            payload.extend_from_slice(&source.port().to_be_bytes());
+            payload.extend_from_slice(&destination.port().to_be_bytes());
+            (V2_COMMAND_PROXY, V2_FAMILY_TCP6)
+        }
+        None => (V2_COMMAND_LOCAL, V2_FAMILY_UNSPEC),
+    };
+
+    for tlv in tlvs {
+        if tlv.value.len() > usize::from(u16::max_value()) {
+            return Err(ProxyProtocolError::HeaderTooLong.into());
+        }
+        payload.push(tlv.kind);
+        payload.extend_from_slice(&(tlv.value.len() as u16).to_be_bytes());
+        payload.extend_from_slice(&tlv.value);
+    }
+
+    if payload.len() > usize::from(u16::max_value()) {
+        return Err(ProxyProtocolError::HeaderTooLong.into());
+    }
+
+    let mut buf = Vec::with_capacity(V2_SIGNATURE.len() + 4 + payload.len());
+    buf.extend_from_slice(&V2_SIGNATURE);
+    buf.push(command);
+    buf.push(family);
+    buf.extend_from_slice(&(payload.len() as u16).to_be_bytes());
+    buf.extend_from_slice(&payload);
+    Ok(buf)
+}
+
+/// Reads a v1 or v2 header from `io`, consuming exactly the header.
+pub(crate) async fn read_header<T: AsyncRead + Unpin>(io: &mut T) -> Result<ProxyHeader> {
+    // Even the shortest v1 header, `PROXY UNKNOWN\r\n`, is longer than the
+    // v2 signature.
+    let mut buf = vec![0; V2_SIGNATURE.len()];
+    io.read_exact(&mut buf).err_into::<Error>().await?;
+
+    if buf == V2_SIGNATURE {
+        read_v2(io).await
+    } else if buf.starts_with(b"PROXY ") {
+        read_v1(io, buf).await
+    } else {
+        Err(ProxyProtocolError::InvalidSignature.into())
+    }
+}
+
+async fn read_v1<T: AsyncRead + Unpin>(io: &mut T, mut buf: Vec<u8>) -> Result<ProxyHeader> {
+    // The line is short and the bytes after it belong to the inner
+    // protocol, so it is read a byte at a time.
+    let mut byte = [0; 1];
+    while !buf.ends_with(b"\r\n") {
+        if buf.len() >= V1_MAX_LEN {
+            return Err(ProxyProtocolError::InvalidHeader.into());
+        }
+        io.read_exact(&mut byte).err_into::<Error>().await?;
+        buf.push(byte[0]);
+    }
+
+    let line =
+        str::from_utf8(&buf[..buf.len() - 2]).map_err(|_| ProxyProtocolError::InvalidHeader)?;
+    parse_v1(line).ok_or_else(|| ProxyProtocolError::InvalidHeader.into())
+}
+
+fn parse_v1(line: &str) -> Option<ProxyHeader> {
+    let mut fields = line.split(' ').skip(1);
+
+    let (source, destination) = match fields.next()? {
+        "UNKNOWN" => (None, None),
+        family @ "TCP4" | family @ "TCP6" => {
+            let source_ip: IpAddr = fields.next()?.parse().ok()?;
+            let destination_ip: IpAddr = fields.next()?.parse().ok()?;
+            let source_port: u16 = fields.next()?.parse().ok()?;
+            let destination_port: u16 = fields.next()?.parse().ok()?;
+            if fields.next().is_some()
+                || source_ip.is_ipv4() != (family == "TCP4")
+                || destination_ip.is_ipv4() != (family == "TCP4")
+            {
+                return None;
+            }
+            (
+                Some(SocketAddr::new(source_ip, source_port)),
+                Some(SocketAddr::new(destination_ip, destination_port)),
+            )
+        }
+        _ => return None,
+    };
+
+    Some(ProxyHeader {
+        version: ProxyVersion::V1,
+        source,
+        destination,
+        tlvs: Vec::new(),
+    })
+}
+
+async fn read_v2<T: AsyncRead + Unpin>(io: &mut T) -> Result<ProxyHeader> {
+    let mut head = [0; 4];
+    io.read_exact(&mut head).err_into::<Error>().await?;
+
+    let command = head[0];
+    if command >> 4 != 2 {
+        return Err(ProxyProtocolError::UnsupportedVersion(command >> 4).into());
+    }
+    if command != V2_COMMAND_LOCAL && command != V2_COMMAND_PROXY {
+        return Err(ProxyProtocolError::UnsupportedCommand(command & 0x0F).into());
+    }
+    let family = head[1];
+
+    let mut payload = vec![0; u16::from_be_bytes([head[2], head[3]]) as usize];
+    io.read_exact(&mut payload).err_into::<Error>().await?;
+
+    let address_len = match family {
+        V2_FAMILY_UNSPEC => 0,
+        V2_FAMILY_TCP4 | V2_FAMILY_UDP4 => 12,
+        V2_FAMILY_TCP6 | V2_FAMILY_UDP6 => 36,
+        V2_FAMILY_UNIX_STREAM | V2_FAMILY_UNIX_DGRAM => 216,
+        _ => return Err(ProxyProtocolError::UnsupportedFamily(family).into()),
+    };
+    if payload.len() < address_len {
+        return Err(ProxyProtocolError::InvalidHeader.into());
+    }
+    let (addresses, tlvs) = payload.split_at(address_len);
+
+    let (source, destination) = match family {
+        // The addresses of a LOCAL connection are those of the sender
+        // itself and carry no information about a client.
+        _ if command == V2_COMMAND_LOCAL => (None, None),
+        V2_FAMILY_TCP4 | V2_FAMILY_UDP4 => {
+            let ip = |at: usize| {
+                IpAddr::V4(Ipv4Addr::new(
+                    addresses[at],
+                    addresses[at + 1],
+                    addresses[at + 2],
+                    addresses[at + 3],
+                ))
+            };
+            (
+                Some(SocketAddr::new(ip(0), read_u16(addresses, 8))),
+                Some(SocketAddr::new(ip(4), read_u16(addresses, 10))),
+            )
+        }
+        V2_FAMILY_TCP6 | V2_FAMILY_UDP6 => {
+            let ip = |at: usize| {
+                let mut octets = [0; 16];
+                octets.copy_from_slice(&addresses[at..at + 16]);
+                IpAddr::V6(Ipv6Addr::from(octets))
+            };
+            (
+                Some(SocketAddr::new(ip(0), read_u16(addresses, 32))),
+                Some(SocketAddr::new(ip(16), read_u16(addresses, 34))),
+            )
+        }
+        _ => (None, None),
+    };
+
+    Ok(ProxyHeader {
+        version: ProxyVersion::V2,
+        source,
+        destination,
+        tlvs: parse_tlvs(tlvs).ok_or(ProxyProtocolError::InvalidHeader)?,
+    })
+}
+
+fn read_u16(buf: &[u8], at: usize) -> u16 {
+    u16::from_be_bytes([buf[at], buf[at + 1]])
+}
+
+fn parse_tlvs(mut buf: &[u8]) -> Option<Vec<Tlv>> {
+    let mut tlvs = Vec::new();
+    while !buf.is_empty() {
+        if buf.len() < 3 {
+            return None;
+        }
+        let len = read_u16(buf, 1) as usize;
+        let value = buf.get(3..3 + len)?;
+        tlvs.push(Tlv {
+            kind: buf[0],
+            value: value.to_vec(),
+        });
+        buf = &buf[3 + len..];
+    }
+    Some(tlvs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::mock::MockStream;
+    use futures::executor::block_on;
+
+    /// Reads a header from `input`, returning it along with the rest.
+    fn read(input: &[u8]) -> Result<(ProxyHeader, Vec<u8>)> {
+        let mut io = MockStream::new(input);
+        let header = block_on(read_header(&mut io))?;
+        let mut rest = Vec::new();
+        block_on(io.read_to_end(&mut rest))?;
+        Ok((header, rest))
+    }
+
+    fn v2(command: u8, family: u8, payload: &[u8]) -> Vec<u8> {
+        let mut buf = V2_SIGNATURE.to_vec();
+        buf.push(command);
+        buf.push(family);
+        buf.extend_from_slice(&(payload.len() as u16).to_be_bytes());
+        buf.extend_from_slice(payload);
+        buf
+    }
+
+    fn assert_invalid(input: &[u8]) {
+        let err = read(input).err().unwrap();
+        match err.downcast_ref::<ProxyProtocolError>() {
+            Some(ProxyProtocolError::InvalidHeader) => {}
+            _ => panic!("unexpected error {}", err),
+        }
+    }
+
+    #[test]
+    fn reads_v1() {
+        let (header, rest) = read(b"PROXY TCP4 192.0.2.1 198.51.100.1 56324 443\r\nGET /").unwrap();
+        assert_eq!(header.version, ProxyVersion::V1);
+        assert_eq!(header.source, Some("192.0.2.1:56324".parse().unwrap()));
+        assert_eq!(
+            header.destination,
+            Some("198.51.100.1:443".parse().unwrap())
+        );
+        assert_eq!(rest, b"GET /");
+
+        let (header, _) = read(b"PROXY TCP6 2001:db8::1 2001:db8::2 1 2\r\n").unwrap();
+        assert_eq!(header.source, Some("[2001:db8::1]:1".parse().unwrap()));
+        assert_eq!(header.destination, Some("[2001:db8::2]:2".parse().unwrap()));
+
+        let (header, rest) = read(b"PROXY UNKNOWN\r\ndata").unwrap();
+        assert_eq!(header.source, None);
+        assert_eq!(header.destination, None);
+        assert_eq!(rest, b"data");
+    }
+
+    #[test]
+    fn rejects_invalid_v1() {
+        assert_invalid(b"PROXY TCP4 2001:db8::1 192.0.2.1 1 2\r\n");
+        assert_invalid(b"PROXY TCP4 192.0.2.1 192.0.2.2 1\r\n");
+        assert_invalid(b"PROXY TCP4 192.0.2.1 192.0.2.2 1 70000\r\n");
+        assert_invalid(b"PROXY UDP4 192.0.2.1 192.0.2.2 1 2\r\n");
+        assert_invalid(&[&b"PROXY "[..], &[b'a'; V1_MAX_LEN][..]].concat());
+
+        let err = read(b"GET / HTTP/1.1\r\n\r\n").err().unwrap();
+        match err.downcast_ref::<ProxyProtocolError>() {
+            Some(ProxyProtocolError::InvalidSignature) => {}
+            _ => panic!("unexpected error {}", err),
+        }
+    }
+
+    #[test]
+    fn reads_v2_proxy() {
+        let mut payload = vec![192, 0, 2, 1, 198, 51, 100, 1, 0xdc, 0x04, 0x01, 0xbb];
+        payload.extend_from_slice(&[0x04, 0x00, 0x02, b'h', b'i']);
+        let mut input = v2(V2_COMMAND_PROXY, V2_FAMILY_TCP4, &payload);
+        input.extend_from_slice(b"data");
+
+        let (header, rest) = read(&input).unwrap();
+        assert_eq!(header.version, ProxyVersion::V2);
+        assert_eq!(header.source, Some("192.0.2.1:56324".parse().unwrap()));
+        assert_eq!(
+            header.destination,
+            Some("198.51.100.1:443".parse().unwrap())
+        );
+        assert_eq!(header.tlv(0x04), Some(&b"hi"[..]));
+        assert_eq!(header.tlv(0x05), None);
+        assert_eq!(rest, b"data");
+
+        let mut payload = Vec::new();
+        payload.extend_from_slice(&"2001:db8::1".parse::<Ipv6Addr>().unwrap().octets());
+        payload.extend_from_slice(&"2001:db8::2".parse::<Ipv6Addr>().unwrap().octets());
+        payload.extend_from_slice(&[0, 1, 0, 2]);
+        let (header, _) = read(&v2(V2_COMMAND_PROXY, V2_FAMILY_TCP6, &payload)).unwrap();
+        assert_eq!(header.source, Some("[2001:db8::1]:1".parse().unwrap()));
+        assert_eq!(header.destination, Some("[2001:db8::2]:2".parse().unwrap()));
+    }
+
+    #[test]
+    fn reads_v2_local() {
+        let payload: [u8; 12] = [127, 0, 0, 1, 127, 0, 0, 1, 0, 1, 0, 2];
+        let (header, _) = read(&v2(V2_COMMAND_LOCAL, V2_FAMILY_TCP4, &payload)).unwrap();
+        assert_eq!(header.source, None);
+        assert_eq!(header.destination, None);
+
+        let (header, _) = read(&v2(V2_COMMAND_LOCAL, V2_FAMILY_UNSPEC, &[])).unwrap();
+        assert_eq!(header.source, None);
+        assert!(header.tlvs.is_empty());
+    }
+
+    #[test]
+    fn rejects_invalid_v2() {
+        let err = read(&v2(0x11, V2_FAMILY_UNSPEC, &[])).err().unwrap();
+        match err.downcast_ref::<ProxyProtocolError>() {
+            Some(ProxyProtocolError::UnsupportedVersion(1)) => {}
+            _ => panic!("unexpected error {}", err),
+        }
+
+        let err = read(&v2(0x22, V2_FAMILY_UNSPEC, &[])).err().unwrap();
+        match err.downcast_ref::<ProxyProtocolError>() {
+            Some(ProxyProtocolError::UnsupportedCommand(2)) => {}
+            _ => panic!("unexpected error {}", err),
+        }
+
+        let err = read(&v2(V2_COMMAND_PROXY, 0x41, &[])).err().unwrap();
+        match err.downcast_ref::<ProxyProtocolError>() {
+            Some(ProxyProtocolError::UnsupportedFamily(0x41)) => {}
+            _ => panic!("unexpected error {}", err),
+        }
+
+        // Too short for the addresses, and a TLV longer than what is left.
+        assert_invalid(&v2(V2_COMMAND_PROXY, V2_FAMILY_TCP4, &[0; 11]));
+        let mut payload = vec![0; 12];
+        payload.extend_from_slice(&[0x04, 0x00, 0x05, 0]);
+        assert_invalid(&v2(V2_COMMAND_PROXY, V2_FAMILY_TCP4, &payload));
+    }
+
+    fn header(
+        version: ProxyVersion,
+        source: Option<&str>,
+        destination: Option<&str>,
+    ) -> ProxyHeader {
+        ProxyHeader {
+            version,
+            source: source.map(|addr| addr.parse().unwrap()),
+            destination: destination.map(|addr| addr.parse().unwrap()),
+            tlvs: Vec::new(),
+        }
+    }
+
+    fn round_trip(header: &ProxyHeader) -> ProxyHeader {
+        let mut input = header.encode().unwrap();
+        input.extend_from_slice(b"data");
+        let (decoded, rest) = read(&input).unwrap();
+        assert_eq!(decoded.version, header.version);
+        assert_eq!(rest, b"data");
+        decoded
+    }
+
+    #[test]
+    fn round_trips_addresses() {
+        for &version in &[ProxyVersion::V1, ProxyVersion::V2] {
+            for &(source, destination) in &[
+                ("192.0.2.1:56324", "198.51.100.1:443"),
+                ("[2001:db8::1]:56324", "[2001:db8::2]:443"),
+            ] {
+                let decoded = round_trip(&header(version, Some(source), Some(destination)));
+                assert_eq!(decoded.source, Some(source.parse().unwrap()));
+                assert_eq!(decoded.destination, Some(destination.parse().unwrap()));
+            }
+
+            // Mixed families are sent as IPv6.
+            let decoded = round_trip(&header(
+                version,
+                Some("192.0.2.1:56324"),
+                Some("[2001:db8::2]:443"),
+            ));
+            assert_eq!(
+                decoded.source,
+                Some("[::ffff:192.0.2.1]:56324".parse().unwrap())
+            );
+            assert_eq!(
+                decoded.destination,
+                Some("[2001:db8::2]:443".parse().unwrap())
+            );
+
+            // Without both addresses the header is UNKNOWN or LOCAL.
+            let decoded = round_trip(&header(version, Some("192.0.2.1:56324"), None));
+            assert_eq!(decoded.source, None);
+            assert_eq!(decoded.destination, None);
+        }
+
+        assert_eq!(
+            header(ProxyVersion::V1, None, None).encode().unwrap(),
+            b"PROXY UNKNOWN\r\n"
+        );
+        assert_eq!(
+            header(ProxyVersion::V2, None, None).encode().unwrap()[12..],
+            [V2_COMMAND_LOCAL, V2_FAMILY_UNSPEC, 0, 0]
+        );
+    }
+
+    #[test]
+    fn round_trips_tlvs() {
+        let mut header = header(
+            ProxyVersion::V2,
+            Some("192.0.2.1:56324"),
+            Some("198.51.100.1:443"),
+        );
+        header.tlvs.push(Tlv {
+            kind: 0x04,
+            value: b"hi".to_vec(),
+        });
+        header.tlvs.push(Tlv {
+            kind: 0x05,
+            value: Vec::new(),
+        });
+
+        let decoded = round_trip(&header);
+        assert_eq!(decoded.tlv(0x04), Some(&b"hi"[..]));
+        assert_eq!(decoded.tlv(0x05), Some(&b""[..]));
+
+        header.version = ProxyVersion::V1;
+        assert!(round_trip(&header).tlvs.is_empty());
+    }
+
+    #[test]
+    fn refuses_to_encode_oversized_tlvs() {
+        let mut header = header(ProxyVersion::V2, None, None);
+        header.tlvs.push(Tlv {
+            kind: 0x04,
+            value: vec![0; 0x1_0000],
+        });
+        let err = header.encode().err().unwrap();
+        match err.downcast_ref::<ProxyProtocolError>() {
+            Some(ProxyProtocolError::HeaderTooLong) => {}
+            _ => panic!("unexpected error {}", err),
+        }
+
+        // Each TLV fits but the header doesn't.
+        header.tlvs[0].value.truncate(0x8000);
+        header.tlvs.push(header.tlvs[0].clone());
+        assert!(header.encode().is_err());
+
+        header.tlvs.pop();
+        assert!(header.encode().is_ok());
+    }
+}
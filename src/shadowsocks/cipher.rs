@@ -0,0 +1,311 @@
+// MIT License
+
+// Copyright (c) 2019 Zhuhao Wang
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use super::{invalid_data, ShadowsocksError};
+use crate::core::Result;
+use ring::{
+    aead::{self, Aad, LessSafeKey, Nonce, UnboundKey, NONCE_LEN},
+    hkdf,
+    rand::{SecureRandom, SystemRandom},
+};
+use std::{io, str::FromStr};
+
+/// The `info` of the HKDF deriving session keys in SIP004.
+const SUBKEY_INFO: &[u8] = b"ss-subkey";
+/// The BLAKE3 context deriving session keys in SIP022.
+const SUBKEY_CONTEXT_2022: &str = "shadowsocks 2022 session subkey";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ShadowsocksMethod {
+    Aes128Gcm,
+    Aes256Gcm,
+    Chacha20IetfPoly1305,
+    Blake3Aes128Gcm,
+    Blake3Aes256Gcm,
+    Blake3Chacha20Poly1305,
+}
+
+impl ShadowsocksMethod {
+    pub fn key_len(self) -> usize {
+        match self {
+            ShadowsocksMethod::Aes128Gcm | ShadowsocksMethod::Blake3Aes128Gcm => 16,
+            _ => 32,
+        }
+    }
+
+    pub fn salt_len(self) -> usize {
+        self.key_len()
+    }
+
+    pub fn is_2022(self) -> bool {
+        match self {
+            ShadowsocksMethod::Blake3Aes128Gcm
+            | ShadowsocksMethod::Blake3Aes256Gcm
+            | ShadowsocksMethod::Blake3Chacha20Poly1305 => true,
+            _ => false,
+        }
+    }
+
+    fn algorithm(self) -> &'static aead::Algorithm {
+        match self {
+            ShadowsocksMethod::Aes128Gcm | ShadowsocksMethod::Blake3Aes128Gcm => &aead::AES_128_GCM,
+            ShadowsocksMethod::Aes256Gcm | ShadowsocksMethod::Blake3Aes256Gcm => &aead::AES_256_GCM,
+            ShadowsocksMethod::Chacha20IetfPoly1305 | ShadowsocksMethod::Blake3Chacha20Poly1305 => {
+                &aead::CHACHA20_POLY1305
+            }
+        }
+    }
+}
+
+impl FromStr for ShadowsocksMethod {
+    type Err = ShadowsocksError;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "aes-128-gcm" => Ok(ShadowsocksMethod::Aes128Gcm),
+            "aes-256-gcm" => Ok(ShadowsocksMethod::Aes256Gcm),
+            "chacha20-ietf-poly1305" => Ok(ShadowsocksMethod::Chacha20IetfPoly1305),
+            "2022-blake3-aes-128-gcm" => Ok(ShadowsocksMethod::Blake3Aes128Gcm),
+            "2022-blake3-aes-256-gcm" => Ok(ShadowsocksMethod::Blake3Aes256Gcm),
+            "2022-blake3-chacha20-poly1305" => Ok(ShadowsocksMethod::Blake3Chacha20Poly1305),
+            _ => Err(ShadowsocksError::UnknownMethod(s.to_owned())),
+        }
+    }
+}
+
+/// The method along with the master key shared by the client and server.
+#[derive(Clone)]
+pub struct ShadowsocksKey {
+    method: ShadowsocksMethod,
+    key: Vec<u8>,
+}
+
+impl ShadowsocksKey {
+    /// For the 2022 methods `password` is the base64 encoded key of exactly
+    /// `method.key_len()` bytes; for the others the key is derived from it
+    /// with `EVP_BytesToKey`.
+    pub fn new(method: ShadowsocksMethod, password: &str) -> Result<Self> {
+        let key = if method.is_2022() {
+            let key = base64::decode(password).map_err(|_| ShadowsocksError::InvalidKey)?;
+            if key.len() != method.key_len() {
+                return Err(ShadowsocksError::InvalidKey.into());
+            }
+            key
+        } else {
+            bytes_to_key(password.as_bytes(), method.key_len())
+        };
+
+        Ok(ShadowsocksKey { method, key })
+    }
+
+    pub fn method(&self) -> ShadowsocksMethod {
+        self.method
+    }
+
+    pub(super) fn key(&self) -> &[u8] {
+        &self.key
+    }
+}
+
+/// OpenSSL's `EVP_BytesToKey` with MD5 and no salt, as every Shadowsocks
+/// implementation derives keys from passwords.
+fn bytes_to_key(password: &[u8], len: usize) -> Vec<u8> {
+    let mut key = Vec::with_capacity(len + 16);
+    let mut data = Vec::new();
+    while key.len() < len {
+        data.extend_from_slice(password);
+        let digest = md5::compute(&data);
+        key.extend_from_slice(&digest.0);
+        data.clear();
+        data.extend_from_slice(&digest.0);
+    }
+    key.truncate(len);
+    key
+}
+
+pub(super) fn random_bytes(len: usize) -> Vec<u8> {
+    let mut buf = vec![0; len];
+    // There is nothing sensible to do if the system has no randomness.
+    SystemRandom::new().fill(&mut buf).unwrap();
+    buf
+}
+
+struct SubkeyLen(usize);
+
+impl hkdf::KeyType for SubkeyLen {
+    fn len(&self) -> usize {
+        self.0
+    }
+}
+
+fn derive_subkey(key: &ShadowsocksKey, salt: &[u8]) -> Vec<u8> {
+    let len = key.method().key_len();
+
+    if key.method().is_2022() {
+        let mut material = key.key().to_vec();
+        material.extend_from_slice(salt);
+        blake3::derive_key(SUBKEY_CONTEXT_2022, &material)[..len].to_vec()
+    } else {
+        let mut subkey = vec![0; len];
+        hkdf::Salt::new(hkdf::HKDF_SHA1_FOR_LEGACY_USE_ONLY, salt)
+            .extract(key.key())
+            .expand(&[SUBKEY_INFO], SubkeyLen(len))
+            .and_then(|okm| okm.fill(&mut subkey))
+            // Only fails if the output is longer than 255 blocks.
+            .unwrap();
+        subkey
+    }
+}
+
+/// The AEAD of one direction of a session, with the nonce counting up from
+/// zero.
+pub(super) struct SessionCipher {
+    key: LessSafeKey,
+    nonce: [u8; NONCE_LEN],
+}
+
+impl SessionCipher {
+    pub(super) fn new(key: &ShadowsocksKey, salt: &[u8]) -> Self {
+        let subkey = derive_subkey(key, salt);
+        SessionCipher {
+            // The subkey always has the length of the algorithm's key.
+            key: LessSafeKey::new(UnboundKey::new(key.method().algorithm(), &subkey).unwrap()),
+            nonce: [0; NONCE_LEN],
+        }
+    }
+
+    fn next_nonce(&mut self) -> Nonce {
+        let nonce = Nonce::assume_unique_for_key(self.nonce);
+        for byte in self.nonce.iter_mut() {
+            *byte = byte.wrapping_add(1);
+            if *byte != 0 {
+                break;
+            }
+        }
+        nonce
+    }
+
+    /// Appends `data` encrypted along with its tag to `out`.
+    pub(super) fn seal(&mut self, data: &[u8], out: &mut Vec<u8>) {
+        let start = out.len();
+        out.extend_from_slice(data);
+        let nonce = self.next_nonce();
+        // Chunks are far below the length limit of the algorithms.
+        let tag = self
+            .key
+            .seal_in_place_separate_tag(nonce, Aad::empty(), &mut out[start..])
+            .unwrap();
+        out.extend_from_slice(tag.as_ref());
+    }
+
+    /// Decrypts `buf` in place, returning the length of the plaintext.
+    pub(super) fn open(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let nonce = self.next_nonce();
+        self.key
+            .open_in_place(nonce, Aad::empty(), buf)
+            .map(|plaintext| plaintext.len())
+            .map_err(|_| invalid_data(ShadowsocksError::DecryptionFailed))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_method_names() {
+        assert_eq!(
+            "aes-256-gcm".parse::<ShadowsocksMethod>().unwrap(),
+            ShadowsocksMethod::Aes256Gcm
+        );
+        assert_eq!(
+            "2022-blake3-chacha20-poly1305"
+                .parse::<ShadowsocksMethod>()
+                .unwrap(),
+            ShadowsocksMethod::Blake3Chacha20Poly1305
+        );
+        assert!("rc4-md5".parse::<ShadowsocksMethod>().is_err());
+    }
+
+    #[test]
+    fn derives_key_like_openssl() {
+        // MD5("password") followed by MD5(MD5("password") || "password").
+        let key = bytes_to_key(b"password", 32);
+        assert_eq!(
+            key[..16],
+            [
+                0x5f, 0x4d, 0xcc, 0x3b, 0x5a, 0xa7, 0x65, 0xd6, 0x1d, 0x83, 0x27, 0xde, 0xb8, 0x82,
+                0xcf, 0x99
+            ]
+        );
+        let mut data = key[..16].to_vec();
+        data.extend_from_slice(b"password");
+        assert_eq!(key[16..], md5::compute(&data).0);
+    }
+
+    #[test]
+    fn checks_2022_key_length() {
+        let method = ShadowsocksMethod::Blake3Aes256Gcm;
+        assert!(ShadowsocksKey::new(method, &base64::encode(&[0; 32])).is_ok());
+        assert!(ShadowsocksKey::new(method, &base64::encode(&[0; 16])).is_err());
+        assert!(ShadowsocksKey::new(method, "not base64!").is_err());
+    }
+
+    #[test]
+    fn increments_nonce_little_endian() {
+        let key = ShadowsocksKey::new(ShadowsocksMethod::Aes128Gcm, "password").unwrap();
+        let mut cipher = SessionCipher::new(&key, &[0; 16]);
+        for _ in 0..256 {
+            cipher.next_nonce();
+        }
+        assert_eq!(cipher.nonce[..2], [0, 1]);
+    }
+
+    #[test]
+    fn opens_what_was_sealed() {
+        let key = ShadowsocksKey::new(ShadowsocksMethod::Chacha20IetfPoly1305, "password").unwrap();
+        let salt = random_bytes(32);
+        let mut sealer = SessionCipher::new(&key, &salt);
+        let mut opener = SessionCipher::new(&key, &salt);
+
+        let mut buf = Vec::new();
+        sealer.seal(b"first", &mut buf);
+        let first_len = buf.len();
+        sealer.seal(b"second", &mut buf);
+
+        let (first, second) = buf.split_at_mut(first_len);
+        let len = opener.open(first).unwrap();
+        assert_eq!(&first[..len], b"first");
+        let len = opener.open(second).unwrap();
+        assert_eq!(&second[..len], b"second");
+    }
+
+    #[test]
+    fn rejects_tampered_chunk() {
+        let key = ShadowsocksKey::new(ShadowsocksMethod::Aes128Gcm, "password").unwrap();
+        let mut buf = Vec::new();
+        SessionCipher::new(&key, &[1; 16]).seal(b"data", &mut buf);
+        buf[0] ^= 1;
+
+        assert!(SessionCipher::new(&key, &[1; 16]).open(&mut buf).is_err());
+    }
+}
@@ -20,39 +20,36 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
-use super::{Client, ClientBuilder};
-use crate::core::Error;
-use hyper::{body::Body, client::conn::SendRequest, Request, Response};
-use tokio::prelude::*;
-
-pub struct HttpProxyTransformer {
-    inner: SendRequest<Body>,
+//! The Shadowsocks AEAD protocol, both the original construction (SIP004)
+//! and the 2022 edition (SIP022), shared by the connector and the acceptor.
+
+mod cipher;
+mod stream;
+pub use self::cipher::{ShadowsocksKey, ShadowsocksMethod};
+pub(crate) use self::stream::Role;
+pub use self::stream::ShadowsocksStream;
+
+use std::io;
+
+#[derive(Debug)]
+pub enum ShadowsocksError {
+    UnknownMethod(String),
+    InvalidKey,
+    DecryptionFailed,
+    InvalidHeader,
+    InvalidLength(usize),
+    InvalidTimestamp(u64),
+    SaltMismatch,
 }
 
-impl Client for HttpProxyTransformer {
-    fn send_request(
-        &mut self,
-        mut request: Request<Body>,
-    ) -> Box<Future<Item = Response<Body>, Error = Error> + Send> {
-        let rel_uri = {
-            request
-                .uri()
-                .path_and_query()
-                .map(|p| p.as_str())
-                .unwrap_or(&"/")
-                .parse()
-                .unwrap()
-        };
-        *request.uri_mut() = rel_uri;
-        // TODO: Strip the proxy-* header before sending out.
-        Box::new(self.inner.send_request(request).from_err())
+impl std::fmt::Display for ShadowsocksError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::result::Result<(), std::fmt::Error> {
+        write!(f, "{:#?}", self)
     }
 }
 
-pub struct HttpProxyTransformerBuilder {}
+impl std::error::Error for ShadowsocksError {}
 
-impl ClientBuilder for HttpProxyTransformerBuilder {
-    fn build(self, handler: SendRequest<Body>) -> Box<dyn Client + Send> {
-        Box::new(HttpProxyTransformer { inner: handler })
-    }
+fn invalid_data(err: ShadowsocksError) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, err)
 }
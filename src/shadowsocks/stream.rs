@@ -0,0 +1,513 @@
+// MIT License
+
+// Copyright (c) 2019 Zhuhao Wang
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use super::{
+    cipher::{random_bytes, SessionCipher},
+    invalid_data, ShadowsocksError, ShadowsocksKey,
+};
+use crate::core::{encode_socks_addr, Endpoint, Error, Result};
+use futures::{
+    future::TryFutureExt,
+    io::{AsyncRead, AsyncWrite, AsyncWriteExt},
+    ready,
+    task::{Context, Poll},
+};
+use std::{
+    io, mem,
+    pin::Pin,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+const TAG_LEN: usize = 16;
+/// Headers of the 2022 edition whose timestamp is further than this from
+/// the local clock are rejected.
+const MAX_TIME_DIFF: u64 = 30;
+const MAX_PADDING_LEN: u16 = 900;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Role {
+    Client,
+    Server,
+}
+
+enum ReadState {
+    Salt,
+    Header,
+    Length,
+    Payload(usize),
+}
+
+fn timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// An encrypted Shadowsocks session over `T`.
+///
+/// Data written is sealed into chunks that are sent as a whole, so a
+/// `poll_write` that returns `Pending` must be retried with the same data.
+pub struct ShadowsocksStream<T> {
+    io: T,
+    key: ShadowsocksKey,
+    role: Role,
+    salt: Vec<u8>,
+    peer_salt: Vec<u8>,
+    encryptor: SessionCipher,
+    decryptor: Option<SessionCipher>,
+    response_header_pending: bool,
+    write_buf: Vec<u8>,
+    write_pos: usize,
+    pending: Option<usize>,
+    read_state: ReadState,
+    read_buf: Vec<u8>,
+    plaintext: Vec<u8>,
+    plaintext_pos: usize,
+}
+
+impl<T> ShadowsocksStream<T> {
+    pub(crate) fn new(io: T, key: ShadowsocksKey, role: Role) -> Self {
+        let salt = random_bytes(key.method().salt_len());
+        let encryptor = SessionCipher::new(&key, &salt);
+        let response_header_pending = role == Role::Server && key.method().is_2022();
+
+        ShadowsocksStream {
+            io,
+            role,
+            peer_salt: Vec::new(),
+            encryptor,
+            decryptor: None,
+            response_header_pending,
+            // The salt goes out with the first chunk.
+            write_buf: salt.clone(),
+            write_pos: 0,
+            pending: None,
+            read_state: ReadState::Salt,
+            read_buf: Vec::new(),
+            plaintext: Vec::new(),
+            plaintext_pos: 0,
+            salt,
+            key,
+        }
+    }
+
+    /// The salt sent by the peer, empty until something has been read.
+    pub(crate) fn peer_salt(&self) -> &[u8] {
+        &self.peer_salt
+    }
+
+    fn max_payload_len(&self) -> usize {
+        if self.key.method().is_2022() {
+            0xffff
+        } else {
+            0x3fff
+        }
+    }
+
+    fn header_len(&self) -> usize {
+        match self.role {
+            // Type, timestamp, request salt and length.
+            Role::Client => 1 + 8 + self.key.method().salt_len() + 2,
+            // Type, timestamp and length.
+            Role::Server => 1 + 8 + 2,
+        }
+    }
+
+    fn encrypt_payload(&mut self, data: &[u8]) {
+        let len = (data.len() as u16).to_be_bytes();
+
+        if self.response_header_pending {
+            self.response_header_pending = false;
+            let mut header = vec![1];
+            header.extend_from_slice(&timestamp().to_be_bytes());
+            header.extend_from_slice(&self.peer_salt);
+            header.extend_from_slice(&len);
+            self.encryptor.seal(&header, &mut self.write_buf);
+        } else {
+            self.encryptor.seal(&len, &mut self.write_buf);
+        }
+
+        self.encryptor.seal(data, &mut self.write_buf);
+    }
+
+    fn open(&mut self) -> io::Result<usize> {
+        match self.decryptor {
+            Some(ref mut decryptor) => decryptor.open(&mut self.read_buf),
+            None => unreachable!(),
+        }
+    }
+
+    fn open_header(&mut self) -> io::Result<usize> {
+        let len = self.open()?;
+        let header = &self.read_buf[..len];
+
+        let expected_type = match self.role {
+            Role::Client => 1,
+            Role::Server => 0,
+        };
+        if header[0] != expected_type {
+            return Err(invalid_data(ShadowsocksError::InvalidHeader));
+        }
+
+        let mut buf = [0; 8];
+        buf.copy_from_slice(&header[1..9]);
+        let time = u64::from_be_bytes(buf);
+        let now = timestamp();
+        if now.max(time) - now.min(time) > MAX_TIME_DIFF {
+            return Err(invalid_data(ShadowsocksError::InvalidTimestamp(time)));
+        }
+
+        if self.role == Role::Client && header[9..len - 2] != self.salt[..] {
+            return Err(invalid_data(ShadowsocksError::SaltMismatch));
+        }
+
+        let payload_len = u16::from_be_bytes([header[len - 2], header[len - 1]]);
+        self.read_buf.clear();
+        Ok(payload_len.into())
+    }
+}
+
+impl<T: AsyncRead + Unpin> ShadowsocksStream<T> {
+    /// Reads until `read_buf` holds `len` bytes. Returns `false` if the
+    /// stream ended before any byte was read.
+    fn poll_fill(&mut self, cx: &mut Context<'_>, len: usize) -> Poll<io::Result<bool>> {
+        while self.read_buf.len() < len {
+            let filled = self.read_buf.len();
+            self.read_buf.resize(len, 0);
+
+            let read = Pin::new(&mut self.io).poll_read(cx, &mut self.read_buf[filled..]);
+            let read = match read {
+                Poll::Ready(Ok(read)) => read,
+                Poll::Ready(Err(err)) => {
+                    self.read_buf.truncate(filled);
+                    return Poll::Ready(Err(err));
+                }
+                Poll::Pending => {
+                    self.read_buf.truncate(filled);
+                    return Poll::Pending;
+                }
+            };
+
+            self.read_buf.truncate(filled + read);
+            if read == 0 {
+                if filled == 0 {
+                    return Poll::Ready(Ok(false));
+                }
+                return Poll::Ready(Err(io::ErrorKind::UnexpectedEof.into()));
+            }
+        }
+
+        Poll::Ready(Ok(true))
+    }
+}
+
+impl<T: AsyncWrite + Unpin> ShadowsocksStream<T> {
+    fn poll_drain(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        while self.write_pos < self.write_buf.len() {
+            let written =
+                ready!(Pin::new(&mut self.io).poll_write(cx, &self.write_buf[self.write_pos..]))?;
+            if written == 0 {
+                return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+            }
+            self.write_pos += written;
+        }
+
+        self.write_buf.clear();
+        self.write_pos = 0;
+        Poll::Ready(Ok(()))
+    }
+
+    /// Sends the request header asking the server to connect to `endpoint`.
+    pub(crate) async fn send_request(&mut self, endpoint: &Endpoint) -> Result<()> {
+        let mut header = Vec::new();
+        encode_socks_addr(endpoint, &mut header)?;
+
+        if self.key.method().is_2022() {
+            // Without initial payload the header must be padded.
+            let random = random_bytes(2);
+            let padding_len = u16::from_be_bytes([random[0], random[1]]) % MAX_PADDING_LEN + 1;
+            header.extend_from_slice(&padding_len.to_be_bytes());
+            header.extend_from_slice(&random_bytes(padding_len.into()));
+
+            let mut fixed_header = vec![0];
+            fixed_header.extend_from_slice(&timestamp().to_be_bytes());
+            fixed_header.extend_from_slice(&(header.len() as u16).to_be_bytes());
+            self.encryptor.seal(&fixed_header, &mut self.write_buf);
+            self.encryptor.seal(&header, &mut self.write_buf);
+        } else {
+            self.encrypt_payload(&header);
+        }
+
+        self.flush().err_into::<Error>().await
+    }
+}
+
+impl<T: AsyncRead + Unpin> AsyncRead for ShadowsocksStream<T> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = &mut *self;
+
+        // Nothing fits in an empty buffer, so don't consume a chunk for it.
+        if buf.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+
+        loop {
+            if this.plaintext_pos < this.plaintext.len() {
+                let len = buf.len().min(this.plaintext.len() - this.plaintext_pos);
+                buf[..len]
+                    .copy_from_slice(&this.plaintext[this.plaintext_pos..this.plaintext_pos + len]);
+                this.plaintext_pos += len;
+                return Poll::Ready(Ok(len));
+            }
+
+            match this.read_state {
+                ReadState::Salt => {
+                    let salt_len = this.key.method().salt_len();
+                    if !ready!(this.poll_fill(cx, salt_len))? {
+                        return Poll::Ready(Ok(0));
+                    }
+
+                    this.peer_salt = mem::replace(&mut this.read_buf, Vec::new());
+                    this.decryptor = Some(SessionCipher::new(&this.key, &this.peer_salt));
+                    this.read_state = if this.key.method().is_2022() {
+                        ReadState::Header
+                    } else {
+                        ReadState::Length
+                    };
+                }
+                ReadState::Header => {
+                    let len = this.header_len() + TAG_LEN;
+                    if !ready!(this.poll_fill(cx, len))? {
+                        return Poll::Ready(Err(io::ErrorKind::UnexpectedEof.into()));
+                    }
+
+                    this.read_state = ReadState::Payload(this.open_header()?);
+                }
+                ReadState::Length => {
+                    if !ready!(this.poll_fill(cx, 2 + TAG_LEN))? {
+                        return Poll::Ready(Ok(0));
+                    }
+
+                    this.open()?;
+                    let len = u16::from_be_bytes([this.read_buf[0], this.read_buf[1]]).into();
+                    if len > this.max_payload_len() {
+                        return Poll::Ready(Err(invalid_data(ShadowsocksError::InvalidLength(
+                            len,
+                        ))));
+                    }
+                    this.read_buf.clear();
+                    this.read_state = ReadState::Payload(len);
+                }
+                ReadState::Payload(len) => {
+                    if !ready!(this.poll_fill(cx, len + TAG_LEN))? {
+                        return Poll::Ready(Err(io::ErrorKind::UnexpectedEof.into()));
+                    }
+
+                    let len = this.open()?;
+                    this.read_buf.truncate(len);
+                    mem::swap(&mut this.plaintext, &mut this.read_buf);
+                    this.read_buf.clear();
+                    this.plaintext_pos = 0;
+                    this.read_state = ReadState::Length;
+                }
+            }
+        }
+    }
+}
+
+impl<T: AsyncWrite + Unpin> AsyncWrite for ShadowsocksStream<T> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = &mut *self;
+
+        if this.pending.is_none() {
+            if buf.is_empty() {
+                return Poll::Ready(Ok(0));
+            }
+
+            let len = buf.len().min(this.max_payload_len());
+            this.encrypt_payload(&buf[..len]);
+            this.pending = Some(len);
+        }
+
+        ready!(this.poll_drain(cx))?;
+        Poll::Ready(Ok(this.pending.take().unwrap_or(0)))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        ready!(self.poll_drain(cx))?;
+        Pin::new(&mut self.io).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        ready!(self.poll_drain(cx))?;
+        Pin::new(&mut self.io).poll_close(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{io::mock::MockStream, shadowsocks::ShadowsocksMethod};
+    use futures::{executor::block_on, io::AsyncReadExt};
+
+    fn key(method: ShadowsocksMethod) -> ShadowsocksKey {
+        if method.is_2022() {
+            ShadowsocksKey::new(method, &base64::encode(&vec![7; method.key_len()])).unwrap()
+        } else {
+            ShadowsocksKey::new(method, "password").unwrap()
+        }
+    }
+
+    fn read_all<T: AsyncRead + Unpin>(stream: &mut ShadowsocksStream<T>) -> io::Result<Vec<u8>> {
+        let mut buf = Vec::new();
+        block_on(stream.read_to_end(&mut buf))?;
+        Ok(buf)
+    }
+
+    fn round_trip(method: ShadowsocksMethod) {
+        let endpoint = Endpoint::new_from_hostname("example.com", 443);
+        let mut request_header = Vec::new();
+        encode_socks_addr(&endpoint, &mut request_header).unwrap();
+
+        let client_io = MockStream::new(&[]);
+        let sent = client_io.output();
+        let mut client = ShadowsocksStream::new(client_io, key(method), Role::Client);
+        block_on(client.send_request(&endpoint)).unwrap();
+        block_on(client.write_all(b"hello")).unwrap();
+        block_on(client.flush()).unwrap();
+
+        let server_io = MockStream::new(&sent.lock().unwrap());
+        let replied = server_io.output();
+        let mut server = ShadowsocksStream::new(server_io, key(method), Role::Server);
+        let received = read_all(&mut server).unwrap();
+        assert!(received.starts_with(&request_header));
+        assert!(received.ends_with(b"hello"));
+        assert_eq!(server.peer_salt(), &client.salt[..]);
+
+        block_on(server.write_all(b"world")).unwrap();
+        block_on(server.flush()).unwrap();
+
+        client.io = MockStream::new(&replied.lock().unwrap());
+        assert_eq!(read_all(&mut client).unwrap(), b"world");
+    }
+
+    #[test]
+    fn round_trips_sip004() {
+        round_trip(ShadowsocksMethod::Aes128Gcm);
+        round_trip(ShadowsocksMethod::Aes256Gcm);
+        round_trip(ShadowsocksMethod::Chacha20IetfPoly1305);
+    }
+
+    #[test]
+    fn round_trips_sip022() {
+        round_trip(ShadowsocksMethod::Blake3Aes128Gcm);
+        round_trip(ShadowsocksMethod::Blake3Aes256Gcm);
+        round_trip(ShadowsocksMethod::Blake3Chacha20Poly1305);
+    }
+
+    #[test]
+    fn splits_large_writes_into_chunks() {
+        let method = ShadowsocksMethod::Aes128Gcm;
+        let data = vec![42; 0x3fff * 2 + 1];
+
+        let client_io = MockStream::new(&[]);
+        let sent = client_io.output();
+        let mut client = ShadowsocksStream::new(client_io, key(method), Role::Client);
+        block_on(client.write_all(&data)).unwrap();
+
+        let salt_len = method.salt_len();
+        let overhead = 3 * (2 + TAG_LEN + TAG_LEN);
+        assert_eq!(sent.lock().unwrap().len(), salt_len + data.len() + overhead);
+
+        let mut server = ShadowsocksStream::new(
+            MockStream::new(&sent.lock().unwrap()),
+            key(method),
+            Role::Server,
+        );
+        assert_eq!(read_all(&mut server).unwrap(), data);
+    }
+
+    #[test]
+    fn rejects_oversized_chunk_length() {
+        let method = ShadowsocksMethod::Aes128Gcm;
+        let salt = random_bytes(method.salt_len());
+        let mut buf = salt.clone();
+        SessionCipher::new(&key(method), &salt).seal(&0x4000u16.to_be_bytes(), &mut buf);
+
+        let mut server = ShadowsocksStream::new(MockStream::new(&buf), key(method), Role::Server);
+        let err = read_all(&mut server).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        match err
+            .get_ref()
+            .and_then(|err| err.downcast_ref::<ShadowsocksError>())
+        {
+            Some(ShadowsocksError::InvalidLength(0x4000)) => {}
+            _ => panic!("unexpected error {}", err),
+        }
+    }
+
+    #[test]
+    fn rejects_wrong_key() {
+        let method = ShadowsocksMethod::Aes128Gcm;
+        let client_io = MockStream::new(&[]);
+        let sent = client_io.output();
+        let mut client = ShadowsocksStream::new(client_io, key(method), Role::Client);
+        block_on(client.write_all(b"hello")).unwrap();
+
+        let wrong_key = ShadowsocksKey::new(method, "wrong").unwrap();
+        let mut server = ShadowsocksStream::new(
+            MockStream::new(&sent.lock().unwrap()),
+            wrong_key,
+            Role::Server,
+        );
+        assert_eq!(
+            read_all(&mut server).unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+    }
+
+    #[test]
+    fn empty_read_consumes_nothing() {
+        let method = ShadowsocksMethod::Aes128Gcm;
+        let client_io = MockStream::new(&[]);
+        let sent = client_io.output();
+        let mut client = ShadowsocksStream::new(client_io, key(method), Role::Client);
+        block_on(client.write_all(b"hello")).unwrap();
+
+        let mut server = ShadowsocksStream::new(
+            MockStream::new(&sent.lock().unwrap()),
+            key(method),
+            Role::Server,
+        );
+        assert_eq!(block_on(server.read(&mut [])).unwrap(), 0);
+        assert_eq!(read_all(&mut server).unwrap(), b"hello");
+    }
+}
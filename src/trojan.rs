@@ -20,13 +20,36 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
-use super::{Client, ClientBuilder};
-use hyper::{body::Body, client::conn::SendRequest};
+//! The pieces of the Trojan protocol shared by the connector and the
+//! acceptor.
+//!
+//! A request is `HEX(SHA224(password)) CRLF CMD ATYP ADDR PORT CRLF`,
+//! followed by the payload, all sent over TLS.
 
-pub struct NoOpClientBuilder {}
+use sha2::{Digest, Sha224};
 
-impl ClientBuilder for NoOpClientBuilder {
-    fn build(self, handler: SendRequest<Body>) -> Box<dyn Client + Send> {
-        Box::new(handler)
+pub(crate) const HASH_LEN: usize = 56;
+pub(crate) const CMD_CONNECT: u8 = 1;
+
+/// The lowercase hex SHA-224 of `password` that identifies the client.
+pub fn password_hash(password: &str) -> String {
+    Sha224::digest(password.as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hashes_password() {
+        let hash = password_hash("password");
+        assert_eq!(hash.len(), HASH_LEN);
+        assert_eq!(
+            hash,
+            "d63dc919e201d7bc4c825630d2cf25fdc93d4b2f0d46706d29038d01"
+        );
     }
 }
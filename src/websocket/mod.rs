@@ -0,0 +1,561 @@
+// MIT License
+
+// Copyright (c) 2019 Zhuhao Wang
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! WebSocket framing (RFC 6455), used to carry a byte stream through CDNs and
+//! middleboxes that only pass HTTP.
+//!
+//! Data is sent in binary frames; the payload of every data frame received is
+//! read as part of the stream regardless of its opcode.
+
+use futures::{
+    io::{AsyncRead, AsyncWrite},
+    ready,
+    task::{Context, Poll},
+};
+use ring::{
+    digest,
+    rand::{SecureRandom, SystemRandom},
+};
+use std::{io, mem, pin::Pin};
+
+const OPCODE_BINARY: u8 = 2;
+const OPCODE_CLOSE: u8 = 8;
+const OPCODE_PING: u8 = 9;
+const OPCODE_PONG: u8 = 10;
+
+const MAX_CONTROL_PAYLOAD_LEN: usize = 125;
+const MAX_FRAME_PAYLOAD_LEN: usize = 64 * 1024;
+const CLOSE_NORMAL: u16 = 1000;
+const CLOSE_PROTOCOL_ERROR: u16 = 1002;
+
+const ACCEPT_GUID: &[u8] = b"258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+#[derive(Debug)]
+pub enum WebSocketError {
+    UpgradeRejected { status: u16, status_line: String },
+    InvalidAcceptKey,
+    ControlFrameTooLong,
+    UnmaskedFrame,
+    Closed,
+}
+
+impl std::fmt::Display for WebSocketError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::result::Result<(), std::fmt::Error> {
+        write!(f, "{:#?}", self)
+    }
+}
+
+impl std::error::Error for WebSocketError {}
+
+/// The `Sec-WebSocket-Accept` answering `key`.
+pub(crate) fn accept_key(key: &[u8]) -> String {
+    let mut context = digest::Context::new(&digest::SHA1_FOR_LEGACY_USE_ONLY);
+    context.update(key);
+    context.update(ACCEPT_GUID);
+    base64::encode(context.finish().as_ref())
+}
+
+pub(crate) fn random_bytes(len: usize) -> Vec<u8> {
+    let mut buf = vec![0; len];
+    // There is nothing sensible to do if the system has no randomness.
+    SystemRandom::new().fill(&mut buf).unwrap();
+    buf
+}
+
+/// Clients mask the frames they send, servers don't.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Role {
+    Client,
+    Server,
+}
+
+enum ReadState {
+    Header,
+    Data {
+        remaining: u64,
+        mask: Option<[u8; 4]>,
+        offset: usize,
+    },
+    Control {
+        opcode: u8,
+        len: usize,
+        mask: Option<[u8; 4]>,
+    },
+    Closed,
+}
+
+fn apply_mask(buf: &mut [u8], mask: [u8; 4], offset: usize) {
+    for (i, byte) in buf.iter_mut().enumerate() {
+        *byte ^= mask[(offset + i) % 4];
+    }
+}
+
+/// A byte stream carried in the frames of an established WebSocket
+/// connection.
+///
+/// Pings are answered and a close frame ends the stream. Frames are sent as
+/// a whole, so a `poll_write` that returns `Pending` must be retried with the
+/// same data.
+pub struct WebSocketStream<T> {
+    io: T,
+    role: Role,
+    read_state: ReadState,
+    read_buf: Vec<u8>,
+    write_buf: Vec<u8>,
+    write_pos: usize,
+    pending: Option<usize>,
+    close_sent: bool,
+}
+
+impl<T> WebSocketStream<T> {
+    pub(crate) fn new(io: T, role: Role) -> Self {
+        WebSocketStream {
+            io,
+            role,
+            read_state: ReadState::Header,
+            read_buf: Vec::new(),
+            write_buf: Vec::new(),
+            write_pos: 0,
+            pending: None,
+            close_sent: false,
+        }
+    }
+
+    fn queue_frame(&mut self, opcode: u8, payload: &[u8]) {
+        self.write_buf.push(0x80 | opcode);
+
+        let mask_bit = if self.role == Role::Client { 0x80 } else { 0 };
+        match payload.len() {
+            len if len < 126 => self.write_buf.push(mask_bit | len as u8),
+            len if len <= 0xffff => {
+                self.write_buf.push(mask_bit | 126);
+                self.write_buf
+                    .extend_from_slice(&(len as u16).to_be_bytes());
+            }
+            len => {
+                self.write_buf.push(mask_bit | 127);
+                self.write_buf
+                    .extend_from_slice(&(len as u64).to_be_bytes());
+            }
+        }
+
+        let start = self.write_buf.len();
+        if self.role == Role::Client {
+            let mask = random_bytes(4);
+            self.write_buf.extend_from_slice(&mask);
+            self.write_buf.extend_from_slice(payload);
+            apply_mask(
+                &mut self.write_buf[start + 4..],
+                [mask[0], mask[1], mask[2], mask[3]],
+                0,
+            );
+        } else {
+            self.write_buf.extend_from_slice(payload);
+        }
+    }
+
+    fn queue_close(&mut self, payload: &[u8]) {
+        if !self.close_sent {
+            self.close_sent = true;
+            self.queue_frame(OPCODE_CLOSE, payload);
+        }
+    }
+}
+
+impl<T: AsyncRead + Unpin> WebSocketStream<T> {
+    /// Reads until `read_buf` holds `len` bytes. Returns `false` if the
+    /// stream ended before any byte was read.
+    fn poll_fill(&mut self, cx: &mut Context<'_>, len: usize) -> Poll<io::Result<bool>> {
+        while self.read_buf.len() < len {
+            let filled = self.read_buf.len();
+            self.read_buf.resize(len, 0);
+
+            let read = Pin::new(&mut self.io).poll_read(cx, &mut self.read_buf[filled..]);
+            let read = match read {
+                Poll::Ready(Ok(read)) => read,
+                Poll::Ready(Err(err)) => {
+                    self.read_buf.truncate(filled);
+                    return Poll::Ready(Err(err));
+                }
+                Poll::Pending => {
+                    self.read_buf.truncate(filled);
+                    return Poll::Pending;
+                }
+            };
+
+            self.read_buf.truncate(filled + read);
+            if read == 0 {
+                if filled == 0 {
+                    return Poll::Ready(Ok(false));
+                }
+                return Poll::Ready(Err(io::ErrorKind::UnexpectedEof.into()));
+            }
+        }
+
+        Poll::Ready(Ok(true))
+    }
+
+    fn parse_header(&mut self) -> io::Result<ReadState> {
+        let opcode = self.read_buf[0] & 0x0f;
+        let masked = self.read_buf[1] & 0x80 != 0;
+        if self.role == Role::Server && !masked {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                WebSocketError::UnmaskedFrame,
+            ));
+        }
+
+        let (len, mut pos) = match self.read_buf[1] & 0x7f {
+            126 => (
+                u64::from(u16::from_be_bytes([self.read_buf[2], self.read_buf[3]])),
+                4,
+            ),
+            127 => {
+                let mut buf = [0; 8];
+                buf.copy_from_slice(&self.read_buf[2..10]);
+                (u64::from_be_bytes(buf), 10)
+            }
+            len => (u64::from(len), 2),
+        };
+
+        let mask = if masked {
+            let mut mask = [0; 4];
+            mask.copy_from_slice(&self.read_buf[pos..pos + 4]);
+            pos += 4;
+            Some(mask)
+        } else {
+            None
+        };
+        debug_assert_eq!(pos, self.read_buf.len());
+        self.read_buf.clear();
+
+        if opcode & 0x08 == 0 {
+            return Ok(ReadState::Data {
+                remaining: len,
+                mask,
+                offset: 0,
+            });
+        }
+
+        if len > MAX_CONTROL_PAYLOAD_LEN as u64 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                WebSocketError::ControlFrameTooLong,
+            ));
+        }
+
+        Ok(ReadState::Control {
+            opcode,
+            len: len as usize,
+            mask,
+        })
+    }
+}
+
+fn header_len(buf: &[u8]) -> usize {
+    let len = match buf[1] & 0x7f {
+        126 => 4,
+        127 => 10,
+        _ => 2,
+    };
+    if buf[1] & 0x80 != 0 {
+        len + 4
+    } else {
+        len
+    }
+}
+
+impl<T: AsyncWrite + Unpin> WebSocketStream<T> {
+    /// Closes the connection after the peer broke the protocol, sending the
+    /// close frame on a best-effort basis.
+    fn fail(&mut self, cx: &mut Context<'_>, err: io::Error) -> io::Error {
+        self.read_buf.clear();
+        self.read_state = ReadState::Closed;
+        self.queue_close(&CLOSE_PROTOCOL_ERROR.to_be_bytes());
+        if self.pending.is_none() {
+            let _ = self.poll_drain(cx);
+        }
+        err
+    }
+
+    fn poll_drain(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        while self.write_pos < self.write_buf.len() {
+            let written =
+                ready!(Pin::new(&mut self.io).poll_write(cx, &self.write_buf[self.write_pos..]))?;
+            if written == 0 {
+                return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+            }
+            self.write_pos += written;
+        }
+
+        self.write_buf.clear();
+        self.write_pos = 0;
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<T: AsyncRead + AsyncWrite + Unpin> AsyncRead for WebSocketStream<T> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = &mut *self;
+
+        loop {
+            // Send the pongs and the close frame queued by earlier reads,
+            // unless a write is in progress and will do it.
+            if this.pending.is_none() && !this.write_buf.is_empty() {
+                if let Poll::Ready(Err(err)) = this.poll_drain(cx) {
+                    return Poll::Ready(Err(err));
+                }
+            }
+
+            match this.read_state {
+                ReadState::Header => {
+                    if !ready!(this.poll_fill(cx, 2))? {
+                        return Poll::Ready(Ok(0));
+                    }
+                    let len = header_len(&this.read_buf);
+                    ready!(this.poll_fill(cx, len))?;
+                    this.read_state = match this.parse_header() {
+                        Ok(state) => state,
+                        Err(err) => return Poll::Ready(Err(this.fail(cx, err))),
+                    };
+                }
+                ReadState::Data {
+                    remaining,
+                    mask,
+                    offset,
+                } => {
+                    if remaining == 0 {
+                        this.read_state = ReadState::Header;
+                        continue;
+                    }
+                    if buf.is_empty() {
+                        return Poll::Ready(Ok(0));
+                    }
+
+                    let len = (buf.len() as u64).min(remaining) as usize;
+                    let read = ready!(Pin::new(&mut this.io).poll_read(cx, &mut buf[..len]))?;
+                    if read == 0 {
+                        return Poll::Ready(Err(io::ErrorKind::UnexpectedEof.into()));
+                    }
+
+                    if let Some(mask) = mask {
+                        apply_mask(&mut buf[..read], mask, offset);
+                    }
+                    this.read_state = ReadState::Data {
+                        remaining: remaining - read as u64,
+                        mask,
+                        offset: (offset + read) % 4,
+                    };
+                    return Poll::Ready(Ok(read));
+                }
+                ReadState::Control { opcode, len, mask } => {
+                    if !ready!(this.poll_fill(cx, len))? {
+                        return Poll::Ready(Err(io::ErrorKind::UnexpectedEof.into()));
+                    }
+
+                    let mut payload = mem::replace(&mut this.read_buf, Vec::new());
+                    if let Some(mask) = mask {
+                        apply_mask(&mut payload, mask, 0);
+                    }
+
+                    this.read_state = ReadState::Header;
+                    match opcode {
+                        OPCODE_PING => this.queue_frame(OPCODE_PONG, &payload),
+                        OPCODE_CLOSE => {
+                            // Echo the status code as required.
+                            payload.truncate(2);
+                            this.queue_close(&payload);
+                            this.read_state = ReadState::Closed;
+                        }
+                        _ => {}
+                    }
+                }
+                ReadState::Closed => return Poll::Ready(Ok(0)),
+            }
+        }
+    }
+}
+
+impl<T: AsyncRead + AsyncWrite + Unpin> AsyncWrite for WebSocketStream<T> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = &mut *self;
+
+        if this.pending.is_none() {
+            if this.close_sent {
+                return Poll::Ready(Err(io::Error::new(
+                    io::ErrorKind::BrokenPipe,
+                    WebSocketError::Closed,
+                )));
+            }
+            if buf.is_empty() {
+                return Poll::Ready(Ok(0));
+            }
+
+            let len = buf.len().min(MAX_FRAME_PAYLOAD_LEN);
+            this.queue_frame(OPCODE_BINARY, &buf[..len]);
+            this.pending = Some(len);
+        }
+
+        ready!(this.poll_drain(cx))?;
+        Poll::Ready(Ok(this.pending.take().unwrap_or(0)))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        ready!(self.poll_drain(cx))?;
+        Pin::new(&mut self.io).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.queue_close(&CLOSE_NORMAL.to_be_bytes());
+        ready!(self.poll_drain(cx))?;
+        Pin::new(&mut self.io).poll_close(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::mock::MockStream;
+    use futures::{
+        executor::block_on,
+        io::{AsyncReadExt, AsyncWriteExt},
+    };
+
+    /// The bytes a client sends after queueing `frames`.
+    fn client_frames(frames: &[(u8, &[u8])]) -> Vec<u8> {
+        let io = MockStream::new(b"");
+        let output = io.output();
+        let mut client = WebSocketStream::new(io, Role::Client);
+        for (opcode, payload) in frames {
+            client.queue_frame(*opcode, payload);
+        }
+        block_on(client.flush()).unwrap();
+        let bytes = output.lock().unwrap().clone();
+        bytes
+    }
+
+    #[test]
+    fn computes_accept_key() {
+        assert_eq!(
+            accept_key(b"dGhlIHNhbXBsZSBub25jZQ=="),
+            "s3pPLMBiTxaMQlxlc9rWM4yw/eo="
+        );
+    }
+
+    #[test]
+    fn round_trips_masked_data() {
+        let io = MockStream::new(b"");
+        let output = io.output();
+        let mut client = WebSocketStream::new(io, Role::Client);
+        block_on(client.write_all(b"hello")).unwrap();
+
+        let sent = output.lock().unwrap().clone();
+        assert_eq!(&sent[..2], &[0x82, 0x85]);
+        assert_ne!(&sent[6..], b"hello");
+
+        let mut server = WebSocketStream::new(MockStream::new(&sent), Role::Server);
+        let mut received = Vec::new();
+        block_on(server.read_to_end(&mut received)).unwrap();
+        assert_eq!(received, b"hello");
+    }
+
+    #[test]
+    fn uses_extended_lengths() {
+        let payload = vec![7; 300];
+        let sent = client_frames(&[(OPCODE_BINARY, &payload[..])]);
+        assert_eq!(&sent[..4], &[0x82, 0x80 | 126, 0x01, 0x2c]);
+
+        let mut server = WebSocketStream::new(MockStream::new(&sent), Role::Server);
+        let mut received = Vec::new();
+        block_on(server.read_to_end(&mut received)).unwrap();
+        assert_eq!(received, payload);
+    }
+
+    #[test]
+    fn answers_ping() {
+        let sent = client_frames(&[(OPCODE_PING, &b"hi"[..]), (OPCODE_BINARY, &b"data"[..])]);
+        let io = MockStream::new(&sent);
+        let output = io.output();
+        let mut server = WebSocketStream::new(io, Role::Server);
+
+        let mut received = Vec::new();
+        block_on(server.read_to_end(&mut received)).unwrap();
+        assert_eq!(received, b"data");
+        assert_eq!(*output.lock().unwrap(), [0x8a, 0x02, b'h', b'i']);
+    }
+
+    #[test]
+    fn echoes_close_and_refuses_writes() {
+        let sent = client_frames(&[(OPCODE_CLOSE, &CLOSE_NORMAL.to_be_bytes()[..])]);
+        let io = MockStream::new(&sent);
+        let output = io.output();
+        let mut server = WebSocketStream::new(io, Role::Server);
+
+        let mut received = Vec::new();
+        block_on(server.read_to_end(&mut received)).unwrap();
+        assert!(received.is_empty());
+        assert_eq!(*output.lock().unwrap(), [0x88, 0x02, 0x03, 0xe8]);
+
+        let err = block_on(server.write(b"late")).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::BrokenPipe);
+    }
+
+    #[test]
+    fn refuses_writes_after_close() {
+        let mut client = WebSocketStream::new(MockStream::new(b""), Role::Client);
+        block_on(client.close()).unwrap();
+
+        let err = block_on(client.write(b"late")).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::BrokenPipe);
+    }
+
+    #[test]
+    fn closes_on_unmasked_client_frame() {
+        let io = MockStream::new(&[0x82, 0x02, b'h', b'i']);
+        let output = io.output();
+        let mut server = WebSocketStream::new(io, Role::Server);
+
+        let mut buf = [0; 16];
+        let err = block_on(server.read(&mut buf)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(*output.lock().unwrap(), [0x88, 0x02, 0x03, 0xea]);
+        assert_eq!(block_on(server.read(&mut buf)).unwrap(), 0);
+    }
+
+    #[test]
+    fn rejects_long_control_frames() {
+        let sent = client_frames(&[(OPCODE_PING, &[0; 126][..])]);
+        let mut server = WebSocketStream::new(MockStream::new(&sent), Role::Server);
+
+        let mut buf = [0; 16];
+        let err = block_on(server.read(&mut buf)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}